@@ -0,0 +1,4 @@
+//! libuboot_env.a and libuboot_env.so: uboot-patcher's c abi on its own,
+//! see include/uboot_env.h
+
+pub use uboot_patcher::ffi::*;
@@ -0,0 +1,31 @@
+/* the c abi as a c program sees it, through include/uboot_env.h and
+ * libuboot_env.a. exits with the line of the first check that fails */
+
+#include <string.h>
+
+#include "uboot_env.h"
+
+#define CHECK(c) \
+    if (!(c)) \
+        return __LINE__
+
+int main(void) {
+    uint8_t image[0x100];
+    char buf[8];
+    uboot_env *env = uboot_env_new(), *parsed = NULL;
+
+    CHECK(uboot_env_set(env, "bootcmd", "run boot_flash") == 0);
+    CHECK(uboot_env_serialize(env, image, sizeof(image)) == 0);
+    uboot_env_free(env);
+
+    CHECK(uboot_env_parse(image, sizeof(image), &parsed) == 0);
+    CHECK(uboot_env_get(parsed, "bootcmd", buf, sizeof(buf)) == 14);
+    CHECK(strcmp(buf, "run boo") == 0);
+    CHECK(uboot_env_get(parsed, "bootdelay", buf, sizeof(buf)) == UBOOT_ENV_ENOENT);
+    CHECK(uboot_env_serialize(parsed, image, 8) == UBOOT_ENV_EOVERFLOW);
+    image[0x10] ^= 1;
+    uboot_env_free(parsed);
+    CHECK(uboot_env_parse(image, sizeof(image), &parsed) == 0);
+    uboot_env_free(parsed);
+    return 0;
+}
@@ -0,0 +1,42 @@
+//! builds smoke.c against include/uboot_env.h and libuboot_env.a, then
+//! runs it
+
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn c_smoke_test() {
+    let here = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let tmp = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    // tests don't get the staticlib built for them, so it's built here,
+    // somewhere the cargo running this doesn't have locked
+    let target = tmp.join("smoke-target");
+    let built = Command::new(std::env::var("CARGO").unwrap_or("cargo".into()))
+        .current_dir(here)
+        .args(["build", "--lib", "--target-dir"])
+        .arg(&target)
+        .output()
+        .expect("can't run cargo");
+    assert!(
+        built.status.success(),
+        "building libuboot_env.a failed: {}",
+        String::from_utf8_lossy(&built.stderr)
+    );
+
+    let smoke = tmp.join("smoke");
+    let status = Command::new(std::env::var("CC").unwrap_or("cc".into()))
+        .args(["-Wall", "-Werror", "-I"])
+        .arg(here.join("../include"))
+        .arg(here.join("tests/smoke.c"))
+        .arg(target.join("debug/libuboot_env.a"))
+        .args(["-lpthread", "-ldl", "-lm"])
+        .arg("-o")
+        .arg(&smoke)
+        .status()
+        .expect("can't run cc");
+    assert!(status.success(), "cc failed");
+
+    // smoke.c exits with the line of the check that failed
+    let status = Command::new(&smoke).status().unwrap();
+    assert_eq!(status.code(), Some(0));
+}
@@ -0,0 +1,39 @@
+/* c interface to uboot-patcher's environment codec. build the library with
+ * `cargo build --release -p uboot-env` and link against
+ * target/release/libuboot_env.a (or .so).
+ *
+ * functions return 0, or a length for uboot_env_get, on success and one of
+ * the negative codes below on failure. */
+
+#ifndef UBOOT_ENV_H
+#define UBOOT_ENV_H
+
+#include <stddef.h>
+#include <stdint.h>
+#include <sys/types.h>
+
+#define UBOOT_ENV_EINVAL (-1)    /* bad argument, or bytes that aren't key=value */
+#define UBOOT_ENV_ECRC (-2)      /* crc mismatch */
+#define UBOOT_ENV_EOVERFLOW (-3) /* doesn't fit in the given size */
+#define UBOOT_ENV_ENOENT (-4)    /* no such variable */
+
+typedef struct uboot_env uboot_env;
+
+/* an empty environment */
+uboot_env *uboot_env_new(void);
+
+/* parse len bytes of a redundant environment into *out */
+ssize_t uboot_env_parse(const uint8_t *bytes, size_t len, uboot_env **out);
+
+void uboot_env_free(uboot_env *env);
+
+/* copy key's value into buf like snprintf, returning its full length */
+ssize_t uboot_env_get(const uboot_env *env, const char *key, char *buf, size_t buf_len);
+
+/* set key to val, or remove it if val is NULL */
+ssize_t uboot_env_set(uboot_env *env, const char *key, const char *val);
+
+/* serialize as a redundant environment of exactly size bytes */
+ssize_t uboot_env_serialize(const uboot_env *env, uint8_t *out, size_t size);
+
+#endif
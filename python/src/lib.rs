@@ -0,0 +1,4 @@
+//! the uboot_patcher python module, which the library's python feature
+//! defines, as a cdylib
+
+pub use uboot_patcher;
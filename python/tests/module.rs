@@ -0,0 +1,44 @@
+//! builds the module and runs test_env.py against it with python3
+
+use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn python_tests() {
+    let here = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let tmp = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    // tests don't get the cdylib built for them, so it's built here,
+    // somewhere the cargo running this doesn't have locked
+    let target = tmp.join("module-target");
+    let built = Command::new(std::env::var("CARGO").unwrap_or("cargo".into()))
+        .current_dir(here)
+        .args(["build", "--lib", "--target-dir"])
+        .arg(&target)
+        .output()
+        .expect("can't run cargo");
+    assert!(
+        built.status.success(),
+        "building the module failed: {}",
+        String::from_utf8_lossy(&built.stderr)
+    );
+
+    // named as python imports it, the way maturin installs it
+    let module = tmp.join("module");
+    std::fs::create_dir_all(&module).unwrap();
+    std::fs::copy(
+        target.join(format!(
+            "debug/{}uboot_patcher_py{}",
+            DLL_PREFIX, DLL_SUFFIX
+        )),
+        module.join("uboot_patcher.so"),
+    )
+    .unwrap();
+    let status = Command::new("python3")
+        .args(["-m", "unittest", "discover", "-s"])
+        .arg(here.join("tests"))
+        .env("PYTHONPATH", &module)
+        .status()
+        .expect("can't run python3");
+    assert!(status.success(), "test_env.py failed");
+}
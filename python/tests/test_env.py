@@ -0,0 +1,60 @@
+"""the uboot_patcher module, run by tests/module.rs against a build of it"""
+
+import os
+import tempfile
+import unittest
+
+import uboot_patcher
+
+
+class EnvTest(unittest.TestCase):
+    def test_variables(self):
+        env = uboot_patcher.Env([("bootcmd", "run boot_flash"), ("bootdelay", "3")])
+        env["bootdelay"] = "5"
+        env["console"] = "ttyS0,115200"
+        del env["bootcmd"]
+        self.assertEqual(env.items(), [("bootdelay", "5"), ("console", "ttyS0,115200")])
+        self.assertEqual(list(env), ["bootdelay", "console"])
+        self.assertEqual(env.get("bootcmd", "none"), "none")
+        self.assertNotIn("bootcmd", env)
+        self.assertEqual(len(env), 2)
+        with self.assertRaises(KeyError):
+            env["bootcmd"]
+        with self.assertRaises(KeyError):
+            del env["bootcmd"]
+
+    def test_round_trip(self):
+        env = uboot_patcher.Env([("b", "2"), ("a", "1")])
+        image = env.to_bytes(0x100)
+        self.assertEqual(len(image), 0x100)
+        self.assertEqual(uboot_patcher.Env.parse(image), env)
+        self.assertEqual(uboot_patcher.Env.parse(env.to_bytes(0x100, sorted=True)).keys(), ["a", "b"])
+
+    def test_errors(self):
+        env = uboot_patcher.Env([("bootcmd", "run boot_flash")])
+        with self.assertRaises(uboot_patcher.Error):
+            env["bad=key"] = "1"
+        with self.assertRaises(uboot_patcher.Error):
+            env.to_bytes(0x10)
+        image = bytearray(env.to_bytes(0x100))
+        image[0x10] ^= 1
+        image[0x90] ^= 1
+        with self.assertRaises(uboot_patcher.Error):
+            uboot_patcher.Env.parse(bytes(image))
+
+    def test_load_and_save(self):
+        with tempfile.TemporaryDirectory() as tmp:
+            path = os.path.join(tmp, "flash.bin")
+            with open(path, "wb") as f:
+                f.write(b"\xff" * 0x1000)
+            env = uboot_patcher.Env([("bootdelay", "3")])
+            env.save(path, offset=0x800, size=0x400)
+            self.assertEqual(uboot_patcher.Env.load(path, offset=0x800, size=0x400), env)
+            with open(path, "rb") as f:
+                self.assertEqual(f.read(0x800), b"\xff" * 0x800)
+            with self.assertRaises(OSError):
+                uboot_patcher.Env.load(os.path.join(tmp, "missing"), offset=0, size=0x400)
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,105 @@
+//! async versions of reading and patching an environment, for servers
+//! handling many images or devices at once
+//!
+//! ```no_run
+//! # async fn run() -> uboot_patcher::Result<()> {
+//! use uboot_patcher::async_io::patch_env;
+//! use uboot_patcher::EnvLayout;
+//!
+//! let mut file = tokio::fs::OpenOptions::new()
+//!     .read(true)
+//!     .write(true)
+//!     .open("dump.bin")
+//!     .await?;
+//! patch_env(&mut file, &EnvLayout::new(0x210000, 0x20000), |env| {
+//!     env.set("bootdelay", "5")?;
+//!     Ok(())
+//! })
+//! .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::future::Future;
+use std::io::{self, SeekFrom};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{Env, EnvLayout, EnvWriter, RegionWrite, Result};
+
+/// the async counterpart to storage::EnvStorage. anything seekable that
+/// tokio can read and write, such as a tokio::fs::File, already is one;
+/// implement it directly for things like network block devices
+pub trait AsyncEnvStorage {
+    /// len bytes starting at offset
+    fn read_region(
+        &mut self,
+        offset: u64,
+        len: usize,
+    ) -> impl Future<Output = io::Result<Vec<u8>>> + Send;
+
+    /// write bytes at offset and flush them
+    fn write_region(
+        &mut self,
+        offset: u64,
+        bytes: &[u8],
+    ) -> impl Future<Output = io::Result<()>> + Send;
+}
+
+impl<T: AsyncRead + AsyncWrite + AsyncSeek + Unpin + Send> AsyncEnvStorage for T {
+    async fn read_region(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let mut buf = vec![0; len];
+        self.seek(SeekFrom::Start(offset)).await?;
+        self.read_exact(&mut buf).await?;
+        Ok(buf)
+    }
+
+    async fn write_region(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        self.seek(SeekFrom::Start(offset)).await?;
+        self.write_all(bytes).await?;
+        self.flush().await
+    }
+}
+
+/// layout's region, read from wherever its spans are
+async fn read_layout<S: AsyncEnvStorage>(storage: &mut S, layout: &EnvLayout) -> Result<Vec<u8>> {
+    let mut region = Vec::with_capacity(layout.size);
+    for (offset, span) in layout.spans() {
+        region.extend(storage.read_region(offset, span.len()).await?);
+    }
+    Ok(region)
+}
+
+/// read and parse the environment in layout
+pub async fn read_env<S: AsyncEnvStorage>(storage: &mut S, layout: &EnvLayout) -> Result<Env> {
+    let bytes = read_layout(storage, layout).await?;
+    layout.parse(&bytes)
+}
+
+/// serialize env with writer and write it where its layout says
+pub async fn write_env<S: AsyncEnvStorage>(
+    storage: &mut S,
+    env: &Env,
+    writer: &EnvWriter,
+) -> Result<()> {
+    let write = RegionWrite::whole(writer.write(env)?);
+    for (offset, bytes) in write.placed(&writer.layout) {
+        storage.write_region(offset, bytes).await?;
+    }
+    Ok(())
+}
+
+/// read the environment in layout, change it with f, and write it back,
+/// returning what was written. nothing is written if f fails
+pub async fn patch_env<S: AsyncEnvStorage>(
+    storage: &mut S,
+    layout: &EnvLayout,
+    f: impl FnOnce(&mut Env) -> Result<()>,
+) -> Result<Env> {
+    let region = read_layout(storage, layout).await?;
+    let mut env = layout.parse(&region)?;
+    f(&mut env)?;
+    let writer = EnvWriter::replacing(*layout, &region);
+    write_env(storage, &env, &writer).await?;
+    Ok(env)
+}
@@ -0,0 +1,2088 @@
+//! the environment format itself, parsing and serializing byte slices.
+//! needs only core and alloc, so it builds without the std feature for
+//! use in bootloaders and recovery tools
+
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::hash::BuildHasherDefault;
+use core::net::Ipv4Addr;
+use core::ops::Range;
+
+use indexmap::IndexMap;
+use log::{debug, info, warn};
+
+pub use indexmap::map::Entry;
+
+/// everything that can go wrong reading or writing an environment
+#[derive(Debug, thiserror::Error)]
+pub enum EnvError {
+    /// no copy's stored crc matches its contents. the crcs are copy's
+    #[error("CRC Mismatch! copy {copy} stored: {stored:#x} calc: {computed:#x}")]
+    CrcMismatch {
+        copy: usize,
+        stored: u32,
+        computed: u32,
+    },
+    /// the variables need more room than the environment has
+    #[error("not enough space for environment ({needed} > {available})")]
+    Overflow { needed: usize, available: usize },
+    /// an entry that isn't utf-8 key=value. offset is from the start of
+    /// the region, and bytes are the entry itself
+    #[error(
+        "invalid entry at {offset:#x} in copy {copy} ({problem}): {}",
+        Excerpt(bytes)
+    )]
+    InvalidEntry {
+        copy: usize,
+        offset: usize,
+        bytes: Vec<u8>,
+        problem: EntryProblem,
+    },
+    /// a key or value that u-boot couldn't read back
+    #[error("can't set {key:?}: {problem}")]
+    InvalidVar { key: String, problem: VarProblem },
+    /// a value that isn't in the format a typed getter expects
+    #[error("{key}={val:?} isn't {expected}")]
+    InvalidValue {
+        key: String,
+        val: String,
+        expected: &'static str,
+    },
+    /// a merge with MergeStrategy::Fail found key set differently on each side
+    #[error("{key} is {ours:?} here but {theirs:?} in the merge")]
+    Conflict {
+        key: String,
+        ours: String,
+        theirs: String,
+    },
+    /// a key stored twice in an environment parsed with
+    /// DuplicateKeys::Error
+    #[error("{key} is stored twice, as {first:?} and then {second:?}")]
+    DuplicateKey {
+        key: String,
+        first: String,
+        second: String,
+    },
+    /// the region can't hold even the crcs and flags
+    #[error("environment too small ({size} bytes)")]
+    TooSmall { size: usize },
+    /// EnvLayout::view was given an encrypted environment, which can only
+    /// be parsed into a decrypted copy
+    #[cfg(feature = "aes")]
+    #[error("an encrypted environment can't be viewed in place, parse it instead")]
+    Encrypted,
+    #[cfg(feature = "std")]
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+pub type Result<T, E = EnvError> = core::result::Result<T, E>;
+
+/// the start of some bytes, quoted and escaped, for error messages
+struct Excerpt<'a>(&'a [u8]);
+
+impl core::fmt::Display for Excerpt<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        const MAX: usize = 32;
+        let shown = &self.0[..self.0.len().min(MAX)];
+        let more = if self.0.len() > MAX { "..." } else { "" };
+        write!(f, "\"{}\"{}", shown.escape_ascii(), more)
+    }
+}
+
+/// why a key or value is refused, from validate_key and validate_value
+#[derive(Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum VarProblem {
+    #[error("the name is empty")]
+    EmptyKey,
+    #[error("the name is longer than {MAX_KEY_LEN} bytes")]
+    KeyTooLong,
+    #[error("the name contains '='")]
+    KeyHasEquals,
+    #[error("the name isn't printable ascii")]
+    KeyNotPrintable,
+    #[error("the value contains a nul byte")]
+    ValueHasNul,
+}
+
+/// what's wrong with an invalid entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum EntryProblem {
+    #[error("not utf-8")]
+    NotUtf8,
+    #[error("no '='")]
+    NoEquals,
+    /// runs to the end of the data without a terminating nul
+    #[error("truncated")]
+    Unterminated,
+}
+
+/// the header of one copy of an environment
+pub struct EnvCopy {
+    pub stored_crc: u32,
+    pub calc_crc: u32,
+    pub flag: u8,
+}
+
+impl EnvCopy {
+    pub fn is_valid(&self) -> bool {
+        self.stored_crc == self.calc_crc
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.flag == 1
+    }
+
+    /// one-line summary of the crc state, all there is to say about a
+    /// single-copy environment
+    pub fn describe_crc(&self) -> String {
+        if self.is_valid() {
+            format!("crc {:#010x} valid", self.stored_crc)
+        } else {
+            format!(
+                "crc {:#010x} INVALID (computed {:#010x})",
+                self.stored_crc, self.calc_crc
+            )
+        }
+    }
+
+    /// one-line summary of the crc and flag state
+    pub fn describe(&self) -> String {
+        let crc = self.describe_crc();
+        let state = if self.is_active() {
+            "active"
+        } else {
+            "obsolete"
+        };
+        format!("{}, flag {:#04x} ({})", crc, self.flag, state)
+    }
+}
+
+/// read the crc and flag of both halves of a redundant environment
+pub fn redundant_env_copies(bytes: &[u8]) -> Result<[EnvCopy; 2]> {
+    let [one, two] = EnvLayout::new(0, bytes.len())
+        .copies(bytes)?
+        .try_into()
+        .map_err(|_| EnvError::TooSmall { size: bytes.len() })?;
+    Ok([one, two])
+}
+
+/// max space for one 'data' portion of a redundant environment of len bytes
+pub fn max_data_len(len: usize) -> usize {
+    EnvLayout::new(0, len).capacity()
+}
+
+/// the variables of an environment, kept in the order they were stored or
+/// added in so a read-modify-write only changes what was modified.
+/// comparisons ignore order
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+pub struct Env {
+    vars: IndexMap<String, String, Hasher>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    raw: Vec<RawEntry>,
+}
+
+/// an entry that isn't a utf-8 key=value string, kept as it was stored by
+/// EnvLayout::parse_preserving
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawEntry {
+    /// the variable it was stored after, and is written back after. None
+    /// for the start of the environment
+    pub after: Option<String>,
+    /// the entry without its terminating nul
+    pub bytes: Vec<u8>,
+    pub problem: EntryProblem,
+}
+
+impl RawEntry {
+    /// the name of a variable whose value isn't utf-8, such as a vendor's
+    /// binary mac, or None for an entry that isn't one at all
+    pub fn key(&self) -> Option<&str> {
+        let end = self.bytes.iter().position(|&b| b == b'=')?;
+        let key = core::str::from_utf8(&self.bytes[..end]).ok()?;
+        validate_key(key).is_ok().then_some(key)
+    }
+
+    /// the value of such a variable, as it was stored
+    pub fn value(&self) -> Option<&[u8]> {
+        let key = self.key()?;
+        Some(&self.bytes[key.len() + 1..])
+    }
+}
+
+/// fnv-1a, since core has no hasher of its own. environments are small and
+/// trusted enough that hash flooding isn't a concern
+pub struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Fnv {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl core::hash::Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 = (self.0 ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+type Hasher = BuildHasherDefault<Fnv>;
+
+impl Env {
+    pub fn new() -> Env {
+        Env::default()
+    }
+
+    /// parse the current copy of a redundant environment, failing if
+    /// neither copy's crc checks out
+    pub fn parse(bytes: &[u8]) -> Result<Env> {
+        Ok(EnvView::parse(bytes)?.to_env())
+    }
+
+    /// serialize as a redundant environment of size bytes, variables in
+    /// their current order. see EnvLayout for other layouts
+    pub fn to_bytes(&self, size: usize) -> Result<Vec<u8>> {
+        EnvLayout::new(0, size).write(self)
+    }
+
+    /// like to_bytes, but with the variables in key order so the same
+    /// contents always produce the same bytes, however they got there
+    pub fn to_sorted_bytes(&self, size: usize) -> Result<Vec<u8>> {
+        EnvWriter {
+            sorted: true,
+            ..EnvWriter::new(EnvLayout::new(0, size))
+        }
+        .write(self)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.vars.get(key).map(String::as_str)
+    }
+
+    /// key's value as bytes, including a value that isn't utf-8 (kept as a
+    /// raw entry by EnvLayout::parse_preserving), which get leaves out
+    pub fn get_bytes(&self, key: &str) -> Option<&[u8]> {
+        match self.vars.get(key) {
+            Some(val) => Some(val.as_bytes()),
+            None => self.raw.iter().find(|raw| raw.key() == Some(key))?.value(),
+        }
+    }
+
+    /// set key to val, which needn't be utf-8: one that isn't is stored as
+    /// a raw entry, in place of any variable called key. fails, changing
+    /// nothing, if key doesn't pass validate_key or val holds a nul
+    pub fn set_bytes(&mut self, key: impl Into<String>, val: &[u8]) -> Result<()> {
+        let key = key.into();
+        let Ok(text) = core::str::from_utf8(val) else {
+            let problem = validate_key(&key)
+                .err()
+                .or(val.contains(&0).then_some(VarProblem::ValueHasNul));
+            if let Some(problem) = problem {
+                return Err(EnvError::InvalidVar { key, problem });
+            }
+            self.remove(&key);
+            let mut bytes = format!("{}=", key).into_bytes();
+            bytes.extend_from_slice(val);
+            self.raw.push(RawEntry {
+                after: self.vars.last().map(|(key, _)| key.clone()),
+                bytes,
+                problem: EntryProblem::NotUtf8,
+            });
+            return Ok(());
+        };
+        self.set(key, text).map(drop)
+    }
+
+    /// set key to val, returning the value it replaced. new variables go
+    /// on the end. fails, changing nothing, if either doesn't pass
+    /// validate_key or validate_value
+    pub fn set(
+        &mut self,
+        key: impl Into<String>,
+        val: impl Into<String>,
+    ) -> Result<Option<String>> {
+        let (key, val) = (key.into(), val.into());
+        let invalid = |key: &str, problem| EnvError::InvalidVar {
+            key: key.into(),
+            problem,
+        };
+        validate_key(&key).map_err(|p| invalid(&key, p))?;
+        validate_value(&val).map_err(|p| invalid(&key, p))?;
+        self.raw.retain(|raw| raw.key() != Some(&key));
+        Ok(self.vars.insert(key, val))
+    }
+
+    /// remove key, returning its value if it was set. a value that isn't
+    /// utf-8 is removed too, and returned lossily
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        if let Some(i) = self.raw.iter().position(|raw| raw.key() == Some(key)) {
+            let raw = self.raw.remove(i);
+            let val = String::from_utf8_lossy(raw.value().unwrap_or_default()).into_owned();
+            return Some(self.vars.shift_remove(key).unwrap_or(val));
+        }
+        self.vars.shift_remove(key)
+    }
+
+    /// rename from to to where it stands, replacing anything already called
+    /// to, a value that isn't utf-8 included. returns false, changing
+    /// nothing, if from isn't set, and fails if to doesn't pass validate_key
+    pub fn rename(&mut self, from: &str, to: impl Into<String>) -> Result<bool> {
+        let to = to.into();
+        validate_key(&to).map_err(|problem| EnvError::InvalidVar {
+            key: to.clone(),
+            problem,
+        })?;
+        if !self.contains_key(from) {
+            return Ok(false);
+        }
+        if from != to {
+            self.raw.retain(|raw| raw.key() != Some(&to));
+            for raw in &mut self.raw {
+                if raw.after.as_deref() == Some(from) {
+                    raw.after = Some(to.clone());
+                }
+                if let Some(val) = raw.value().filter(|_| raw.key() == Some(from)) {
+                    let mut bytes = format!("{}=", to).into_bytes();
+                    bytes.extend_from_slice(val);
+                    raw.bytes = bytes;
+                }
+            }
+            self.vars.shift_remove(&to);
+            if let Some(index) = self.vars.get_index_of(from) {
+                let _ = self.vars.replace_index(index, to);
+            }
+        }
+        Ok(true)
+    }
+
+    /// whether key is set, to a value that isn't utf-8 or otherwise
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.vars.contains_key(key) || self.raw.iter().any(|raw| raw.key() == Some(key))
+    }
+
+    pub fn len(&self) -> usize {
+        self.vars.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vars.is_empty()
+    }
+
+    /// every key and value, in order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.vars.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.vars.keys().map(String::as_str)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &str> {
+        self.vars.values().map(String::as_str)
+    }
+
+    /// the variable key, for changing it in place or setting it if missing.
+    /// a value that isn't utf-8 is dropped, as set drops it
+    pub fn entry(&mut self, key: impl Into<String>) -> Entry<'_, String, String> {
+        let key = key.into();
+        self.raw.retain(|raw| raw.key() != Some(&key));
+        self.vars.entry(key)
+    }
+
+    /// keep only the variables f returns true for, leaving their order alone
+    pub fn retain(&mut self, mut f: impl FnMut(&str, &mut String) -> bool) {
+        self.vars.retain(|k, v| f(k, v))
+    }
+
+    /// parse a bare data area: nul-terminated key=value strings with no
+    /// crc in front, as `env export -b` writes them. an empty entry ends
+    /// them, and whatever follows it is ignored
+    pub fn parse_data(data: &[u8]) -> Result<Env> {
+        let end = (0..data.len())
+            .find(|&i| data[i] == 0 && (i == 0 || data[i - 1] == 0))
+            .unwrap_or(data.len());
+        let mut env = Env::new();
+        for entry in entries(&data[..end], 1, 0) {
+            let (key, val) = entry?;
+            insert_var(&mut env.vars, key, val, DuplicateKeys::default())?;
+        }
+        Ok(env)
+    }
+
+    /// the inverse of parse_data, with the variables in their current
+    /// order and then the empty entry
+    pub fn to_data(&self) -> Result<Vec<u8>> {
+        let layout = EnvLayout {
+            redundant: false,
+            ..EnvLayout::new(0, 4 + self.serialized_len() + 1)
+        };
+        Ok(layout.write(self)?.split_off(4))
+    }
+
+    /// bytes taken up by the key=val c strings once serialized, raw
+    /// entries included
+    pub fn serialized_len(&self) -> usize {
+        let raw: usize = self.raw.iter().map(|r| r.bytes.len() + 1).sum();
+        raw + self
+            .iter()
+            .map(|(k, v)| k.len() + 1 + v.len() + 1)
+            .sum::<usize>()
+    }
+
+    /// entries that weren't key=value strings, in stored order. only
+    /// EnvLayout::parse_preserving keeps any
+    pub fn raw_entries(&self) -> impl Iterator<Item = &RawEntry> {
+        self.raw.iter()
+    }
+
+    /// remove the raw entries, so they aren't written back
+    pub fn take_raw_entries(&mut self) -> Vec<RawEntry> {
+        core::mem::take(&mut self.raw)
+    }
+
+    /// add raw entries taken from another environment, as when one is
+    /// rebuilt from edited text. any for a key that's now set as a
+    /// variable are left out, the variable having replaced them
+    pub fn keep_raw_entries(&mut self, raw: impl IntoIterator<Item = RawEntry>) {
+        let vars = &self.vars;
+        self.raw.extend(
+            raw.into_iter()
+                .filter(|raw| raw.key().is_none_or(|key| !vars.contains_key(key))),
+        );
+    }
+
+    /// bytes left in layout's data area with this environment in it, 0 if
+    /// it doesn't fit at all
+    pub fn remaining(&self, layout: &EnvLayout) -> usize {
+        layout.capacity().saturating_sub(self.serialized_len())
+    }
+
+    /// whether this would still fit in layout with key set to val, for
+    /// warning before a set rather than failing at write time
+    pub fn fits_with(&self, key: &str, val: &str, layout: &EnvLayout) -> bool {
+        let old = self.get(key).map_or(0, |v| key.len() + v.len() + 2);
+        let new = key.len() + val.len() + 2;
+        self.serialized_len() - old + new <= layout.capacity()
+    }
+
+    /// fail early if this won't fit in layout
+    pub fn check_fits(&self, layout: &EnvLayout) -> Result<()> {
+        let (used, capacity) = (self.serialized_len(), layout.capacity());
+        if used > capacity {
+            return Err(EnvError::Overflow {
+                needed: used,
+                available: capacity,
+            });
+        }
+        Ok(())
+    }
+
+    /// overlay other's variables onto this environment. ones only in other
+    /// are appended in its order, and strategy settles ones set differently
+    /// on each side. nothing is changed if MergeStrategy::Fail finds a
+    /// conflict
+    pub fn merge(&mut self, other: Env, strategy: MergeStrategy) -> Result<()> {
+        if strategy == MergeStrategy::Fail {
+            let conflict = other
+                .iter()
+                .find_map(|(key, theirs)| match self.get_bytes(key) {
+                    Some(ours) if ours != theirs.as_bytes() => Some((key, ours, theirs)),
+                    _ => None,
+                });
+            if let Some((key, ours, theirs)) = conflict {
+                return Err(EnvError::Conflict {
+                    key: key.into(),
+                    ours: String::from_utf8_lossy(ours).into_owned(),
+                    theirs: theirs.into(),
+                });
+            }
+        }
+        for (key, val) in other {
+            // ours may be a value that isn't utf-8
+            let raw = self.raw.iter().position(|raw| raw.key() == Some(&key));
+            match (self.vars.entry(key), raw) {
+                (Entry::Occupied(mut e), _) => {
+                    if strategy == MergeStrategy::Theirs {
+                        e.insert(val);
+                    }
+                }
+                (Entry::Vacant(e), Some(i)) => {
+                    if strategy == MergeStrategy::Theirs {
+                        self.raw.remove(i);
+                        e.insert(val);
+                    }
+                }
+                (Entry::Vacant(e), None) => {
+                    e.insert(val);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// everything needed to turn this environment into other, sorted by key
+    pub fn diff<'a>(&'a self, other: &'a Env) -> EnvDiff<'a> {
+        let mut keys: Vec<&str> = self.keys().chain(other.keys()).collect();
+        keys.sort_unstable();
+        keys.dedup();
+
+        let changes = keys
+            .into_iter()
+            .filter_map(|key| match (self.get(key), other.get(key)) {
+                (Some(o), None) => Some(Change::Removed(key, o)),
+                (None, Some(n)) => Some(Change::Added(key, n)),
+                (Some(o), Some(n)) if o != n => Some(Change::Changed(key, o, n)),
+                _ => None,
+            })
+            .collect();
+        EnvDiff { changes }
+    }
+}
+
+/// the key=value strings of a data area, or a diagnostic for any entry that
+/// isn't one. copy and base, where the data starts in the region, are only
+/// for the diagnostics
+fn entries(data: &[u8], copy: usize, base: usize) -> impl Iterator<Item = Result<(&str, &str)>> {
+    // nor-friendly tools pad with 0xff rather than zeroes. it can't end a
+    // utf-8 entry, so a trailing run of it is only ever padding
+    let data = &data[..data.iter().rposition(|b| *b != 0xff).map_or(0, |i| i + 1)];
+    let mut offset = 0;
+    // split data by null bytes
+    data.split(|b| *b == 0u8).filter_map(move |entry| {
+        let start = offset;
+        offset += entry.len() + 1;
+        // skip the empty strings between the padding zeroes
+        if entry.is_empty() {
+            return None;
+        }
+        let invalid = |problem| EnvError::InvalidEntry {
+            copy,
+            offset: base + start,
+            bytes: entry.to_vec(),
+            problem,
+        };
+        if offset > data.len() {
+            return Some(Err(invalid(EntryProblem::Unterminated)));
+        }
+        let Ok(line) = core::str::from_utf8(entry) else {
+            return Some(Err(invalid(EntryProblem::NotUtf8)));
+        };
+        Some(
+            line.split_once('=')
+                .ok_or_else(|| invalid(EntryProblem::NoEquals)),
+        )
+    })
+}
+
+/// CONFIG_ENV_AES's cipher, in place: aes-128 in cbc mode with a zero iv
+/// over the whole blocks of data, leaving any bytes after them in the clear
+#[cfg(feature = "aes")]
+fn aes_cbc(key: &[u8; 16], data: &mut [u8], encrypt: bool) {
+    use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+
+    let cipher = aes::Aes128::new(key.into());
+    let mut chain = [0u8; 16];
+    for block in data.chunks_exact_mut(16) {
+        if encrypt {
+            block.iter_mut().zip(chain).for_each(|(b, c)| *b ^= c);
+            cipher.encrypt_block(aes::Block::from_mut_slice(block));
+            chain.copy_from_slice(block);
+        } else {
+            let next: [u8; 16] = (*block).try_into().unwrap();
+            cipher.decrypt_block(aes::Block::from_mut_slice(block));
+            block.iter_mut().zip(chain).for_each(|(b, c)| *b ^= c);
+            chain = next;
+        }
+    }
+}
+
+/// the longest name validate_key accepts. u-boot itself has no limit, but
+/// anything longer is a mistake
+pub const MAX_KEY_LEN: usize = 255;
+
+/// check that key can be stored and set from the u-boot shell: printable
+/// ascii other than '=', and not too long
+pub fn validate_key(key: &str) -> core::result::Result<(), VarProblem> {
+    if key.is_empty() {
+        return Err(VarProblem::EmptyKey);
+    }
+    if key.contains('=') {
+        return Err(VarProblem::KeyHasEquals);
+    }
+    if !key.bytes().all(|b| b.is_ascii_graphic()) {
+        return Err(VarProblem::KeyNotPrintable);
+    }
+    if key.len() > MAX_KEY_LEN {
+        return Err(VarProblem::KeyTooLong);
+    }
+    Ok(())
+}
+
+/// check that val can be stored. values can hold anything but nul, and
+/// their length is only limited by the room in the environment
+pub fn validate_value(val: &str) -> core::result::Result<(), VarProblem> {
+    if val.contains('\0') {
+        return Err(VarProblem::ValueHasNul);
+    }
+    Ok(())
+}
+
+/// a parsed environment that borrows its keys and values from the bytes it
+/// was parsed from rather than copying them, for read-only use
+#[derive(Clone, Copy, Debug)]
+pub struct EnvView<'a> {
+    data: &'a [u8],
+    /// which copy data is, from 1, and where it starts in the region,
+    /// after the copy's crc and any flag byte
+    copy: usize,
+    base: usize,
+}
+
+impl<'a> EnvView<'a> {
+    /// parse the current copy of a redundant environment, failing if
+    /// neither copy's crc checks out or an entry isn't key=value
+    pub fn parse(bytes: &'a [u8]) -> Result<EnvView<'a>> {
+        EnvLayout::new(0, bytes.len()).view(bytes)
+    }
+
+    /// every key and value, in stored order
+    pub fn iter(&self) -> impl Iterator<Item = (&'a str, &'a str)> + 'a {
+        // parse already checked every entry
+        entries(self.data, self.copy, self.base).filter_map(Result::ok)
+    }
+
+    /// the value of key. this is a linear search; convert to an Env for
+    /// many lookups
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.iter().find(|(k, _)| *k == key).map(|(_, v)| v)
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    /// bytes taken up by the key=val c strings
+    pub fn serialized_len(&self) -> usize {
+        self.iter().map(|(k, v)| k.len() + 1 + v.len() + 1).sum()
+    }
+
+    /// an owned copy that can be modified
+    pub fn to_env(&self) -> Env {
+        self.iter().collect()
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for Env {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Env {
+        Env {
+            vars: iter
+                .into_iter()
+                .map(|(k, v)| (k.into(), v.into()))
+                .collect(),
+            raw: Vec::new(),
+        }
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> Extend<(K, V)> for Env {
+    /// set every variable, replacing existing values where they stand.
+    /// unlike set, nothing is validated until the environment is written
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (k, v) in iter {
+            self.vars.insert(k.into(), v.into());
+        }
+    }
+}
+
+impl IntoIterator for Env {
+    type Item = (String, String);
+    type IntoIter = indexmap::map::IntoIter<String, String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.vars.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Env {
+    type Item = (&'a str, &'a str);
+    type IntoIter = Box<dyn Iterator<Item = (&'a str, &'a str)> + 'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Box::new(self.iter())
+    }
+}
+
+/// a mac address, stored the way u-boot's ethaddr is: six hex bytes
+/// separated by colons
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl core::fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
+
+/// a string that isn't six colon or dash separated hex bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+#[error("not a mac address")]
+pub struct ParseMacError;
+
+impl core::str::FromStr for MacAddr {
+    type Err = ParseMacError;
+
+    fn from_str(s: &str) -> core::result::Result<MacAddr, ParseMacError> {
+        let mut mac = [0; 6];
+        let mut parts = s.split([':', '-']);
+        for byte in &mut mac {
+            let part = parts.next().ok_or(ParseMacError)?;
+            if part.is_empty() || part.len() > 2 {
+                return Err(ParseMacError);
+            }
+            *byte = u8::from_str_radix(part, 16).map_err(|_| ParseMacError)?;
+        }
+        if parts.next().is_some() {
+            return Err(ParseMacError);
+        }
+        Ok(MacAddr(mac))
+    }
+}
+
+/// getters and setters for the formats u-boot itself reads values in. the
+/// getters return None if key isn't set and fail if it can't be parsed
+impl Env {
+    fn get_parsed<T>(
+        &self,
+        key: &str,
+        expected: &'static str,
+        parse: impl FnOnce(&str) -> Option<T>,
+    ) -> Result<Option<T>> {
+        let Some(val) = self.get(key) else {
+            return Ok(None);
+        };
+        match parse(val) {
+            Some(parsed) => Ok(Some(parsed)),
+            None => Err(EnvError::InvalidValue {
+                key: key.into(),
+                val: val.into(),
+                expected,
+            }),
+        }
+    }
+
+    /// a decimal or 0x-prefixed hex number, like bootdelay or loadaddr
+    pub fn get_u32(&self, key: &str) -> Result<Option<u32>> {
+        self.get_parsed(key, "a number", |val| match val.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16).ok(),
+            None => val.parse().ok(),
+        })
+    }
+
+    /// set key to n in decimal
+    pub fn set_u32(&mut self, key: impl Into<String>, n: u32) -> Result<Option<String>> {
+        self.set(key, n.to_string())
+    }
+
+    /// yes or no the way u-boot's env_get_yesno reads it: by the first
+    /// character, y, t or 1 for yes and n, f or 0 for no
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>> {
+        self.get_parsed(key, "yes or no", |val| match val.bytes().next()? {
+            b'y' | b'Y' | b't' | b'T' | b'1' => Some(true),
+            b'n' | b'N' | b'f' | b'F' | b'0' => Some(false),
+            _ => None,
+        })
+    }
+
+    /// set key to yes or no
+    pub fn set_bool(&mut self, key: impl Into<String>, b: bool) -> Result<Option<String>> {
+        self.set(key, if b { "yes" } else { "no" })
+    }
+
+    /// a mac address, like ethaddr or eth1addr
+    pub fn get_mac(&self, key: &str) -> Result<Option<MacAddr>> {
+        self.get_parsed(key, "a mac address", |val| val.parse().ok())
+    }
+
+    /// set key to mac as lowercase 00:11:22:33:44:55
+    pub fn set_mac(&mut self, key: impl Into<String>, mac: MacAddr) -> Result<Option<String>> {
+        self.set(key, mac.to_string())
+    }
+
+    /// a dotted ipv4 address, like ipaddr or serverip
+    pub fn get_ipaddr(&self, key: &str) -> Result<Option<Ipv4Addr>> {
+        self.get_parsed(key, "an ipv4 address", |val| val.parse().ok())
+    }
+
+    pub fn set_ipaddr(&mut self, key: impl Into<String>, ip: Ipv4Addr) -> Result<Option<String>> {
+        self.set(key, ip.to_string())
+    }
+}
+
+/// an environment being edited, remembering each change so it can be
+/// undone and redone, and the environment it started from so the changes
+/// can be shown before they're written
+///
+/// ```
+/// use uboot_patcher::{Env, EnvSession};
+///
+/// let mut session = EnvSession::new(Env::from_iter([("bootdelay", "3")]));
+/// session.set("bootdelay", "0")?;
+/// session.remove("bootdelay");
+/// session.undo();
+/// assert_eq!(session.env().get("bootdelay"), Some("0"));
+/// assert_eq!(session.changes().len(), 1);
+/// # Ok::<(), uboot_patcher::EnvError>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct EnvSession {
+    original: Env,
+    env: Env,
+    undo: Vec<Env>,
+    redo: Vec<Env>,
+}
+
+impl EnvSession {
+    pub fn new(env: Env) -> EnvSession {
+        EnvSession {
+            original: env.clone(),
+            env,
+            undo: Vec::new(),
+            redo: Vec::new(),
+        }
+    }
+
+    /// the environment as edited so far
+    pub fn env(&self) -> &Env {
+        &self.env
+    }
+
+    /// the environment the session started with
+    pub fn original(&self) -> &Env {
+        &self.original
+    }
+
+    /// change the environment with f as a single undoable step. nothing is
+    /// recorded if f leaves it as it was
+    pub fn edit<T>(&mut self, f: impl FnOnce(&mut Env) -> T) -> T {
+        let before = self.env.clone();
+        let out = f(&mut self.env);
+        if self.env != before {
+            self.undo.push(before);
+            self.redo.clear();
+        }
+        out
+    }
+
+    /// Env::set as an undoable step
+    pub fn set(
+        &mut self,
+        key: impl Into<String>,
+        val: impl Into<String>,
+    ) -> Result<Option<String>> {
+        self.edit(|env| env.set(key, val))
+    }
+
+    /// Env::remove as an undoable step
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.edit(|env| env.remove(key))
+    }
+
+    /// Env::rename as an undoable step
+    pub fn rename(&mut self, from: &str, to: impl Into<String>) -> Result<bool> {
+        self.edit(|env| env.rename(from, to))
+    }
+
+    /// go back to the original environment, as an undoable step
+    pub fn revert(&mut self) {
+        let original = self.original.clone();
+        self.edit(|env| *env = original);
+    }
+
+    /// undo the last step, returning false if there's nothing to undo
+    pub fn undo(&mut self) -> bool {
+        let Some(env) = self.undo.pop() else {
+            return false;
+        };
+        self.redo.push(core::mem::replace(&mut self.env, env));
+        true
+    }
+
+    /// redo the last undone step, returning false if there's nothing to redo
+    pub fn redo(&mut self) -> bool {
+        let Some(env) = self.redo.pop() else {
+            return false;
+        };
+        self.undo.push(core::mem::replace(&mut self.env, env));
+        true
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// everything that differs from the original
+    pub fn changes(&self) -> EnvDiff<'_> {
+        self.original.diff(&self.env)
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.env != self.original
+    }
+
+    /// finish the session, returning the edited environment
+    pub fn commit(self) -> Env {
+        self.env
+    }
+}
+
+/// which value Env::merge keeps for a variable set on both sides
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// the existing value
+    Ours,
+    /// the merged-in value
+    #[default]
+    Theirs,
+    /// neither, failing with EnvError::Conflict if they differ
+    Fail,
+}
+
+/// how a variable differs between two environments
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Change<'a> {
+    /// key and its new value
+    Added(&'a str, &'a str),
+    /// key and its old value
+    Removed(&'a str, &'a str),
+    /// key, its old value, and its new value
+    Changed(&'a str, &'a str, &'a str),
+}
+
+impl<'a> Change<'a> {
+    pub fn key(&self) -> &'a str {
+        match *self {
+            Change::Added(key, _) | Change::Removed(key, _) | Change::Changed(key, _, _) => key,
+        }
+    }
+}
+
+/// the changes between two environments, from Env::diff
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EnvDiff<'a> {
+    changes: Vec<Change<'a>>,
+}
+
+impl<'a> EnvDiff<'a> {
+    pub fn iter(&self) -> impl Iterator<Item = Change<'a>> + '_ {
+        self.changes.iter().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    /// true if the environments hold the same variables
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn added(&self) -> impl Iterator<Item = (&'a str, &'a str)> + '_ {
+        self.iter().filter_map(|c| match c {
+            Change::Added(key, val) => Some((key, val)),
+            _ => None,
+        })
+    }
+
+    pub fn removed(&self) -> impl Iterator<Item = (&'a str, &'a str)> + '_ {
+        self.iter().filter_map(|c| match c {
+            Change::Removed(key, val) => Some((key, val)),
+            _ => None,
+        })
+    }
+
+    /// key, old value, new value
+    pub fn changed(&self) -> impl Iterator<Item = (&'a str, &'a str, &'a str)> + '_ {
+        self.iter().filter_map(|c| match c {
+            Change::Changed(key, o, n) => Some((key, o, n)),
+            _ => None,
+        })
+    }
+}
+
+impl<'a> IntoIterator for EnvDiff<'a> {
+    type Item = Change<'a>;
+    type IntoIter = alloc::vec::IntoIter<Change<'a>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.changes.into_iter()
+    }
+}
+
+impl<'a, 'b> IntoIterator for &'b EnvDiff<'a> {
+    type Item = &'b Change<'a>;
+    type IntoIter = core::slice::Iter<'b, Change<'a>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.changes.iter()
+    }
+}
+
+/// a crc32 variant, by its rocksoft model parameters. u-boot uses CRC32,
+/// the one zlib and ethernet use, but some vendor forks change it
+///
+/// ```
+/// use uboot_patcher::Crc;
+///
+/// assert_eq!(Crc::CRC32.checksum(b"123456789"), 0xcbf43926);
+/// assert_eq!(Crc::BZIP2.checksum(b"123456789"), 0xfc891918);
+/// assert_eq!(Crc::JAMCRC.checksum(b"123456789"), 0x340bc6d9);
+/// assert_eq!(Crc::named("posix").unwrap().checksum(b"123456789"), 0x765e7680);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Crc {
+    pub poly: u32,
+    pub init: u32,
+    pub xorout: u32,
+    /// whether bytes go in least significant bit first and the result
+    /// comes out reversed
+    pub reflected: bool,
+}
+
+impl Crc {
+    pub const CRC32: Crc = Crc {
+        poly: 0x04c1_1db7,
+        init: 0xffff_ffff,
+        xorout: 0xffff_ffff,
+        reflected: true,
+    };
+    /// CRC32 without the final inversion
+    pub const JAMCRC: Crc = Crc {
+        xorout: 0,
+        ..Crc::CRC32
+    };
+    /// CRC32 most significant bit first
+    pub const BZIP2: Crc = Crc {
+        reflected: false,
+        ..Crc::CRC32
+    };
+    pub const MPEG2: Crc = Crc {
+        xorout: 0,
+        ..Crc::BZIP2
+    };
+    /// what cksum(1) uses, less the length it appends
+    pub const POSIX: Crc = Crc {
+        init: 0,
+        ..Crc::BZIP2
+    };
+
+    /// the variants above by lowercase name
+    pub const NAMED: &'static [(&'static str, Crc)] = &[
+        ("crc32", Crc::CRC32),
+        ("jamcrc", Crc::JAMCRC),
+        ("bzip2", Crc::BZIP2),
+        ("mpeg2", Crc::MPEG2),
+        ("posix", Crc::POSIX),
+    ];
+
+    pub fn named(name: &str) -> Option<Crc> {
+        Crc::NAMED
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, crc)| crc)
+    }
+
+    pub fn checksum(&self, bytes: &[u8]) -> u32 {
+        if *self == Crc::CRC32 {
+            return crc32fast::hash(bytes);
+        }
+        // a bit at a time. slow, but environments are small and odd crcs
+        // are rare
+        let mut crc = if self.reflected {
+            let poly = self.poly.reverse_bits();
+            bytes.iter().fold(self.init.reverse_bits(), |mut crc, &b| {
+                crc ^= u32::from(b);
+                for _ in 0..8 {
+                    crc = if crc & 1 != 0 {
+                        (crc >> 1) ^ poly
+                    } else {
+                        crc >> 1
+                    };
+                }
+                crc
+            })
+        } else {
+            bytes.iter().fold(self.init, |mut crc, &b| {
+                crc ^= u32::from(b) << 24;
+                for _ in 0..8 {
+                    crc = if crc & 0x8000_0000 != 0 {
+                        (crc << 1) ^ self.poly
+                    } else {
+                        crc << 1
+                    };
+                }
+                crc
+            })
+        };
+        crc ^= self.xorout;
+        crc
+    }
+}
+
+impl Default for Crc {
+    fn default() -> Crc {
+        Crc::CRC32
+    }
+}
+
+/// what parsing does with a key stored more than once, as on some
+/// corrupted devices. all but Error warn about it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateKeys {
+    /// keep the first value and where it was
+    KeepFirst,
+    /// keep the last value and where it was, as u-boot itself does
+    #[default]
+    KeepLast,
+    /// fail with EnvError::DuplicateKey
+    Error,
+}
+
+/// key=val added to vars, with policy saying what happens if key is
+/// already there
+fn insert_var(
+    vars: &mut IndexMap<String, String, Hasher>,
+    key: &str,
+    val: &str,
+    policy: DuplicateKeys,
+) -> Result<()> {
+    let Some(first) = vars.get(key) else {
+        vars.insert(key.into(), val.into());
+        return Ok(());
+    };
+    match policy {
+        DuplicateKeys::Error => {
+            return Err(EnvError::DuplicateKey {
+                key: key.into(),
+                first: first.clone(),
+                second: val.into(),
+            })
+        }
+        DuplicateKeys::KeepFirst => {
+            warn!(
+                "{} is stored twice, keeping the first, {:?}, over {:?}",
+                key, first, val
+            );
+        }
+        DuplicateKeys::KeepLast => {
+            warn!(
+                "{} is stored twice, keeping the last, {:?}, over {:?}",
+                key, val, first
+            );
+            vars.shift_remove(key);
+            vars.insert(key.into(), val.into());
+        }
+    }
+    Ok(())
+}
+
+/// byte order of a stored crc
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Endian {
+    #[default]
+    Little,
+    Big,
+}
+
+/// how the flag bytes of a redundant environment mark which copy is current
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FlagScheme {
+    /// 1 for the active copy and 0 for the obsolete one
+    #[default]
+    Boolean,
+    /// a counter that goes up by one with every save, as on nand, where
+    /// the higher is current (255 counts as older than 0 after wrapping)
+    Counter,
+}
+
+/// the pages of a raw nand dump that has each page's out-of-band bytes
+/// after it, as `nanddump --oob` writes it, e.g. 2048 and 64
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NandOob {
+    pub page_size: usize,
+    pub oob_size: usize,
+}
+
+impl NandOob {
+    /// where offset, counted in page data alone, is in the dump
+    pub fn physical(&self, offset: u64) -> u64 {
+        let page = self.page_size as u64;
+        offset / page * (page + self.oob_size as u64) + offset % page
+    }
+
+    /// the page data of dump, without the oob
+    pub fn data(&self, dump: &[u8]) -> Vec<u8> {
+        dump.chunks(self.page_size + self.oob_size)
+            .flat_map(|page| &page[..page.len().min(self.page_size)])
+            .copied()
+            .collect()
+    }
+}
+
+/// where an environment is and how it's stored. new() gives u-boot's usual
+/// redundant layout; change fields for anything else:
+///
+/// ```
+/// use uboot_patcher::{Endian, Env, EnvLayout};
+///
+/// let layout = EnvLayout {
+///     redundant: false,
+///     crc_endianness: Endian::Big,
+///     ..EnvLayout::new(0x40000, 0x2000)
+/// };
+/// let bytes = layout.write(&Env::from_iter([("bootdelay", "3")]))?;
+/// assert_eq!(bytes.len(), 0x2000);
+/// assert_eq!(layout.parse(&bytes)?.get("bootdelay"), Some("3"));
+/// # Ok::<(), uboot_patcher::EnvError>(())
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EnvLayout {
+    /// where the region starts in its image or device
+    pub offset: u64,
+    /// bytes in the region, both copies included
+    pub size: usize,
+    /// where the second copy is when it isn't straight after the first, as
+    /// when each has a partition of its own. the region is then the two
+    /// copies read from their places and put together, first then second
+    pub redundant_offset: Option<u64>,
+    /// two copies each with a flag byte, rather than a single copy without
+    pub redundant: bool,
+    /// what fills the data area after the last variable: zeroes, or 0xff
+    /// on nor flash so unused space stays erased. parsing takes either
+    pub padding: u8,
+    pub crc_endianness: Endian,
+    pub flag_scheme: FlagScheme,
+    pub crc: Crc,
+    /// what parsing does with a key stored twice
+    pub duplicates: DuplicateKeys,
+    /// for a raw nand dump with oob after every page. offsets still count
+    /// page data alone; the spans step around the oob, so writes leave it
+    /// as it was (ecc included, which then no longer matches)
+    pub oob: Option<NandOob>,
+    /// the erase block size, as fw_env.config's sector size. writes that
+    /// erase are widened to whole blocks, keeping whatever else is in them;
+    /// None leaves it to the storage, which for an mtd knows its own
+    pub sector_size: Option<usize>,
+    /// the key of an environment built with CONFIG_ENV_AES, whose data
+    /// area is aes-128-cbc encrypted under it. the crcs are of the
+    /// encrypted bytes
+    #[cfg(feature = "aes")]
+    pub aes_key: Option<[u8; 16]>,
+}
+
+impl EnvLayout {
+    pub fn new(offset: u64, size: usize) -> EnvLayout {
+        EnvLayout {
+            offset,
+            size,
+            redundant_offset: None,
+            redundant: true,
+            padding: 0,
+            crc_endianness: Endian::Little,
+            flag_scheme: FlagScheme::Boolean,
+            crc: Crc::CRC32,
+            duplicates: DuplicateKeys::KeepLast,
+            oob: None,
+            sector_size: None,
+            #[cfg(feature = "aes")]
+            aes_key: None,
+        }
+    }
+
+    /// where the parts of the region are in the image: each one's offset
+    /// there and the bytes of the region it holds. a single part unless
+    /// the copies are stored apart
+    pub fn spans(&self) -> Vec<(u64, Range<usize>)> {
+        let spans = match self.redundant_offset {
+            Some(second) if self.redundant => {
+                let half = self.copy_len();
+                vec![(self.offset, 0..half), (second, half..self.size)]
+            }
+            _ => vec![(self.offset, 0..self.size)],
+        };
+        let Some(oob) = self.oob else {
+            return spans;
+        };
+        // a span for each page's part
+        let mut paged = Vec::new();
+        for (at, span) in spans {
+            let mut pos = span.start;
+            while pos < span.end {
+                let offset = at + (pos - span.start) as u64;
+                let in_page = (offset % oob.page_size as u64) as usize;
+                let len = (oob.page_size - in_page).min(span.end - pos);
+                paged.push((oob.physical(offset), pos..pos + len));
+                pos += len;
+            }
+        }
+        paged
+    }
+
+    /// bytes in each copy
+    fn copy_len(&self) -> usize {
+        if self.redundant {
+            self.size / 2
+        } else {
+            self.size
+        }
+    }
+
+    /// bytes before each copy's data: the crc, then the flag if redundant
+    fn header_len(&self) -> usize {
+        if self.redundant {
+            5
+        } else {
+            4
+        }
+    }
+
+    /// bytes after the header of each copy
+    fn data_area_len(&self) -> usize {
+        self.copy_len().saturating_sub(self.header_len())
+    }
+
+    /// room for key=val strings in each copy, less the nul that ends them
+    /// when the padding isn't zeroes
+    pub fn capacity(&self) -> usize {
+        self.data_area_len()
+            .saturating_sub(usize::from(self.padding != 0))
+    }
+
+    /// the crc and flag of each copy in region, which holds the size bytes
+    /// at offset. a single copy has no flag of its own and gets 1, active
+    pub fn copies(&self, region: &[u8]) -> Result<Vec<EnvCopy>> {
+        let (copy_len, header) = (self.copy_len(), self.header_len());
+        if copy_len <= header || region.len() < self.size {
+            return Err(EnvError::TooSmall { size: region.len() });
+        }
+
+        let copy = |half: &[u8]| {
+            let crc = [half[0], half[1], half[2], half[3]];
+            EnvCopy {
+                stored_crc: match self.crc_endianness {
+                    Endian::Little => u32::from_le_bytes(crc),
+                    Endian::Big => u32::from_be_bytes(crc),
+                },
+                calc_crc: self.crc.checksum(&half[header..]),
+                flag: if self.redundant { half[4] } else { 1 },
+            }
+        };
+        let mut copies = vec![copy(&region[..copy_len])];
+        if self.redundant {
+            copies.push(copy(&region[copy_len..copy_len * 2]));
+        }
+        Ok(copies)
+    }
+
+    /// the index into copies of the one u-boot would load: the only valid
+    /// one, or when both are valid the one that flag_scheme says is
+    /// current, the first if the flags can't tell. None if none is valid
+    pub fn current_copy(&self, copies: &[EnvCopy]) -> Option<usize> {
+        match copies {
+            [one, two] if one.is_valid() && two.is_valid() => Some(match self.flag_scheme {
+                FlagScheme::Boolean => usize::from(!one.is_active() && two.is_active()),
+                FlagScheme::Counter => match (one.flag, two.flag) {
+                    (255, 0) => 1,
+                    (0, 255) => 0,
+                    (a, b) => usize::from(b > a),
+                },
+            }),
+            _ => copies.iter().position(EnvCopy::is_valid),
+        }
+    }
+
+    /// the data area of the current copy in region, its crc, and which
+    /// copy it is from 0
+    fn checked_data<'a>(&self, region: &'a [u8]) -> Result<(&'a [u8], u32, usize)> {
+        let copies = self.copies(region)?;
+        for (i, copy) in copies.iter().enumerate() {
+            debug!("copy {}: {}", i + 1, copy.describe());
+        }
+
+        let Some(current) = self.current_copy(&copies) else {
+            return Err(EnvError::CrcMismatch {
+                copy: 1,
+                stored: copies[0].stored_crc,
+                computed: copies[0].calc_crc,
+            });
+        };
+        if let Some(bad) = copies.iter().position(|copy| !copy.is_valid()) {
+            warn!("copy {} has a bad crc, using copy {}", bad + 1, current + 1);
+        } else if copies
+            .iter()
+            .any(|copy| copy.stored_crc != copies[current].stored_crc)
+        {
+            // saves that alternate between the copies leave them differing.
+            // only flags that don't pick one out suggest something went wrong
+            let flagged = match self.flag_scheme {
+                FlagScheme::Boolean => copies[0].is_active() != copies[1].is_active(),
+                FlagScheme::Counter => copies[0].flag != copies[1].flag,
+            };
+            if flagged {
+                info!(
+                    "copies differ, using copy {} as flagged current",
+                    current + 1
+                );
+            } else {
+                warn!(
+                    "copies differ and their flags don't say which is current, \
+                     probably from an interrupted write; using copy {}",
+                    current + 1
+                );
+            }
+        }
+
+        let start = self.data_start(current);
+        Ok((
+            &region[start..(current + 1) * self.copy_len()],
+            copies[current].stored_crc,
+            current,
+        ))
+    }
+
+    /// where copy's data area starts in the region
+    fn data_start(&self, copy: usize) -> usize {
+        copy * self.copy_len() + self.header_len()
+    }
+
+    /// checked_data, decrypted when there's an aes key
+    fn plain_data<'a>(&self, region: &'a [u8]) -> Result<(Cow<'a, [u8]>, u32, usize)> {
+        let (data, crc, current) = self.checked_data(region)?;
+        #[cfg(feature = "aes")]
+        if let Some(key) = &self.aes_key {
+            let mut data = data.to_vec();
+            aes_cbc(key, &mut data, false);
+            return Ok((Cow::Owned(data), crc, current));
+        }
+        Ok((Cow::Borrowed(data), crc, current))
+    }
+
+    /// parse the current copy in region without copying, failing unless
+    /// some copy's crc checks out and every entry is key=value. encrypted
+    /// environments have to be parsed instead
+    pub fn view<'a>(&self, region: &'a [u8]) -> Result<EnvView<'a>> {
+        #[cfg(feature = "aes")]
+        if self.aes_key.is_some() {
+            return Err(EnvError::Encrypted);
+        }
+        let (data, crc, current) = self.checked_data(region)?;
+        let view = EnvView {
+            data,
+            copy: current + 1,
+            base: self.data_start(current),
+        };
+        for entry in entries(data, view.copy, view.base) {
+            entry?;
+        }
+        if self.duplicates == DuplicateKeys::Error {
+            let mut seen = IndexMap::<&str, &str, Hasher>::default();
+            for (key, val) in view.iter() {
+                if let Some(first) = seen.insert(key, val) {
+                    return Err(EnvError::DuplicateKey {
+                        key: key.into(),
+                        first: first.into(),
+                        second: val.into(),
+                    });
+                }
+            }
+        }
+        info!(
+            "crc {:#010x} ok, parsed {} variables using {} of {} bytes",
+            crc,
+            view.len(),
+            view.serialized_len(),
+            self.capacity()
+        );
+        Ok(view)
+    }
+
+    /// parse region, see view
+    pub fn parse(&self, region: &[u8]) -> Result<Env> {
+        #[cfg(feature = "aes")]
+        if self.aes_key.is_some() {
+            let (data, crc, current) = self.plain_data(region)?;
+            let env = self.collect(entries(&data, current + 1, self.data_start(current)))?;
+            info!(
+                "crc {:#010x} ok, decrypted {} variables using {} of {} bytes",
+                crc,
+                env.len(),
+                env.serialized_len(),
+                self.capacity()
+            );
+            return Ok(env);
+        }
+        let view = self.view(region)?;
+        self.collect(view.iter().map(Ok))
+    }
+
+    /// entries as an environment, handling duplicates as the layout says
+    fn collect<'a>(
+        &self,
+        entries: impl Iterator<Item = Result<(&'a str, &'a str)>>,
+    ) -> Result<Env> {
+        let mut env = Env::new();
+        for entry in entries {
+            let (key, val) = entry?;
+            insert_var(&mut env.vars, key, val, self.duplicates)?;
+        }
+        Ok(env)
+    }
+
+    /// like parse, but entries that aren't key=value are kept as raw
+    /// entries rather than failing, so that writing the environment back
+    /// leaves them as they were
+    pub fn parse_preserving(&self, region: &[u8]) -> Result<Env> {
+        let (data, crc, current) = self.plain_data(region)?;
+        let mut env = Env::new();
+        for entry in entries(&data, current + 1, self.data_start(current)) {
+            match entry {
+                Ok((key, val)) => insert_var(&mut env.vars, key, val, self.duplicates)?,
+                Err(EnvError::InvalidEntry { bytes, problem, .. }) => {
+                    env.raw.push(RawEntry {
+                        after: env.vars.last().map(|(key, _)| key.clone()),
+                        bytes,
+                        problem,
+                    });
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        info!(
+            "crc {:#010x} ok, parsed {} variables and {} raw entries",
+            crc,
+            env.len(),
+            env.raw.len()
+        );
+        Ok(env)
+    }
+
+    /// serialize env in this layout with its variables in their current
+    /// order
+    pub fn write(&self, env: &Env) -> Result<Vec<u8>> {
+        EnvWriter::new(*self).write(env)
+    }
+}
+
+/// one write of an update to part of a region, as EnvWriter::write_alternate
+/// returns them
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegionWrite {
+    /// from the start of the region
+    pub offset: usize,
+    pub bytes: Vec<u8>,
+    /// whether flash has to be erased before writing. clearing a flag
+    /// byte only turns bits off, which nor flash can do in place
+    pub erase: bool,
+}
+
+impl RegionWrite {
+    /// replacing all of a region with bytes
+    pub fn whole(bytes: Vec<u8>) -> RegionWrite {
+        RegionWrite {
+            offset: 0,
+            bytes,
+            erase: true,
+        }
+    }
+
+    /// make the same change to a region in memory
+    pub fn apply(&self, region: &mut [u8]) {
+        region[self.offset..self.offset + self.bytes.len()].copy_from_slice(&self.bytes);
+    }
+
+    /// the image offsets and bytes this comes down to in layout, split
+    /// where the region is split
+    pub fn placed(&self, layout: &EnvLayout) -> Vec<(u64, &[u8])> {
+        let end = self.offset + self.bytes.len();
+        layout
+            .spans()
+            .into_iter()
+            .filter_map(|(at, span)| {
+                let (start, stop) = (self.offset.max(span.start), end.min(span.end));
+                (start < stop).then(|| {
+                    (
+                        at + (start - span.start) as u64,
+                        &self.bytes[start - self.offset..stop - self.offset],
+                    )
+                })
+            })
+            .collect()
+    }
+}
+
+/// serializes environments in a layout, with options that don't change
+/// how the result is read back
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EnvWriter {
+    pub layout: EnvLayout,
+    /// write variables in key order rather than their current order
+    pub sorted: bool,
+    /// under FlagScheme::Counter, the counter of the environment being
+    /// replaced. the first copy is written with one more and the second
+    /// with this, so the first is current
+    pub counter: u8,
+}
+
+impl EnvWriter {
+    pub fn new(layout: EnvLayout) -> EnvWriter {
+        EnvWriter {
+            layout,
+            sorted: false,
+            counter: 0,
+        }
+    }
+
+    /// a writer whose flags carry on from the environment in region, the
+    /// size bytes at the layout's offset, so a counter keeps counting
+    pub fn replacing(layout: EnvLayout, region: &[u8]) -> EnvWriter {
+        let counter = layout
+            .copies(region)
+            .ok()
+            .and_then(|copies| Some(copies[layout.current_copy(&copies)?].flag))
+            .unwrap_or(0);
+        EnvWriter {
+            counter,
+            ..EnvWriter::new(layout)
+        }
+    }
+
+    /// room for key=val strings in each copy
+    pub fn capacity(&self) -> usize {
+        self.layout.capacity()
+    }
+
+    /// the writes that put env in the copy of current, the region as it is
+    /// now, that isn't current, flagging it current and leaving the other
+    /// as a fallback, the way fw_setenv does. with boolean flags the old
+    /// copy's flag is then cleared. a single copy is rewritten whole
+    pub fn write_alternate(&self, env: &Env, current: &[u8]) -> Result<Vec<RegionWrite>> {
+        let layout = &self.layout;
+        let bytes = self.write(env)?;
+        if !layout.redundant {
+            return Ok(vec![RegionWrite::whole(bytes)]);
+        }
+
+        let copies = layout.copies(current)?;
+        let old = layout.current_copy(&copies);
+        let new = usize::from(old == Some(0));
+        let copy_len = layout.copy_len();
+        let mut copy = bytes[..copy_len].to_vec();
+        copy[4] = match layout.flag_scheme {
+            FlagScheme::Boolean => 1,
+            FlagScheme::Counter => old
+                .map_or(self.counter, |old| copies[old].flag)
+                .wrapping_add(1),
+        };
+
+        let mut writes = vec![RegionWrite {
+            offset: new * copy_len,
+            bytes: copy,
+            erase: true,
+        }];
+        if let (Some(old), FlagScheme::Boolean) = (old, layout.flag_scheme) {
+            writes.push(RegionWrite {
+                offset: old * copy_len + 4,
+                bytes: vec![0],
+                erase: false,
+            });
+        }
+        Ok(writes)
+    }
+
+    pub fn write(&self, env: &Env) -> Result<Vec<u8>> {
+        let max_data_len = self.capacity();
+        let mut data_bytes: Vec<u8> = Vec::with_capacity(max_data_len);
+
+        let mut vars: Vec<_> = env.iter().collect();
+        if self.sorted {
+            vars.sort();
+        }
+
+        // raw entries go back after the variable they followed, or at the
+        // end if it's gone
+        let raw_after = |key: Option<&str>| -> Vec<u8> {
+            env.raw
+                .iter()
+                .filter(|raw| raw.after.as_deref() == key)
+                .flat_map(|raw| raw.bytes.iter().copied().chain([0]))
+                .collect()
+        };
+        let orphans = env
+            .raw
+            .iter()
+            .filter(|raw| raw.after.as_deref().is_some_and(|k| !env.contains_key(k)))
+            .flat_map(|raw| raw.bytes.iter().copied().chain([0]));
+        data_bytes.extend(raw_after(None));
+
+        // convert to key=val c strings
+        for (key, val) in vars {
+            // anything that would split or cut short its entry. stricter
+            // checks are left to set, so odd names read from a device
+            // still round-trip
+            let problem = if key.is_empty() {
+                Some(VarProblem::EmptyKey)
+            } else if key.contains('=') {
+                Some(VarProblem::KeyHasEquals)
+            } else if key.contains('\0') {
+                Some(VarProblem::KeyNotPrintable)
+            } else {
+                validate_value(val).err()
+            };
+            if let Some(problem) = problem {
+                return Err(EnvError::InvalidVar {
+                    key: key.into(),
+                    problem,
+                });
+            }
+
+            data_bytes.extend(key.bytes());
+            data_bytes.extend("=".bytes());
+            data_bytes.extend(val.bytes());
+            data_bytes.push(0);
+            data_bytes.extend(raw_after(Some(key)));
+        }
+        data_bytes.extend(orphans);
+
+        let usage = data_bytes.len();
+
+        if usage > max_data_len {
+            return Err(EnvError::Overflow {
+                needed: usage,
+                available: max_data_len,
+            });
+        }
+
+        let layout = &self.layout;
+        // with anything but zeroes after it, the data needs an empty entry
+        // to mark its end, as mkenvimage leaves
+        if layout.padding != 0 {
+            data_bytes.push(0);
+        }
+        // pad to length
+        data_bytes.resize(layout.data_area_len(), layout.padding);
+        #[cfg(feature = "aes")]
+        if let Some(key) = &layout.aes_key {
+            aes_cbc(key, &mut data_bytes, true);
+        }
+
+        let crc = layout.crc.checksum(&data_bytes);
+        let crc = match layout.crc_endianness {
+            Endian::Little => crc.to_le_bytes(),
+            Endian::Big => crc.to_be_bytes(),
+        };
+
+        let (active, obsolete) = match layout.flag_scheme {
+            FlagScheme::Boolean => (1, 0),
+            FlagScheme::Counter => (self.counter.wrapping_add(1), self.counter),
+        };
+
+        let mut total_vec: Vec<u8> = Vec::with_capacity(layout.size);
+
+        if !layout.redundant {
+            total_vec.extend(crc);
+            total_vec.extend(&data_bytes);
+        } else {
+            // first half
+            total_vec.extend(crc);
+            total_vec.push(active);
+            total_vec.extend(&data_bytes);
+            // second half
+            total_vec.extend(crc);
+            total_vec.push(obsolete);
+            total_vec.extend(&data_bytes);
+        }
+
+        // an odd size leaves a byte over after two copies
+        total_vec.resize(layout.size, layout.padding);
+
+        Ok(total_vec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// one copy of data in layout: its crc, the flag byte if the layout has
+    /// them, and data zero-padded to the end of the copy
+    fn copy(layout: &EnvLayout, flag: u8, data: &[u8]) -> Vec<u8> {
+        let mut area = data.to_vec();
+        area.resize(layout.copy_len() - layout.header_len(), 0);
+        let mut copy = layout.crc.checksum(&area).to_le_bytes().to_vec();
+        if layout.redundant {
+            copy.push(flag);
+        }
+        copy.extend(area);
+        copy
+    }
+
+    /// a region of copies, each a flag and its data
+    fn region(layout: &EnvLayout, copies: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut region: Vec<u8> = copies
+            .iter()
+            .flat_map(|&(flag, data)| copy(layout, flag, data))
+            .collect();
+        region.resize(layout.size, 0);
+        region
+    }
+
+    #[test]
+    fn view_of_single_copy() {
+        let layout = EnvLayout {
+            redundant: false,
+            ..EnvLayout::new(0, 0x40)
+        };
+        let region = region(&layout, &[(1, b"bootdelay=3\0baudrate=115200\0")]);
+        assert_eq!(&region[4..16], b"bootdelay=3\0");
+        let view = layout.view(&region).unwrap();
+        assert_eq!(
+            view.iter().collect::<Vec<_>>(),
+            [("bootdelay", "3"), ("baudrate", "115200")]
+        );
+    }
+
+    const OLD: &[u8] = b"bootcmd=old\0";
+    const NEW: &[u8] = b"bootcmd=new\0";
+
+    fn bootcmd(layout: &EnvLayout, region: &[u8]) -> String {
+        layout.parse(region).unwrap().get("bootcmd").unwrap().into()
+    }
+
+    #[test]
+    fn boolean_flags_pick_the_active_copy() {
+        let layout = EnvLayout::new(0, 0x40);
+        let second = region(&layout, &[(0, OLD), (1, NEW)]);
+        assert_eq!(bootcmd(&layout, &second), "new");
+        let first = region(&layout, &[(1, NEW), (0, OLD)]);
+        assert_eq!(bootcmd(&layout, &first), "new");
+    }
+
+    #[test]
+    fn boolean_flags_that_dont_tell_pick_the_first() {
+        let layout = EnvLayout::new(0, 0x40);
+        let both = region(&layout, &[(1, NEW), (1, OLD)]);
+        assert_eq!(bootcmd(&layout, &both), "new");
+        let neither = region(&layout, &[(0, NEW), (0, OLD)]);
+        assert_eq!(bootcmd(&layout, &neither), "new");
+    }
+
+    #[test]
+    fn a_bad_crc_loses_to_the_good_copy() {
+        let layout = EnvLayout::new(0, 0x40);
+        let mut region = region(&layout, &[(0, OLD), (1, NEW)]);
+        region[0x20 + 5] ^= 1;
+        assert_eq!(bootcmd(&layout, &region), "old");
+        let copies = layout.copies(&region).unwrap();
+        assert_eq!(layout.current_copy(&copies), Some(0));
+    }
+
+    fn counter_layout() -> EnvLayout {
+        EnvLayout {
+            flag_scheme: FlagScheme::Counter,
+            ..EnvLayout::new(0, 0x40)
+        }
+    }
+
+    #[test]
+    fn the_higher_counter_is_current() {
+        let layout = counter_layout();
+        assert_eq!(
+            bootcmd(&layout, &region(&layout, &[(4, OLD), (5, NEW)])),
+            "new"
+        );
+        assert_eq!(
+            bootcmd(&layout, &region(&layout, &[(5, NEW), (4, OLD)])),
+            "new"
+        );
+    }
+
+    #[test]
+    fn the_counter_wraps_from_0xff_to_0() {
+        let layout = counter_layout();
+        let wrapped = region(&layout, &[(0xff, OLD), (0x00, NEW)]);
+        assert_eq!(bootcmd(&layout, &wrapped), "new");
+        let wrapped = region(&layout, &[(0x00, NEW), (0xff, OLD)]);
+        assert_eq!(bootcmd(&layout, &wrapped), "new");
+    }
+
+    #[test]
+    fn writing_carries_the_counter_on() {
+        let layout = counter_layout();
+        let current = region(&layout, &[(0xff, OLD), (0x00, NEW)]);
+        let writer = EnvWriter::replacing(layout, &current);
+        assert_eq!(writer.counter, 0);
+        let mut env = Env::new();
+        env.set("bootcmd", "newer").unwrap();
+        let written = writer.write(&env).unwrap();
+        assert_eq!((written[4], written[0x20 + 4]), (1, 0));
+        assert_eq!(bootcmd(&layout, &written), "newer");
+    }
+
+    fn newer() -> Env {
+        let mut env = Env::new();
+        env.set("bootcmd", "newer").unwrap();
+        env
+    }
+
+    #[test]
+    fn write_alternate_leaves_the_current_copy_alone() {
+        let layout = EnvLayout::new(0, 0x40);
+        let mut current = region(&layout, &[(1, OLD), (0, NEW)]);
+        let before = current.clone();
+        let writer = EnvWriter::replacing(layout, &current);
+        let writes = writer.write_alternate(&newer(), &current).unwrap();
+
+        assert_eq!(writes.len(), 2);
+        assert_eq!((writes[0].offset, writes[0].erase), (0x20, true));
+        assert_eq!(writes[0].bytes[4], 1);
+        // clearing the old flag last, without an erase
+        assert_eq!(writes[1].offset, 4);
+        assert_eq!(writes[1].bytes, [0]);
+        assert!(!writes[1].erase);
+
+        for write in &writes {
+            write.apply(&mut current);
+        }
+        assert_eq!(current[..4], before[..4]);
+        assert_eq!(current[5..0x20], before[5..0x20]);
+        assert_eq!(bootcmd(&layout, &current), "newer");
+    }
+
+    #[test]
+    fn write_alternate_with_counters_only_writes_the_other_copy() {
+        let layout = counter_layout();
+        let mut current = region(&layout, &[(7, OLD), (6, NEW)]);
+        let before = current.clone();
+        let writer = EnvWriter::replacing(layout, &current);
+        let writes = writer.write_alternate(&newer(), &current).unwrap();
+
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0].offset, 0x20);
+        assert_eq!(writes[0].bytes[4], 8);
+        writes[0].apply(&mut current);
+        assert_eq!(current[..0x20], before[..0x20]);
+        assert_eq!(bootcmd(&layout, &current), "newer");
+    }
+
+    #[test]
+    fn write_alternate_wraps_the_counter() {
+        let layout = counter_layout();
+        let mut current = region(&layout, &[(0xff, OLD), (0xfe, NEW)]);
+        let writer = EnvWriter::replacing(layout, &current);
+        let writes = writer.write_alternate(&newer(), &current).unwrap();
+
+        assert_eq!(writes[0].bytes[4], 0);
+        writes[0].apply(&mut current);
+        let copies = layout.copies(&current).unwrap();
+        assert_eq!(layout.current_copy(&copies), Some(1));
+        assert_eq!(bootcmd(&layout, &current), "newer");
+    }
+
+    #[test]
+    fn a_bad_entry_in_copy_2_says_so() {
+        let layout = EnvLayout::new(0, 0x40);
+        let current = region(&layout, &[(0, OLD), (1, b"bootcmd=new\0junk\0")]);
+        let Err(EnvError::InvalidEntry { copy, offset, .. }) = layout.view(&current) else {
+            panic!("junk parsed");
+        };
+        // past copy 1, copy 2's crc and flag, and bootcmd=new
+        assert_eq!((copy, offset), (2, 0x20 + 5 + 12));
+        let env = layout.parse_preserving(&current).unwrap();
+        assert_eq!(env.raw_entries().count(), 1);
+        assert_eq!(env.get("bootcmd"), Some("new"));
+    }
+
+    #[test]
+    fn raw_entries_carry_over_unless_replaced() {
+        let layout = EnvLayout::new(0, 0x40);
+        let current = region(&layout, &[(1, b"a=\xff\0junk\0"), (0, OLD)]);
+        let mut old = layout.parse_preserving(&current).unwrap();
+        let raw = old.take_raw_entries();
+
+        let mut new = Env::new();
+        new.set("b", "1").unwrap();
+        new.keep_raw_entries(raw.clone());
+        assert_eq!(new.raw_entries().count(), 2);
+
+        let mut new = Env::new();
+        new.set("a", "1").unwrap();
+        new.keep_raw_entries(raw);
+        assert_eq!(new.raw_entries().count(), 1);
+        assert_eq!(new.get_bytes("a"), Some(&b"1"[..]));
+    }
+
+    /// an environment with k set to a value that isn't utf-8
+    fn not_utf8() -> Env {
+        let mut env = Env::new();
+        env.set("a", "1").unwrap();
+        env.set_bytes("k", b"\xff").unwrap();
+        env
+    }
+
+    #[test]
+    fn values_that_arent_utf8_are_still_set() {
+        let mut env = not_utf8();
+        assert!(env.contains_key("k"));
+        env.entry("k").or_default().push_str("new");
+        assert_eq!(env.raw_entries().count(), 0);
+        assert_eq!(env.get("k"), Some("new"));
+    }
+
+    #[test]
+    fn rename_moves_values_that_arent_utf8() {
+        let mut env = not_utf8();
+        assert!(env.rename("k", "j").unwrap());
+        assert_eq!(env.get_bytes("j"), Some(&b"\xff"[..]));
+        assert!(!env.contains_key("k"));
+
+        assert!(env.rename("a", "j").unwrap());
+        assert_eq!(env.get("j"), Some("1"));
+        assert_eq!(env.raw_entries().count(), 0);
+    }
+
+    #[test]
+    fn merge_sees_values_that_arent_utf8() {
+        let mut theirs = Env::new();
+        theirs.set("k", "2").unwrap();
+        let mut env = not_utf8();
+        assert!(env.merge(theirs.clone(), MergeStrategy::Fail).is_err());
+        env.merge(theirs.clone(), MergeStrategy::Ours).unwrap();
+        assert_eq!(env.get_bytes("k"), Some(&b"\xff"[..]));
+        env.merge(theirs, MergeStrategy::Theirs).unwrap();
+        assert_eq!(env.get("k"), Some("2"));
+        assert_eq!(env.raw_entries().count(), 0);
+    }
+}
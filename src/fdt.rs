@@ -0,0 +1,577 @@
+//! flattened device trees (.dtb), for reading where a board's device tree
+//! says its environment is: a fixed partition marked u-boot,env (linux's
+//! nvmem binding) or labelled u-boot-env, or the u-boot,mmc-env-offset
+//! properties of u-boot's own /config node
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// the first four bytes of a dtb, big-endian
+pub const MAGIC: u32 = 0xd00d_feed;
+
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+/// what's wrong with a dtb that can't be parsed
+#[derive(Debug, thiserror::Error)]
+pub enum FdtError {
+    /// no magic, or a header that points outside the blob
+    #[error("not a flattened device tree")]
+    BadHeader,
+    /// the structure block ends in the middle of something
+    #[error("device tree is cut short at {offset:#x}")]
+    Truncated { offset: usize },
+    /// a token that isn't a node, property, nop or end
+    #[error("device tree has unknown token {token:#x} at {offset:#x}")]
+    UnknownToken { offset: usize, token: u32 },
+}
+
+/// one node of a device tree, with its properties in the order they're
+/// stored
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Node {
+    /// the name with its unit address, as partition@40000. empty for the
+    /// root
+    pub name: String,
+    pub properties: Vec<(String, Vec<u8>)>,
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    /// the value of property name
+    pub fn property(&self, name: &str) -> Option<&[u8]> {
+        self.properties
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// a string list property such as compatible, empty if it's missing
+    pub fn strings<'a>(&'a self, name: &str) -> impl Iterator<Item = &'a str> {
+        self.property(name)
+            .unwrap_or_default()
+            .split(|&b| b == 0)
+            .filter(|s| !s.is_empty())
+            .filter_map(|s| core::str::from_utf8(s).ok())
+    }
+
+    /// a property of one 32-bit cell
+    pub fn cell(&self, name: &str) -> Option<u32> {
+        Some(u32::from_be_bytes(self.property(name)?.try_into().ok()?))
+    }
+
+    /// the child at path, as config or /chosen (leading slash or not)
+    pub fn find(&self, path: &str) -> Option<&Node> {
+        path.split('/')
+            .filter(|part| !part.is_empty())
+            .try_fold(self, |node, part| {
+                node.children
+                    .iter()
+                    .find(|child| child.name == part || child.name.split('@').next() == Some(part))
+            })
+    }
+
+    /// the first region of reg, read with the #address-cells and
+    /// #size-cells of its parent. None for more than two cells of either,
+    /// which wouldn't fit in a u64
+    fn reg(&self, parent: &Node) -> Option<(u64, u64)> {
+        let address_cells = parent.cell("#address-cells").unwrap_or(2) as usize;
+        let size_cells = parent.cell("#size-cells").unwrap_or(1) as usize;
+        if address_cells > 2 || size_cells > 2 {
+            return None;
+        }
+        let reg = self.property("reg")?;
+        let number = |cells: &[u8]| {
+            cells.chunks(4).try_fold(0u64, |n, c| {
+                Some(n << 32 | u64::from(u32::from_be_bytes(c.try_into().ok()?)))
+            })
+        };
+        let address = number(reg.get(..address_cells * 4)?)?;
+        let size = number(reg.get(address_cells * 4..(address_cells + size_cells) * 4)?)?;
+        Some((address, size))
+    }
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// whether bytes start like a dtb
+pub fn is_fdt(bytes: &[u8]) -> bool {
+    u32_at(bytes, 0) == Some(MAGIC)
+}
+
+/// the tree in dtb, from its root node
+pub fn parse(dtb: &[u8]) -> Result<Node, FdtError> {
+    if !is_fdt(dtb) {
+        return Err(FdtError::BadHeader);
+    }
+    let header = |at| {
+        u32_at(dtb, at)
+            .ok_or(FdtError::BadHeader)
+            .map(|n| n as usize)
+    };
+    let total = header(4)?.min(dtb.len());
+    let structs = dtb.get(header(8)?..total).ok_or(FdtError::BadHeader)?;
+    let strings = dtb.get(header(12)?..total).ok_or(FdtError::BadHeader)?;
+
+    // the nodes still open, innermost last
+    let mut open: Vec<Node> = Vec::new();
+    let mut at = 0;
+    loop {
+        let truncated = FdtError::Truncated { offset: at };
+        let token = u32_at(structs, at).ok_or(truncated)?;
+        at += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let len = structs
+                    .get(at..)
+                    .and_then(|rest| rest.iter().position(|&b| b == 0))
+                    .ok_or(FdtError::Truncated { offset: at })?;
+                open.push(Node {
+                    name: String::from_utf8_lossy(&structs[at..at + len]).into_owned(),
+                    ..Node::default()
+                });
+                at = (at + len + 1).next_multiple_of(4);
+            }
+            FDT_END_NODE => {
+                let node = open.pop().ok_or(FdtError::Truncated { offset: at })?;
+                match open.last_mut() {
+                    Some(parent) => parent.children.push(node),
+                    None => return Ok(node),
+                }
+            }
+            FDT_PROP => {
+                let truncated = || FdtError::Truncated { offset: at };
+                let len = u32_at(structs, at).ok_or_else(truncated)? as usize;
+                let name_at = u32_at(structs, at + 4).ok_or_else(truncated)? as usize;
+                let value = structs.get(at + 8..at + 8 + len).ok_or_else(truncated)?;
+                let name = strings
+                    .get(name_at..)
+                    .and_then(|rest| rest.split(|&b| b == 0).next())
+                    .ok_or_else(truncated)?;
+                open.last_mut()
+                    .ok_or_else(truncated)?
+                    .properties
+                    .push((String::from_utf8_lossy(name).into_owned(), value.to_vec()));
+                at = (at + 8 + len).next_multiple_of(4);
+            }
+            FDT_NOP => {}
+            FDT_END => return Err(FdtError::Truncated { offset: at }),
+            token => {
+                return Err(FdtError::UnknownToken {
+                    offset: at - 4,
+                    token,
+                })
+            }
+        }
+    }
+}
+
+/// the labels u-boot-env partitions go by without a compatible, and those
+/// of the second copy's partition
+const ENV_LABELS: [&str; 5] = [
+    "u-boot-env",
+    "uboot-env",
+    "ubootenv",
+    "u-boot-environment",
+    "env",
+];
+const REDUNDANT_LABELS: [&str; 5] = [
+    "u-boot-env2",
+    "uboot-env2",
+    "ubootenv2",
+    "u-boot-env-redundant",
+    "env2",
+];
+
+/// where a device tree puts the environment
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DtEnv {
+    /// bytes into the flash or disk the partition is on, or back from its
+    /// end if negative (a negative u-boot,mmc-env-offset)
+    pub offset: i64,
+    /// a fixed partition's size. u-boot's /config doesn't say, its
+    /// CONFIG_ENV_SIZE being built in
+    pub size: Option<u64>,
+    /// the second copy, when it's somewhere of its own
+    pub redundant_offset: Option<i64>,
+    /// Some(true) for a redundant environment, Some(false) for a single
+    /// copy, None when the tree doesn't say
+    pub redundant: Option<bool>,
+    /// whether the flag bytes count saves (u-boot,env-redundant-count)
+    /// rather than being 1 and 0
+    pub counter: bool,
+    /// the partition's label, which is also its mtd name on the target
+    pub label: Option<String>,
+    /// u-boot,mmc-env-partition: the offsets count from the start of this
+    /// partition of the mmc
+    pub partition: Option<String>,
+}
+
+/// where the tree from root says the environment is, if it says
+pub fn find_env(root: &Node) -> Option<DtEnv> {
+    let mut partitions = Vec::new();
+    collect_partitions(root, &mut partitions);
+    let compatible = |node: &Node| {
+        node.strings("compatible")
+            .chain(
+                node.find("nvmem-layout")
+                    .into_iter()
+                    .flat_map(|layout| layout.strings("compatible")),
+            )
+            .find_map(|c| match c {
+                "u-boot,env" | "brcm,env" => Some((false, false)),
+                "u-boot,env-redundant-bool" => Some((true, false)),
+                "u-boot,env-redundant-count" => Some((true, true)),
+                _ => None,
+            })
+    };
+    let labelled = |node: &Node, labels: &[&str]| {
+        let label = node.strings("label").next().or(node.name.split('@').next());
+        label.is_some_and(|label| labels.contains(&label))
+    };
+    let find = |labels: &[&str]| {
+        partitions
+            .iter()
+            .copied()
+            .find(|(node, _)| labelled(node, labels))
+    };
+
+    let marked: Vec<_> = partitions
+        .iter()
+        .filter_map(|&(node, reg)| Some((node, reg, compatible(node)?)))
+        .collect();
+    let (first, second, redundant, counter) = match marked.as_slice() {
+        // two marked copies, as on boards with u-boot-env and u-boot-env2
+        [(a, a_reg, (_, counter)), (b, b_reg, _), ..] => {
+            (Some((*a, *a_reg)), Some((*b, *b_reg)), Some(true), *counter)
+        }
+        [(a, a_reg, (redundant, counter))] => {
+            let second = find(&REDUNDANT_LABELS);
+            let redundant = *redundant || second.is_some();
+            (Some((*a, *a_reg)), second, Some(redundant), *counter)
+        }
+        // nothing marked, so going by labels. one alone could be either
+        [] => {
+            let second = find(&REDUNDANT_LABELS);
+            (find(&ENV_LABELS), second, second.map(|_| true), false)
+        }
+    };
+    if let Some((node, (offset, size))) = first {
+        return Some(DtEnv {
+            offset: offset as i64,
+            size: Some(size),
+            redundant_offset: second.map(|(_, (offset, _))| offset as i64),
+            redundant,
+            counter,
+            label: node.strings("label").next().map(String::from),
+            partition: None,
+        });
+    }
+
+    let config = root.find("config")?;
+    // read as signed, a negative offset counting back from the end
+    let offset = |name| config.cell(name).map(|n| i64::from(n as i32));
+    Some(DtEnv {
+        offset: offset("u-boot,mmc-env-offset")?,
+        size: None,
+        redundant_offset: offset("u-boot,mmc-env-offset-redundant"),
+        redundant: None,
+        counter: false,
+        label: None,
+        partition: config
+            .strings("u-boot,mmc-env-partition")
+            .next()
+            .map(String::from),
+    })
+}
+
+/// every partition under a fixed-partitions node below node, with its
+/// offset and size
+fn collect_partitions<'a>(node: &'a Node, found: &mut Vec<(&'a Node, (u64, u64))>) {
+    if node.strings("compatible").any(|c| c == "fixed-partitions") {
+        found.extend(
+            node.children
+                .iter()
+                .filter_map(|child| Some((child, child.reg(node)?))),
+        );
+    }
+    for child in &node.children {
+        collect_partitions(child, found);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    /// a dtb written out a token at a time
+    #[derive(Default)]
+    struct Dtb {
+        structs: Vec<u8>,
+        strings: Vec<u8>,
+    }
+
+    impl Dtb {
+        fn token(&mut self, token: u32) -> &mut Dtb {
+            self.structs.extend_from_slice(&token.to_be_bytes());
+            self
+        }
+
+        fn pad(&mut self) {
+            self.structs
+                .resize(self.structs.len().next_multiple_of(4), 0);
+        }
+
+        fn node(&mut self, name: &str) -> &mut Dtb {
+            self.token(FDT_BEGIN_NODE);
+            self.structs.extend_from_slice(name.as_bytes());
+            self.structs.push(0);
+            self.pad();
+            self
+        }
+
+        fn end(&mut self) -> &mut Dtb {
+            self.token(FDT_END_NODE)
+        }
+
+        fn prop(&mut self, name: &str, value: &[u8]) -> &mut Dtb {
+            let name_at = self.strings.len() as u32;
+            self.strings.extend_from_slice(name.as_bytes());
+            self.strings.push(0);
+            self.token(FDT_PROP)
+                .token(value.len() as u32)
+                .token(name_at);
+            self.structs.extend_from_slice(value);
+            self.pad();
+            self
+        }
+
+        fn string(&mut self, name: &str, value: &str) -> &mut Dtb {
+            self.prop(name, &[value.as_bytes(), b"\0"].concat())
+        }
+
+        fn cells(&mut self, name: &str, cells: &[u32]) -> &mut Dtb {
+            let value: Vec<u8> = cells.iter().flat_map(|c| c.to_be_bytes()).collect();
+            self.prop(name, &value)
+        }
+
+        /// a fixed-partitions node of one cell addresses and sizes
+        fn partitions(&mut self) -> &mut Dtb {
+            self.node("partitions")
+                .string("compatible", "fixed-partitions")
+                .cells("#address-cells", &[1])
+                .cells("#size-cells", &[1])
+        }
+
+        fn partition(&mut self, offset: u32, size: u32, label: &str) -> &mut Dtb {
+            self.node(&format!("partition@{:x}", offset))
+                .cells("reg", &[offset, size])
+                .string("label", label)
+        }
+
+        fn finish(&mut self) -> Vec<u8> {
+            self.token(FDT_END);
+            let structs_at = 40;
+            let strings_at = structs_at + self.structs.len();
+            let total = strings_at + self.strings.len();
+            let mut dtb = vec![0; 40];
+            for (i, field) in [MAGIC, total as u32, structs_at as u32, strings_at as u32]
+                .into_iter()
+                .enumerate()
+            {
+                dtb[i * 4..i * 4 + 4].copy_from_slice(&field.to_be_bytes());
+            }
+            dtb.extend_from_slice(&self.structs);
+            dtb.extend_from_slice(&self.strings);
+            dtb
+        }
+    }
+
+    fn env_of(dtb: &[u8]) -> Option<DtEnv> {
+        find_env(&parse(dtb).unwrap())
+    }
+
+    #[test]
+    fn parses_nodes_and_properties() {
+        let dtb = Dtb::default()
+            .node("")
+            .string("model", "board")
+            .node("chosen")
+            .cells("stdout", &[1, 2])
+            .end()
+            .end()
+            .finish();
+        let root = parse(&dtb).unwrap();
+        assert_eq!(root.strings("model").collect::<Vec<_>>(), ["board"]);
+        assert_eq!(
+            root.find("/chosen").unwrap().property("stdout"),
+            Some(&[0, 0, 0, 1, 0, 0, 0, 2][..])
+        );
+        assert_eq!(root.find("chosen/missing"), None);
+    }
+
+    #[test]
+    fn truncated_and_bad_blobs() {
+        let dtb = Dtb::default().node("").node("chosen").end().end().finish();
+        // ending with chosen and the root still open
+        let open = Dtb::default().node("").node("chosen").finish();
+        assert!(matches!(parse(&open), Err(FdtError::Truncated { .. })));
+        // a property longer than what's left of the structure block, its
+        // length 12 bytes into it
+        let mut long = Dtb::default()
+            .node("")
+            .string("model", "board")
+            .end()
+            .finish();
+        long[52..56].copy_from_slice(&0x1000u32.to_be_bytes());
+        assert!(matches!(
+            parse(&long),
+            Err(FdtError::Truncated { offset: 12 })
+        ));
+        // a file cut short loses the strings the header points to
+        assert!(matches!(parse(&dtb[..52]), Err(FdtError::BadHeader)));
+        assert!(matches!(parse(&dtb[..8]), Err(FdtError::BadHeader)));
+        assert!(matches!(parse(&[0; 64]), Err(FdtError::BadHeader)));
+        let mut unknown = dtb.clone();
+        unknown[40..44].copy_from_slice(&7u32.to_be_bytes());
+        assert!(matches!(
+            parse(&unknown),
+            Err(FdtError::UnknownToken {
+                offset: 0,
+                token: 7
+            })
+        ));
+    }
+
+    #[test]
+    fn partition_marked_u_boot_env() {
+        let dtb = Dtb::default()
+            .node("")
+            .partitions()
+            .partition(0, 0x40000, "u-boot")
+            .end()
+            .partition(0x40000, 0x10000, "config")
+            .string("compatible", "u-boot,env")
+            .end()
+            .end()
+            .end()
+            .finish();
+        assert_eq!(
+            env_of(&dtb),
+            Some(DtEnv {
+                offset: 0x40000,
+                size: Some(0x10000),
+                redundant_offset: None,
+                redundant: Some(false),
+                counter: false,
+                label: Some("config".into()),
+                partition: None,
+            })
+        );
+    }
+
+    #[test]
+    fn redundant_compatibles() {
+        for (compatible, counter) in [
+            ("u-boot,env-redundant-count", true),
+            ("u-boot,env-redundant-bool", false),
+        ] {
+            let dtb = Dtb::default()
+                .node("")
+                .partitions()
+                .partition(0x40000, 0x10000, "u-boot-env")
+                .string("compatible", compatible)
+                .end()
+                .partition(0x50000, 0x10000, "u-boot-env2")
+                .string("compatible", compatible)
+                .end()
+                .end()
+                .end()
+                .finish();
+            let env = env_of(&dtb).unwrap();
+            assert_eq!(env.redundant, Some(true));
+            assert_eq!(env.counter, counter);
+            assert_eq!(env.redundant_offset, Some(0x50000));
+        }
+    }
+
+    #[test]
+    fn partitions_by_label_alone() {
+        let dtb = Dtb::default()
+            .node("")
+            .partitions()
+            .partition(0x40000, 0x2000, "env")
+            .end()
+            .partition(0x42000, 0x2000, "env2")
+            .end()
+            .end()
+            .end()
+            .finish();
+        let env = env_of(&dtb).unwrap();
+        assert_eq!((env.offset, env.size), (0x40000, Some(0x2000)));
+        assert_eq!(
+            (env.redundant_offset, env.redundant),
+            (Some(0x42000), Some(true))
+        );
+
+        // one label alone doesn't say whether it's redundant
+        let dtb = Dtb::default()
+            .node("")
+            .partitions()
+            .partition(0x40000, 0x2000, "u-boot-env")
+            .end()
+            .end()
+            .end()
+            .finish();
+        assert_eq!(env_of(&dtb).unwrap().redundant, None);
+    }
+
+    #[test]
+    fn config_mmc_env_offset() {
+        let dtb = Dtb::default()
+            .node("")
+            .node("config")
+            .cells("u-boot,mmc-env-offset", &[-0x4000i32 as u32])
+            .cells("u-boot,mmc-env-offset-redundant", &[-0x2000i32 as u32])
+            .string("u-boot,mmc-env-partition", "boot0")
+            .end()
+            .end()
+            .finish();
+        assert_eq!(
+            env_of(&dtb),
+            Some(DtEnv {
+                offset: -0x4000,
+                size: None,
+                redundant_offset: Some(-0x2000),
+                redundant: None,
+                counter: false,
+                label: None,
+                partition: Some("boot0".into()),
+            })
+        );
+    }
+
+    #[test]
+    fn too_many_address_cells() {
+        let dtb = Dtb::default()
+            .node("")
+            .node("partitions")
+            .string("compatible", "fixed-partitions")
+            .cells("#address-cells", &[3])
+            .cells("#size-cells", &[1])
+            .node("partition@0")
+            .cells("reg", &[1, 0, 0, 0x2000])
+            .string("compatible", "u-boot,env")
+            .end()
+            .end()
+            .end()
+            .finish();
+        assert_eq!(env_of(&dtb), None);
+    }
+}
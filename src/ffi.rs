@@ -0,0 +1,227 @@
+//! a c abi over Env, see include/uboot_env.h. every function returns 0 or
+//! a positive count on success and one of the negative UBOOT_ENV_E* codes
+//! on failure
+
+use std::ffi::{c_char, CStr};
+
+use crate::{Env, EnvError};
+
+const EINVAL: isize = -1;
+const ECRC: isize = -2;
+const EOVERFLOW: isize = -3;
+const ENOENT: isize = -4;
+
+fn error_code(e: &EnvError) -> isize {
+    match e {
+        EnvError::CrcMismatch { .. } => ECRC,
+        EnvError::Overflow { .. } => EOVERFLOW,
+        _ => EINVAL,
+    }
+}
+
+/// a nul-terminated utf-8 string, or None for null or anything else
+unsafe fn str_arg<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        return None;
+    }
+    CStr::from_ptr(s).to_str().ok()
+}
+
+/// an empty environment, freed with uboot_env_free
+#[no_mangle]
+pub extern "C" fn uboot_env_new() -> *mut Env {
+    Box::into_raw(Box::new(Env::new()))
+}
+
+/// parse len bytes of a redundant environment into *out
+///
+/// # Safety
+/// bytes must point to len readable bytes and out to writable space for a
+/// pointer
+#[no_mangle]
+pub unsafe extern "C" fn uboot_env_parse(
+    bytes: *const u8,
+    len: usize,
+    out: *mut *mut Env,
+) -> isize {
+    if bytes.is_null() || out.is_null() {
+        return EINVAL;
+    }
+    match Env::parse(std::slice::from_raw_parts(bytes, len)) {
+        Ok(env) => {
+            *out = Box::into_raw(Box::new(env));
+            0
+        }
+        Err(e) => error_code(&e),
+    }
+}
+
+/// free an environment from uboot_env_new or uboot_env_parse. null is
+/// ignored
+///
+/// # Safety
+/// env must be null or an environment not already freed
+#[no_mangle]
+pub unsafe extern "C" fn uboot_env_free(env: *mut Env) {
+    if !env.is_null() {
+        drop(Box::from_raw(env));
+    }
+}
+
+/// copy key's value into buf as a nul-terminated string, like snprintf:
+/// returns the value's length, truncating if that's buf_len or more
+///
+/// # Safety
+/// env must be valid, key nul-terminated, and buf writable for buf_len
+/// bytes (or null with buf_len 0 to only ask for the length)
+#[no_mangle]
+pub unsafe extern "C" fn uboot_env_get(
+    env: *const Env,
+    key: *const c_char,
+    buf: *mut c_char,
+    buf_len: usize,
+) -> isize {
+    let (Some(env), Some(key)) = (env.as_ref(), str_arg(key)) else {
+        return EINVAL;
+    };
+    let Some(val) = env.get(key) else {
+        return ENOENT;
+    };
+    if !buf.is_null() && buf_len > 0 {
+        let n = val.len().min(buf_len - 1);
+        std::ptr::copy_nonoverlapping(val.as_ptr(), buf.cast(), n);
+        *buf.add(n) = 0;
+    }
+    val.len() as isize
+}
+
+/// set key to val, or remove key if val is null
+///
+/// # Safety
+/// env must be valid and key and val nul-terminated
+#[no_mangle]
+pub unsafe extern "C" fn uboot_env_set(
+    env: *mut Env,
+    key: *const c_char,
+    val: *const c_char,
+) -> isize {
+    let (Some(env), Some(key)) = (env.as_mut(), str_arg(key)) else {
+        return EINVAL;
+    };
+    if val.is_null() {
+        return match env.remove(key) {
+            Some(_) => 0,
+            None => ENOENT,
+        };
+    }
+    let Some(val) = str_arg(val) else {
+        return EINVAL;
+    };
+    match env.set(key, val) {
+        Ok(_) => 0,
+        Err(e) => error_code(&e),
+    }
+}
+
+/// serialize env as a redundant environment filling all size bytes of out
+///
+/// # Safety
+/// env must be valid and out writable for size bytes
+#[no_mangle]
+pub unsafe extern "C" fn uboot_env_serialize(env: *const Env, out: *mut u8, size: usize) -> isize {
+    let Some(env) = env.as_ref() else {
+        return EINVAL;
+    };
+    if out.is_null() {
+        return EINVAL;
+    }
+    match env.to_bytes(size) {
+        Ok(bytes) => {
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), out, size);
+            0
+        }
+        Err(e) => error_code(&e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(env: *const Env, key: &CStr, buf_len: usize) -> (isize, Vec<u8>) {
+        let mut buf = vec![0x55u8; buf_len];
+        let n = unsafe { uboot_env_get(env, key.as_ptr(), buf.as_mut_ptr().cast(), buf_len) };
+        (n, buf)
+    }
+
+    #[test]
+    fn round_trip() {
+        let env = uboot_env_new();
+        unsafe {
+            assert_eq!(uboot_env_set(env, c"bootdelay".as_ptr(), c"3".as_ptr()), 0);
+            assert_eq!(
+                uboot_env_set(env, c"bootcmd".as_ptr(), c"run x".as_ptr()),
+                0
+            );
+            let mut image = vec![0; 0x100];
+            assert_eq!(uboot_env_serialize(env, image.as_mut_ptr(), image.len()), 0);
+            uboot_env_free(env);
+
+            let mut parsed = std::ptr::null_mut();
+            assert_eq!(uboot_env_parse(image.as_ptr(), image.len(), &mut parsed), 0);
+            assert_eq!(
+                get(parsed, c"bootcmd", 16),
+                (5, b"run x\0".iter().chain(&[0x55; 10]).copied().collect())
+            );
+            assert_eq!(
+                uboot_env_set(parsed, c"bootcmd".as_ptr(), std::ptr::null()),
+                0
+            );
+            assert_eq!(get(parsed, c"bootcmd", 16).0, ENOENT);
+            uboot_env_free(parsed);
+        }
+    }
+
+    #[test]
+    fn get_truncates_like_snprintf() {
+        let env = uboot_env_new();
+        unsafe {
+            uboot_env_set(env, c"bootcmd".as_ptr(), c"run boot_flash".as_ptr());
+        }
+        assert_eq!(get(env, c"bootcmd", 4), (14, b"run\0".to_vec()));
+        assert_eq!(get(env, c"bootcmd", 1), (14, b"\0".to_vec()));
+        let n = unsafe { uboot_env_get(env, c"bootcmd".as_ptr(), std::ptr::null_mut(), 0) };
+        assert_eq!(n, 14);
+        unsafe { uboot_env_free(env) };
+    }
+
+    #[test]
+    fn errors() {
+        let env = uboot_env_new();
+        unsafe {
+            assert_eq!(get(env, c"missing", 8).0, ENOENT);
+            assert_eq!(
+                uboot_env_set(env, c"missing".as_ptr(), std::ptr::null()),
+                ENOENT
+            );
+            assert_eq!(uboot_env_set(env, c"a=b".as_ptr(), c"1".as_ptr()), EINVAL);
+            uboot_env_set(env, c"bootcmd".as_ptr(), c"run boot_flash".as_ptr());
+            let mut small = [0; 16];
+            assert_eq!(
+                uboot_env_serialize(env, small.as_mut_ptr(), small.len()),
+                EOVERFLOW
+            );
+
+            let mut image = vec![0; 0x100];
+            uboot_env_serialize(env, image.as_mut_ptr(), image.len());
+            image[0x10] ^= 1;
+            image[0x80 + 0x10] ^= 1;
+            let mut parsed = std::ptr::null_mut();
+            assert_eq!(
+                uboot_env_parse(image.as_ptr(), image.len(), &mut parsed),
+                ECRC
+            );
+            uboot_env_free(env);
+        }
+    }
+}
@@ -0,0 +1,48 @@
+//! reading and writing u-boot environments in their stored format.
+//!
+//! a redundant environment is two copies of the same data back to back, each
+//! a little-endian crc32, a flag byte (1 for the active copy, 0 for the
+//! obsolete one), and then nul-terminated key=value strings padded with
+//! zeroes to the end of the copy. the copies differ after an interrupted
+//! write, in which case the valid copy flagged active is the one read.
+//!
+//! ```no_run
+//! use uboot_patcher::Env;
+//!
+//! let region = std::fs::read("env.bin")?;
+//! let mut env = Env::parse(&region)?;
+//! env.set("bootdelay", "5")?;
+//! std::fs::write("env.bin", env.to_bytes(region.len())?)?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! the codec is the core feature and needs only core and alloc; std adds
+//! storage backends, and cli the uboot-patcher binary along with its
+//! dependencies. a library embedding the codec wants
+//! `default-features = false, features = ["std"]` (or just "core").
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "tokio")]
+pub mod async_io;
+#[cfg(feature = "core")]
+pub mod codec;
+#[cfg(feature = "core")]
+pub mod fdt;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "core")]
+pub mod partition;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "core")]
+pub mod sparse;
+#[cfg(feature = "std")]
+pub mod storage;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+#[cfg(feature = "core")]
+pub use codec::*;
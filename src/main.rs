@@ -1,129 +1,4741 @@
-use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::collections::BTreeMap;
+use std::io::{IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
 
 use anyhow::{anyhow, Result};
+use clap::{ArgAction, Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use log::{debug, info, warn};
+use serde_json::json;
+use uboot_patcher::fdt;
+use uboot_patcher::partition::{self, Partition};
+use uboot_patcher::sparse;
+use uboot_patcher::storage::{self, EnvSink, EnvSource};
+use uboot_patcher::{
+    validate_key, validate_value, Change, Crc, DuplicateKeys, Endian, EntryProblem, Env, EnvCopy,
+    EnvDiff, EnvError, EnvLayout, EnvWriter, FlagScheme, MergeStrategy, NandOob, RegionWrite,
+};
 
-fn redundant_env_bytes_to_hashmap(bytes: &[u8]) -> Result<HashMap<String, String>> {
-    let single_len = bytes.len() / 2;
+#[cfg(feature = "tui")]
+mod tui;
 
-    let crc_one = u32::from_le_bytes(bytes[0..4].try_into()?);
-    let crc_two = u32::from_le_bytes(bytes[single_len..single_len + 4].try_into()?);
-    let calc_crc_one = crc32fast::hash(&bytes[5..single_len]);
-    let calc_crc_two = crc32fast::hash(&bytes[single_len + 5..]);
+/// classes of failure with their own exit code, see --help-exit-codes
+#[derive(Clone, Copy, Debug)]
+enum Failure {
+    Usage = 2,
+    Io = 3,
+    Crc = 4,
+    Overflow = 5,
+    NotFound = 6,
+    Invalid = 7,
+    Aborted = 8,
+}
+
+/// an error message tagged with its failure class
+#[derive(Debug)]
+struct Failed(Failure, String);
+
+impl std::fmt::Display for Failed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.1)
+    }
+}
+
+impl std::error::Error for Failed {}
+
+/// like anyhow!, but tagged with the failure class that picks the exit code
+macro_rules! fail {
+    ($kind:ident, $($arg:tt)*) => {
+        anyhow::Error::new(Failed(Failure::$kind, format!($($arg)*)))
+    };
+}
+
+const EXIT_CODES: &str = "\
+exit codes:
+  0  success
+  1  any other error. also: grep matched nothing, verify found one valid copy
+  2  invalid command line. also: verify found no valid copy
+  3  i/o error reading or writing an image or file
+  4  crc mismatch in the environment
+  5  environment doesn't fit in its region
+  6  variable (or environment, for scan) not found
+  7  invalid input, such as malformed key=value text or a clashing name
+  8  write aborted at the confirmation prompt
+";
+
+/// a likely fix for an error, when there's an obvious one
+fn hint(e: &anyhow::Error) -> Option<&'static str> {
+    match e
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<EnvError>())?
+    {
+        EnvError::CrcMismatch {
+            stored, computed, ..
+        } if stored.swap_bytes() == *computed => {
+            Some("the crc matches with its bytes swapped, try the other --endian")
+        }
+        EnvError::InvalidEntry {
+            problem: EntryProblem::NotUtf8,
+            offset,
+            ..
+        } if *offset <= 5 => Some(
+            "the very first entry is garbage, which is what an encrypted \
+             environment looks like; if it's from CONFIG_ENV_AES, check --aes-key",
+        ),
+        EnvError::DuplicateKey { .. } => {
+            Some("--duplicates keep-first or keep-last reads it anyway")
+        }
+        _ => None,
+    }
+}
+
+/// the exit code for an error, from the first tagged error in its chain
+fn exit_code(e: &anyhow::Error) -> i32 {
+    for cause in e.chain() {
+        if let Some(Failed(kind, _)) = cause.downcast_ref::<Failed>() {
+            return *kind as i32;
+        }
+        if let Some(e) = cause.downcast_ref::<EnvError>() {
+            let kind = match e {
+                EnvError::CrcMismatch { .. } => Failure::Crc,
+                EnvError::Overflow { .. } => Failure::Overflow,
+                EnvError::InvalidEntry { .. }
+                | EnvError::TooSmall { .. }
+                | EnvError::InvalidVar { .. }
+                | EnvError::InvalidValue { .. }
+                | EnvError::Conflict { .. }
+                | EnvError::DuplicateKey { .. }
+                | EnvError::Encrypted => Failure::Invalid,
+                EnvError::Io(_) => Failure::Io,
+            };
+            return kind as i32;
+        }
+        if cause.is::<sparse::SparseError>() || cause.is::<fdt::FdtError>() {
+            return Failure::Invalid as i32;
+        }
+        if cause.is::<std::io::Error>() {
+            return Failure::Io as i32;
+        }
+    }
+    1
+}
+
+/// the crc and flag state of one copy as json
+fn copy_to_json(copy: &EnvCopy, layout: &EnvLayout) -> serde_json::Value {
+    let mut json = json!({
+        "stored_crc": copy.stored_crc,
+        "computed_crc": copy.calc_crc,
+        "valid": copy.is_valid(),
+        "flag": copy.flag,
+    });
+    if layout.flag_scheme == FlagScheme::Boolean {
+        json["active"] = copy.is_active().into();
+    }
+    json
+}
+
+/// a copy's crc and flag state, or its crc state alone when layout has no
+/// flag byte
+fn describe_copy(copy: &EnvCopy, layout: &EnvLayout) -> String {
+    match (layout.redundant, layout.flag_scheme) {
+        (false, _) => copy.describe_crc(),
+        (true, FlagScheme::Boolean) => copy.describe(),
+        (true, FlagScheme::Counter) => format!("{}, counter {}", copy.describe_crc(), copy.flag),
+    }
+}
+
+/// "-" in place of a filename means stdin or stdout
+fn is_stdio(filename: &Path) -> bool {
+    filename == Path::new("-")
+}
+
+/// everything on stdin. it's read once and kept, so the same image can be
+/// both parsed and then patched and written back out
+fn stdin_bytes() -> Result<&'static [u8]> {
+    static STDIN: OnceLock<Vec<u8>> = OnceLock::new();
+    if STDIN.get().is_none() {
+        let mut buf = Vec::new();
+        std::io::stdin().read_to_end(&mut buf)?;
+        let _ = STDIN.set(buf);
+    }
+    Ok(STDIN.get().unwrap())
+}
+
+/// a way of packing an image that reading undoes and writing redoes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Packing {
+    Gzip,
+    Xz,
+    Zstd,
+    /// android sparse, in blocks of this size
+    Sparse(usize),
+}
+
+impl Packing {
+    /// the packing bytes are in, by its magic
+    fn detect(bytes: &[u8]) -> Option<Packing> {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Some(Packing::Gzip)
+        } else if bytes.starts_with(b"\xfd7zXZ\0") {
+            Some(Packing::Xz)
+        } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Packing::Zstd)
+        } else {
+            sparse::block_size(bytes).map(Packing::Sparse)
+        }
+    }
+
+    fn unpack(self, bytes: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Packing::Gzip => {
+                let mut image = Vec::new();
+                flate2::read::MultiGzDecoder::new(bytes).read_to_end(&mut image)?;
+                Ok(image)
+            }
+            Packing::Xz => {
+                let mut image = Vec::new();
+                xz2::read::XzDecoder::new_multi_decoder(bytes).read_to_end(&mut image)?;
+                Ok(image)
+            }
+            Packing::Zstd => Ok(zstd::decode_all(bytes)?),
+            Packing::Sparse(_) => Ok(sparse::expand(bytes)?),
+        }
+    }
+
+    fn pack(self, image: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Packing::Gzip => {
+                let mut gz = flate2::write::GzEncoder::new(Vec::new(), Default::default());
+                gz.write_all(image)?;
+                Ok(gz.finish()?)
+            }
+            Packing::Xz => {
+                let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+                xz.write_all(image)?;
+                Ok(xz.finish()?)
+            }
+            // zstd's default level
+            Packing::Zstd => Ok(zstd::encode_all(image, 0)?),
+            Packing::Sparse(block) => Ok(sparse::encode(image, block)),
+        }
+    }
+}
+
+impl std::fmt::Display for Packing {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Packing::Gzip => "gzip",
+            Packing::Xz => "xz",
+            Packing::Zstd => "zstd",
+            Packing::Sparse(_) => "sparse",
+        })
+    }
+}
+
+/// an image file's contents unpacked, and the packings to redo in turn to
+/// write it back, such as a sparse image inside gzip
+#[derive(Clone)]
+struct PackedFile {
+    image: &'static [u8],
+    packings: Vec<Packing>,
+}
+
+/// what packed_file has found out about each file
+static PACKED_FILES: Mutex<BTreeMap<PathBuf, Option<PackedFile>>> = Mutex::new(BTreeMap::new());
+
+/// filename (or stdin) unpacked, if it's compressed or an android sparse
+/// image, and None if it's stored plainly. like stdin, each one is read
+/// once and kept
+fn packed_file(filename: &Path) -> Result<Option<PackedFile>> {
+    let mut cache = PACKED_FILES.lock().unwrap();
+    if let Some(known) = cache.get(filename) {
+        return Ok(known.clone());
+    }
+    let bytes = if is_stdio(filename) {
+        Some(stdin_bytes()?.to_vec())
+    } else {
+        // only read the whole of what looks packed, not a whole device;
+        // anything that can't be read is left for the caller to report
+        let mut head = Vec::new();
+        let packed = std::fs::File::open(filename)
+            .and_then(|f| f.take(28).read_to_end(&mut head))
+            .is_ok()
+            && Packing::detect(&head).is_some();
+        packed.then(|| std::fs::read(filename)).transpose()?
+    };
+    let mut found = None;
+    if let Some(mut bytes) = bytes {
+        let mut packings = Vec::new();
+        while let Some(packing) = Packing::detect(&bytes) {
+            bytes = packing.unpack(&bytes).map_err(|e| {
+                e.context(format!(
+                    "can't unpack {} as {}",
+                    filename.display(),
+                    packing
+                ))
+            })?;
+            debug!(
+                "unpacked {} from {} to {:#x} bytes",
+                filename.display(),
+                packing,
+                bytes.len()
+            );
+            packings.push(packing);
+        }
+        if !packings.is_empty() {
+            found = Some(PackedFile {
+                image: Vec::leak(bytes),
+                packings,
+            });
+        }
+    }
+    cache.insert(filename.to_path_buf(), found.clone());
+    Ok(found)
+}
+
+/// forget what packed_file read of filename once it's been written, so
+/// reading it back reads what's there now
+fn forget_packed(filename: &Path) {
+    PACKED_FILES.lock().unwrap().remove(filename);
+}
+
+/// the image filename stands for when it isn't simply a file to read and
+/// write: stdin, or a packed file's contents
+fn in_memory(filename: &Path) -> Result<Option<&'static [u8]>> {
+    if let Some(packed) = packed_file(filename)? {
+        return Ok(Some(packed.image));
+    }
+    if is_stdio(filename) {
+        return Ok(Some(stdin_bytes()?));
+    }
+    Ok(None)
+}
+
+/// the whole of an image, from a file or stdin
+fn read_image(filename: &Path) -> Result<Vec<u8>> {
+    if let Some(bytes) = in_memory(filename)? {
+        return Ok(bytes.to_vec());
+    }
+    Ok(std::fs::read(filename)?)
+}
+
+/// at most the first len bytes of an image, leaving the rest of a large
+/// dump or a whole disk unread
+fn read_image_head(filename: &Path, len: u64) -> Result<Vec<u8>> {
+    if let Some(bytes) = in_memory(filename)? {
+        return Ok(bytes[..bytes.len().min(len as usize)].to_vec());
+    }
+    let mut storage = EnvSource::Path(filename).open()?;
+    let len = storage.size()?.min(len) as usize;
+    Ok(storage.read_region(0, len)?)
+}
+
+/// the raw bytes of layout's region
+fn read_region(filename: &Path, layout: &EnvLayout) -> Result<Vec<u8>> {
+    for (offset, span) in layout.spans() {
+        debug!(
+            "reading {:#x} bytes at {:#x} from {}",
+            span.len(),
+            offset,
+            filename.display()
+        );
+    }
+    let source = match in_memory(filename)? {
+        Some(bytes) => EnvSource::Bytes(bytes),
+        None => EnvSource::Path(filename),
+    };
+    let mut storage = source.open()?;
+    Ok(progress().read_layout(&mut *storage, layout)?)
+}
+
+/// storage::Progress logging each step, which on slow devices shows
+/// where the time goes
+fn progress() -> storage::Progress<'static> {
+    storage::Progress::new(|event| debug!("{:?}", event))
+}
+
+/// a place in an image that looks like it holds a redundant environment
+struct Candidate {
+    offset: usize,
+    size: usize,
+    copies: [EnvCopy; 2],
+    layout: EnvLayout,
+}
+
+/// find every region of image whose first copy's stored crc matches its
+/// contents, trying offsets at multiples of step and power-of-two sizes
+/// between min_size and max_size. crcs are as template says
+fn scan_image(
+    image: &[u8],
+    step: usize,
+    min_size: usize,
+    max_size: usize,
+    template: &EnvLayout,
+) -> Vec<Candidate> {
+    let mut sizes = Vec::new();
+    let mut size = min_size.next_power_of_two();
+    while size <= max_size {
+        sizes.push(size);
+        size *= 2;
+    }
+
+    let mut found: Vec<Candidate> = Vec::new();
+    for offset in (0..image.len()).step_by(step.max(1)) {
+        // the second copy of a good environment looks like an environment
+        // of its own, don't report it twice
+        let inside_good = found
+            .iter()
+            .any(|c| c.copies.iter().all(|copy| copy.is_valid()) && offset < c.offset + c.size);
+        if inside_good {
+            continue;
+        }
+
+        for &size in &sizes {
+            let Some(region) = image.get(offset..offset + size) else {
+                break;
+            };
+            let layout = EnvLayout {
+                offset: offset as u64,
+                size,
+                ..*template
+            };
+            let Ok(Ok(copies)) = layout.copies(region).map(<[EnvCopy; 2]>::try_from) else {
+                continue;
+            };
+            if copies[0].is_valid() {
+                found.push(Candidate {
+                    offset,
+                    size,
+                    copies,
+                    layout,
+                });
+            }
+        }
+    }
+    found
+}
+
+fn read_file(filename: &Path, layout: &EnvLayout) -> Result<Env> {
+    parse_region(layout, &read_region(filename, layout)?)
+}
+
+/// bytes as text, with anything that isn't utf-8 as \x escapes
+fn escape_bytes(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for chunk in bytes.utf8_chunks() {
+        text.push_str(chunk.valid());
+        for b in chunk.invalid() {
+            text.push_str(&format!("\\x{:02x}", b));
+        }
+    }
+    text
+}
+
+/// every variable's name and value, shown with escape_bytes where the
+/// value isn't utf-8, sorted as printenv sorts them
+fn printable_vars(vars: &Env) -> Vec<(String, String)> {
+    let mut shown: Vec<_> = vars
+        .iter()
+        .map(|(key, val)| (key.to_string(), val.to_string()))
+        .chain(
+            vars.raw_entries()
+                .filter_map(|raw| Some((raw.key()?.to_string(), escape_bytes(raw.value()?)))),
+        )
+        .collect();
+    shown.sort();
+    shown
+}
+
+/// parse region, keeping any entries that aren't key=value as raw entries
+/// (with a warning for each) so one corrupted variable neither makes the
+/// environment unreadable nor gets lost when it's written back. nothing but
+/// raw entries is no environment at all, though, and fails as parse does
+fn parse_region(layout: &EnvLayout, region: &[u8]) -> Result<Env> {
+    let vars = layout.parse_preserving(region)?;
+    if vars.is_empty() && vars.raw_entries().next().is_some() {
+        return Ok(layout.parse(region)?);
+    }
+    for raw in vars.raw_entries() {
+        if let Some(key) = raw.key() {
+            info!("{}'s value isn't utf-8, kept as it is", key);
+            continue;
+        }
+        warn!(
+            "kept an entry that isn't a variable ({}) {}: {:?}",
+            raw.problem,
+            raw.after
+                .as_ref()
+                .map_or("at the start".to_string(), |key| format!("after {}", key)),
+            String::from_utf8_lossy(&raw.bytes)
+        );
+    }
+    Ok(vars)
+}
+
+/// make writes to layout's region
+fn write_region(filename: &Path, layout: &EnvLayout, writes: &[RegionWrite]) -> Result<()> {
+    for (offset, bytes) in writes.iter().flat_map(|write| write.placed(layout)) {
+        info!(
+            "writing {:#x} bytes at {:#x} to {}",
+            bytes.len(),
+            offset,
+            filename.display()
+        );
+    }
+    let mut storage = EnvSink::Path(filename).open()?;
+    progress().apply(&mut *storage, layout, writes)?;
+    info!("write complete");
+    Ok(())
+}
+
+/// parse key=value lines into an environment. blank lines and lines
+/// starting with # are skipped
+fn text_to_env(text: &str) -> Result<Env> {
+    let mut vars = Env::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, val) = line.split_once('=').ok_or_else(|| {
+            fail!(
+                Invalid,
+                "line {}: expected key=value, got {:?}",
+                i + 1,
+                line
+            )
+        })?;
+        vars.set(key, val)
+            .map_err(|e| fail!(Invalid, "line {}: {}", i + 1, e))?;
+    }
+    Ok(vars)
+}
+
+/// the inverse of text_to_env. lines are sorted by key so snapshots
+/// diff cleanly
+fn env_to_text(vars: &Env) -> Result<String> {
+    let mut sorted: Vec<_> = vars.iter().collect();
+    sorted.sort();
+
+    let mut text = String::new();
+    for (key, val) in sorted {
+        if val.contains('\n') {
+            return Err(fail!(
+                Invalid,
+                "{} contains a newline and can't be exported as text",
+                key
+            ));
+        }
+        text.push_str(&format!("{}={}\n", key, val));
+    }
+    Ok(text)
+}
+
+/// parse a uEnv.txt the way u-boot's `env import -t` does: leading blanks
+/// are skipped, # starts a comment line, and a backslash in a value keeps
+/// the character after it, so `\\` ends up as one backslash and a backslash
+/// at the end of a line continues the value onto the next. a bare name or an
+/// empty value leaves the variable unset, and windows line endings are
+/// dropped
+fn uenv_to_env(text: &str) -> Result<Env> {
+    let mut vars = Env::new();
+    let mut lines = text.split('\n').enumerate();
+    while let Some((i, line)) = lines.next() {
+        let line = line.trim_start_matches([' ', '\t']);
+        if line.starts_with('#') {
+            continue;
+        }
+        let Some((key, raw)) = line.split_once('=') else {
+            continue;
+        };
+        let mut raw = raw.to_string();
+        while raw.chars().rev().take_while(|&c| c == '\\').count() % 2 == 1 {
+            let Some((_, next)) = lines.next() else {
+                break;
+            };
+            raw.push('\n');
+            raw.push_str(next);
+        }
+        let raw = raw.strip_suffix('\r').unwrap_or(&raw);
+
+        let mut val = String::new();
+        let mut chars = raw.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => val.push(chars.next().unwrap_or('\\')),
+                c => val.push(c),
+            }
+        }
+        if val.is_empty() {
+            continue;
+        }
+        vars.set(key, &val)
+            .map_err(|e| fail!(Invalid, "line {}: {}", i + 1, e))?;
+    }
+    Ok(vars)
+}
+
+/// the inverse of uenv_to_env, in the environment's own order. newlines
+/// and backslashes in values are escaped, so any environment round-trips
+fn env_to_uenv(vars: &Env) -> String {
+    let mut text = String::new();
+    for (key, val) in vars.iter() {
+        text.push_str(key);
+        text.push('=');
+        for c in val.chars() {
+            if c == '\\' || c == '\n' {
+                text.push('\\');
+            }
+            text.push(c);
+        }
+        text.push('\n');
+    }
+    text
+}
+
+/// the variables in a capture of `printenv` from a serial console. anything
+/// up to the printenv command and from the "Environment size" line on is
+/// dropped, as are carriage returns. a line that doesn't start with name=
+/// continues the value before it: the terminal wrapped it if the line
+/// before was as long as the capture's longest lines (when at least two of
+/// them are, or they're 80 or 132 columns), otherwise the value had a newline
+fn printenv_to_env(text: &str) -> Result<Env> {
+    let starts_entry = |line: &str| {
+        line.split_once('=')
+            .is_some_and(|(key, _)| validate_key(key).is_ok())
+    };
+    let lines: Vec<&str> = text
+        .split('\n')
+        .map(|line| line.trim_end_matches('\r'))
+        .collect();
+    let start = lines
+        .iter()
+        .rposition(|line| {
+            let line = line.trim_end();
+            !starts_entry(line) && (line.ends_with("printenv") || line.ends_with("env print"))
+        })
+        .map_or(0, |i| i + 1);
+    let end = lines[start..]
+        .iter()
+        .position(|line| line.starts_with("Environment size:"))
+        .map_or(lines.len(), |i| start + i);
+    let mut body = &lines[start..end];
+    while let [rest @ .., last] = body {
+        if !last.trim().is_empty() {
+            break;
+        }
+        body = rest;
+    }
+
+    let longest = body
+        .iter()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0);
+    let at_longest = body
+        .iter()
+        .filter(|line| line.chars().count() == longest)
+        .count();
+    let wrap = (longest >= 40 && at_longest >= 2 || matches!(longest, 80 | 132)).then_some(longest);
+
+    let mut entries: Vec<(usize, String)> = Vec::new();
+    let mut wrapped = false;
+    for (i, line) in body.iter().enumerate() {
+        let line_no = start + i + 1;
+        match entries.last_mut() {
+            Some((_, val)) if wrapped => val.push_str(line),
+            Some((_, val)) if !starts_entry(line) => {
+                val.push('\n');
+                val.push_str(line);
+            }
+            None if !starts_entry(line) => {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                return Err(fail!(
+                    Invalid,
+                    "line {}: expected name=value, got {:?}",
+                    line_no,
+                    line
+                ));
+            }
+            _ => entries.push((line_no, line.to_string())),
+        }
+        wrapped = wrap == Some(line.chars().count());
+    }
+
+    let mut vars = Env::new();
+    for (line_no, entry) in entries {
+        let (key, val) = entry.split_once('=').unwrap();
+        vars.set(key, val)
+            .map_err(|e| fail!(Invalid, "line {}: {}", line_no, e))?;
+    }
+    if vars.is_empty() {
+        return Err(fail!(Invalid, "no printenv output found"));
+    }
+    Ok(vars)
+}
+
+/// the variables sorted and printed as they are, as printenv shows them
+fn env_to_printenv(vars: &Env) -> String {
+    let mut sorted: Vec<_> = vars.iter().collect();
+    sorted.sort();
+    let mut text = String::new();
+    for (key, val) in sorted {
+        text.push_str(key);
+        text.push('=');
+        text.push_str(val);
+        text.push('\n');
+    }
+    text
+}
+
+/// the bytes in a capture of u-boot's `md.b` output, `address: hex bytes
+/// ascii` lines, with anything else (the prompt, the command) skipped.
+/// fails if lines are missing in the middle or the dump is of wider units
+fn md_to_bytes(text: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut next: Option<u64> = None;
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        let Some((addr, rest)) = line.split_once(": ") else {
+            continue;
+        };
+        let Ok(addr) = u64::from_str_radix(addr.trim(), 16) else {
+            continue;
+        };
+        // the ascii column is past a gap of four spaces
+        let hex = rest.split("    ").next().unwrap_or("");
+        let tokens: Vec<&str> = hex.split_whitespace().collect();
+        if tokens.iter().any(|t| t.len() != 2) {
+            return Err(fail!(
+                Invalid,
+                "line {}: not md.b output (md.w and md.l show words in the board's byte order, dump with md.b)",
+                i + 1
+            ));
+        }
+        let Ok(line_bytes) = tokens
+            .iter()
+            .map(|t| u8::from_str_radix(t, 16))
+            .collect::<Result<Vec<u8>, _>>()
+        else {
+            continue;
+        };
+        if let Some(expected) = next.filter(|&n| n != addr) {
+            return Err(fail!(
+                Invalid,
+                "line {}: expected address {:08x}, got {:08x}; is part of the dump missing?",
+                i + 1,
+                expected,
+                addr
+            ));
+        }
+        next = Some(addr + line_bytes.len() as u64);
+        bytes.extend(line_bytes);
+    }
+    if bytes.is_empty() {
+        return Err(fail!(Invalid, "no md.b output found"));
+    }
+    Ok(bytes)
+}
+
+/// the environment in a dump of memory, going by template for the crc and
+/// the like. the dump can be the whole region, a single copy or one copy of
+/// a redundant pair (flag byte and all), and may run past its end; failing
+/// a crc match anywhere, the variables are read from the start of the dump
+/// or just past a header, as they are
+fn env_from_dump(dump: &[u8], template: &EnvLayout) -> Result<Env> {
+    let mut sizes = vec![dump.len()];
+    let mut size = dump.len().next_power_of_two() / 2;
+    while size >= 0x100 {
+        if size < dump.len() {
+            sizes.push(size);
+        }
+        size /= 2;
+    }
+    for &size in sizes.iter().filter(|&&size| size > 5) {
+        let region = &dump[..size];
+        let single = EnvLayout {
+            offset: 0,
+            size,
+            redundant_offset: None,
+            redundant: false,
+            ..*template
+        };
+        let redundant = EnvLayout {
+            redundant: true,
+            ..single
+        };
+        // one copy of a pair is a single copy once its flag byte is gone
+        let lone_copy: Vec<u8> = region[..4].iter().chain(&region[5..]).copied().collect();
+        let lone = EnvLayout {
+            size: size - 1,
+            ..single
+        };
+        let tries = [
+            (&single, region, "a single copy"),
+            (&redundant, region, "a redundant environment"),
+            (&lone, &lone_copy[..], "one copy of a redundant environment"),
+        ];
+        for (layout, bytes, what) in tries {
+            let valid = layout
+                .copies(bytes)
+                .is_ok_and(|copies| copies.iter().any(|c| c.is_valid()));
+            if valid {
+                info!("the dump holds {} of {:#x} bytes", what, size);
+                return Ok(layout.parse(bytes)?);
+            }
+        }
+    }
+
+    let starts_entry = |data: &[u8]| {
+        let end = data.iter().position(|&b| b == b'=' || b == 0);
+        end.filter(|&end| data[end] == b'=')
+            .and_then(|end| std::str::from_utf8(&data[..end]).ok())
+            .is_some_and(|key| validate_key(key).is_ok())
+    };
+    let skip = [0, 4, 5]
+        .into_iter()
+        .find(|&skip| dump.get(skip..).is_some_and(starts_entry))
+        .ok_or_else(|| fail!(Invalid, "the dump doesn't hold an environment"))?;
+    warn!(
+        "no crc in the dump matches, reading the variables from {:#x} on as they are",
+        skip
+    );
+    Ok(Env::parse_data(&dump[skip..])?)
+}
+
+/// value quoted for u-boot's hush shell, which takes everything between
+/// single quotes as it is
+fn hush_quote(val: &str) -> String {
+    format!("'{}'", val.replace('\'', "'\\''"))
+}
+
+/// u-boot commands making changes: setenv for what was added or changed,
+/// env delete for what was removed, then saveenv unless save is false
+fn changes_to_commands(changes: &EnvDiff, save: bool) -> Vec<String> {
+    let mut commands: Vec<String> = changes
+        .iter()
+        .map(|change| match change {
+            Change::Added(key, val) | Change::Changed(key, _, val) => {
+                format!("setenv {} {}", key, hush_quote(val))
+            }
+            Change::Removed(key, _) => format!("env delete {}", key),
+        })
+        .collect();
+    if save {
+        commands.push("saveenv".to_string());
+    }
+    commands
+}
+
+/// commands as text to paste at the prompt, warning about any the console
+/// won't take whole: a newline ends a command, and lines past u-boot's
+/// usual CONFIG_SYS_CBSIZE of 256 are cut short
+fn console_commands(commands: &[String]) -> String {
+    const CBSIZE: usize = 256;
+    let mut text = String::new();
+    for command in commands {
+        if command.contains('\n') {
+            warn!(
+                "{}... has a newline, which the console takes for the end of the command; use boot-script instead",
+                command.lines().next().unwrap_or_default()
+            );
+        } else if command.len() >= CBSIZE {
+            warn!(
+                "{:.40}... is {} characters, more than a console with the default CONFIG_SYS_CBSIZE takes",
+                command,
+                command.len()
+            );
+        }
+        text.push_str(command);
+        text.push('\n');
+    }
+    text
+}
+
+/// script in a legacy uimage as `mkimage -T script` makes it, for
+/// `source` at the u-boot prompt: the 64-byte header, a table of one
+/// length, then the script. time is as mkimage takes it, from
+/// SOURCE_DATE_EPOCH if that's set
+fn script_image(script: &str, name: &str, arch: ScriptArch) -> Vec<u8> {
+    const IH_MAGIC: u32 = 0x2705_1956;
+    const IH_OS_LINUX: u8 = 5;
+    const IH_TYPE_SCRIPT: u8 = 6;
+    const IH_COMP_NONE: u8 = 0;
+
+    let mut data = Vec::with_capacity(8 + script.len());
+    data.extend((script.len() as u32).to_be_bytes());
+    data.extend(0u32.to_be_bytes());
+    data.extend(script.as_bytes());
+
+    let time = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|t| t.parse().ok())
+        .unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs())
+        }) as u32;
 
-    if !(crc_one == crc_two && crc_one == calc_crc_one && crc_one == calc_crc_two) {
+    let mut header = Vec::with_capacity(64);
+    header.extend(IH_MAGIC.to_be_bytes());
+    header.extend(0u32.to_be_bytes()); // header crc, filled in below
+    header.extend(time.to_be_bytes());
+    header.extend((data.len() as u32).to_be_bytes());
+    header.extend(0u32.to_be_bytes()); // load address
+    header.extend(0u32.to_be_bytes()); // entry point
+    header.extend(Crc::CRC32.checksum(&data).to_be_bytes());
+    header.extend([IH_OS_LINUX, arch as u8, IH_TYPE_SCRIPT, IH_COMP_NONE]);
+    let mut name = name.as_bytes().to_vec();
+    name.resize(32, 0);
+    header.extend(name);
+    let hcrc = Crc::CRC32.checksum(&header);
+    header[4..8].copy_from_slice(&hcrc.to_be_bytes());
+
+    header.extend(data);
+    header
+}
+
+/// what mkenvimage makes of text: each line becomes an entry as it is,
+/// except that empty lines and lines starting with # are dropped and a
+/// backslash before a newline keeps the newline in the value. unlike
+/// text_to_env, nothing is checked, so the output matches mkenvimage's
+/// byte for byte
+fn mkenvimage(
+    text: &[u8],
+    size: usize,
+    redundant: bool,
+    endian: Endian,
+    padding: u8,
+) -> Result<Vec<u8>> {
+    let header = 4 + usize::from(redundant);
+    let too_large = || {
+        fail!(
+            Overflow,
+            "the environment file is too large for a {:#x} byte image",
+            size
+        )
+    };
+    let data_len = size.checked_sub(header).ok_or_else(too_large)?;
+
+    let mut data = Vec::with_capacity(data_len);
+    let mut i = 0;
+    while i < text.len() && data.len() < data_len {
+        let line_start = i == 0 || text[i - 1] == b'\n';
+        match text[i] {
+            b'\n' if line_start => {}
+            b'\n' if text[i - 1] == b'\\' => {
+                if let Some(last) = data.last_mut() {
+                    *last = b'\n';
+                }
+            }
+            b'\n' => data.push(0),
+            b'#' if line_start => {
+                while i + 1 < text.len() && text[i + 1] != b'\n' {
+                    i += 1;
+                }
+                // the comment's newline goes with it
+                i += 1;
+            }
+            byte => data.push(byte),
+        }
+        i += 1;
+    }
+    if i < text.len() {
+        return Err(too_large());
+    }
+    // a final nul for the last entry if the file didn't end in a newline,
+    // then one more to end the environment
+    if data.last() != Some(&0) {
+        data.push(0);
+    }
+    data.push(0);
+    if data.len() > data_len {
+        return Err(too_large());
+    }
+    data.resize(data_len, padding);
+
+    let crc = Crc::CRC32.checksum(&data);
+    let mut image = match endian {
+        Endian::Little => crc.to_le_bytes().to_vec(),
+        Endian::Big => crc.to_be_bytes().to_vec(),
+    };
+    if redundant {
+        image.push(1);
+    }
+    image.extend(data);
+    Ok(image)
+}
+
+/// text as u-boot's `env export -t` writes it: name=value lines sorted by
+/// name, ending in a nul so that `env import -t` finds the end without
+/// being given a size. a backslash or newline in a value gets a backslash
+/// before it, as hexport_r does, which is also how uEnv.txt escapes them
+fn env_to_export_text(vars: &Env) -> String {
+    let mut sorted: Vec<_> = vars.iter().collect();
+    sorted.sort();
+    let mut text = env_to_uenv(&Env::from_iter(sorted));
+    text.push('\0');
+    text
+}
+
+/// write vars to dest, or stdout for -, in text_format
+fn export(vars: &Env, text_format: TextFormat, dest: &Path) -> Result<()> {
+    let out = match text_format {
+        TextFormat::Text => env_to_text(vars)?.into_bytes(),
+        TextFormat::Uenv => env_to_uenv(vars).into_bytes(),
+        TextFormat::EnvExport => env_to_export_text(vars).into_bytes(),
+        TextFormat::Printenv => env_to_printenv(vars).into_bytes(),
+        TextFormat::Md => return Err(fail!(Usage, "md.b dumps can only be imported")),
+        TextFormat::EnvExportBinary => {
+            // sorted, as export -b leaves them
+            let mut sorted: Vec<_> = vars.iter().collect();
+            sorted.sort();
+            Env::from_iter(sorted).to_data()?
+        }
+    };
+    if let TextFormat::EnvExportBinary = text_format {
+        info!(
+            "{:#x} bytes, for env import -b $loadaddr {:#x} on the board",
+            out.len(),
+            out.len()
+        );
+    }
+    if is_stdio(dest) {
+        std::io::stdout().write_all(&out)?;
+    } else {
+        std::fs::write(dest, out)?;
+    }
+    Ok(())
+}
+
+/// find the default environment compiled into a u-boot binary, returning
+/// where it starts. default_environment is an array of nul-terminated
+/// key=value strings ended by an empty one, so this is the longest such
+/// run that sets bootcmd, bootdelay or baudrate; other strings in the
+/// binary don't come in runs like that. the data of a stored environment
+/// in a whole flash image looks the same, but has a crc in front
+fn find_default_env(image: &[u8]) -> Option<(usize, Env)> {
+    // a stored copy starts on a sector boundary somewhere in the chunk before
+    // the run, its crc and flag bytes having glued onto its first entry
+    let is_stored = |from: usize, to: usize| {
+        (from.next_multiple_of(0x100)..to).step_by(0x100).any(|at| {
+            (12..=18).any(|bits| {
+                [4, 5].into_iter().any(|header| {
+                    let Some(copy) = image.get(at..at + (1 << bits)) else {
+                        return false;
+                    };
+                    let stored = u32::from_le_bytes(copy[..4].try_into().unwrap());
+                    Crc::CRC32.checksum(&copy[header..]) == stored
+                })
+            })
+        })
+    };
+
+    let is_entry = |chunk: &[u8]| {
+        let Some((key, val)) = std::str::from_utf8(chunk)
+            .ok()
+            .and_then(|s| s.split_once('='))
+        else {
+            return false;
+        };
+        validate_key(key).is_ok()
+            && val
+                .chars()
+                .all(|c| !c.is_control() || c == '\t' || c == '\n')
+    };
+
+    let mut best: Option<(usize, Env)> = None;
+    // where the run started, and where the chunk before it did
+    let mut run: Option<(usize, usize)> = None;
+    let mut prev = 0;
+    let mut pos = 0;
+    for chunk in image.split(|&b| b == 0) {
+        let start = pos;
+        pos += chunk.len() + 1;
+        if !chunk.is_empty() {
+            if is_entry(chunk) {
+                run.get_or_insert((start, prev));
+            } else {
+                run = None;
+            }
+            prev = start;
+            continue;
+        }
+        prev = start;
+        // an empty entry ends the run
+        let Some((run_start, before)) = run.take() else {
+            continue;
+        };
+        let Ok(vars) = Env::parse_data(&image[run_start..]) else {
+            continue;
+        };
+        let known = ["bootcmd", "bootdelay", "baudrate"]
+            .iter()
+            .any(|key| vars.contains_key(key));
+        debug!(
+            "{} key=value strings at {:#x}{}",
+            vars.len(),
+            run_start,
+            if known { "" } else { ", none of them u-boot's" }
+        );
+        if known && is_stored(before.saturating_sub(5), run_start) {
+            debug!("that's a stored environment, not the default");
+            continue;
+        }
+        if known && best.as_ref().is_none_or(|(_, b)| vars.len() > b.len()) {
+            best = Some((run_start, vars));
+        }
+    }
+    best
+}
+
+/// the default environment compiled into the u-boot binary (or flash dump)
+/// at path, and where it was found
+fn read_defaults(path: &Path) -> Result<(usize, Env)> {
+    let image = read_image(path)?;
+    let (offset, vars) = find_default_env(&image).ok_or_else(|| {
+        fail!(
+            NotFound,
+            "no default environment found in {}",
+            path.display()
+        )
+    })?;
+    info!(
+        "default environment at {:#x}, {} variables",
+        offset,
+        vars.len()
+    );
+    Ok((offset, vars))
+}
+
+/// a new file holding text, only the user can read, in a directory of its
+/// own under the temp dir. environments hold passwords and keys, and both
+/// are created afresh so nothing planted in /tmp is followed
+fn private_temp_file(text: &str) -> Result<PathBuf> {
+    let mut dir = std::fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut dir, 0o700);
+    let mut attempt = 0;
+    let dir_path = loop {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |t| t.subsec_nanos());
+        let path = std::env::temp_dir().join(format!(
+            "uboot-patcher-{}-{:08x}",
+            std::process::id(),
+            nanos
+        ));
+        match dir.create(&path) {
+            Ok(()) => break path,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && attempt < 100 => {
+                attempt += 1
+            }
+            Err(e) => return Err(e.into()),
+        }
+    };
+    let path = dir_path.join("uboot.env.txt");
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(&path)?.write_all(text.as_bytes())?;
+    Ok(path)
+}
+
+/// remove a file from private_temp_file, and its directory
+fn remove_private_temp_file(path: &Path) -> Result<()> {
+    std::fs::remove_file(path)?;
+    if let Some(dir) = path.parent() {
+        std::fs::remove_dir(dir)?;
+    }
+    Ok(())
+}
+
+/// let the user edit text in $VISUAL/$EDITOR, returning the edited text.
+/// the temp file is kept (and its path returned alongside) so edits can be
+/// recovered if the caller rejects them
+fn edit_text(text: &str) -> Result<(String, PathBuf)> {
+    let path = private_temp_file(text)?;
+
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    // allow things like EDITOR="code --wait"
+    let mut parts = editor.split_whitespace();
+    let program = parts
+        .next()
+        .ok_or_else(|| fail!(Invalid, "$EDITOR is empty"))?;
+
+    let status = std::process::Command::new(program)
+        .args(parts)
+        .arg(&path)
+        .status()?;
+    if !status.success() {
         return Err(anyhow!(
-            "CRC Mismatch! stored: {:#x} {:#x} calc: {:#x} {:#x}",
-            crc_one,
-            crc_two,
-            calc_crc_one,
-            calc_crc_two
+            "{} exited with {}, edits left in {}",
+            program,
+            status,
+            path.display()
         ));
     }
 
-    Ok(HashMap::from_iter(
-        bytes[5..single_len]
-            // split data by null bytes
-            .split(|b| *b == 0u8)
-            .map(|sl| std::str::from_utf8(sl).unwrap())
-            // filter to strings longer than length 0
-            .filter(|s| s.len() > 0)
-            // split on =
-            .map(|line| line.split_once("=").unwrap())
-            .map(|(k, v)| (k.to_owned(), v.to_owned())),
-    ))
+    Ok((std::fs::read_to_string(&path)?, path))
+}
+
+/// set by --no-color
+static NO_COLOR: AtomicBool = AtomicBool::new(false);
+
+/// set by --yes, so the environment search doesn't ask either
+static YES: AtomicBool = AtomicBool::new(false);
+
+/// whether to use escape codes: only on a terminal, and never when asked not
+/// to by --no-color or the NO_COLOR environment variable
+fn color_enabled() -> bool {
+    std::io::stdout().is_terminal()
+        && !NO_COLOR.load(Ordering::Relaxed)
+        && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
 }
 
-fn hashmap_to_redundant_env_bytes(hm: HashMap<String, String>, len: usize) -> Result<Vec<u8>> {
-    // max space for one 'data' portion
-    // half the length (redundant halves), minus u32 crc, minus u8 flag
-    let max_data_len = (len / 2) - 5;
-    let mut data_bytes: Vec<u8> = Vec::with_capacity(max_data_len);
+/// wrap every match of re in text with bold red escape codes
+fn highlight(re: &regex::Regex, text: &str) -> String {
+    re.replace_all(text, "\x1b[1;31m$0\x1b[0m").into_owned()
+}
 
-    // convert to key=val c strings
-    for (key, val) in hm {
-        data_bytes.extend(key.bytes());
-        data_bytes.extend("=".bytes());
-        data_bytes.extend(val.bytes());
-        data_bytes.push(0);
+/// changes as a json object of added, removed, and changed variables
+fn diff_to_json(changes: &EnvDiff) -> serde_json::Value {
+    let mut added = BTreeMap::new();
+    let mut removed = BTreeMap::new();
+    let mut changed = BTreeMap::new();
+    for change in changes {
+        match *change {
+            Change::Added(key, val) => {
+                added.insert(key, val);
+            }
+            Change::Removed(key, val) => {
+                removed.insert(key, val);
+            }
+            Change::Changed(key, o, n) => {
+                changed.insert(key, json!({ "old": o, "new": n }));
+            }
+        }
     }
+    json!({ "added": added, "removed": removed, "changed": changed })
+}
 
-    let usage = data_bytes.len();
+/// print changes in diff style, old values prefixed with - and new with +
+fn print_diff(changes: &EnvDiff, old_label: &str, new_label: &str) {
+    let paint = |code: &str, text: String| {
+        if color_enabled() {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text
+        }
+    };
+    const BOLD: &str = "1";
+    const RED: &str = "31";
+    const GREEN: &str = "32";
+    const CHANGED_KEY: &str = "1;33";
 
-    if usage > max_data_len {
-        return Err(anyhow!(
-            "not enough space for environment ({} > {})",
-            data_bytes.len(),
-            max_data_len
+    println!("{}", paint(BOLD, format!("--- {}", old_label)));
+    println!("{}", paint(BOLD, format!("+++ {}", new_label)));
+    for change in changes {
+        match change {
+            Change::Added(key, val) => println!("{}", paint(GREEN, format!("+{}={}", key, val))),
+            Change::Removed(key, val) => println!("{}", paint(RED, format!("-{}={}", key, val))),
+            Change::Changed(key, o, n) => {
+                let key = paint(CHANGED_KEY, key.to_string());
+                println!(
+                    "{}{}{}",
+                    paint(RED, "-".into()),
+                    key,
+                    paint(RED, format!("={}", o))
+                );
+                println!(
+                    "{}{}{}",
+                    paint(GREEN, "+".into()),
+                    key,
+                    paint(GREEN, format!("={}", n))
+                );
+            }
+        }
+    }
+}
+
+/// parse a number given in decimal or as 0x-prefixed hex, optionally with a
+/// K, M, or G (binary) suffix, e.g. 0x20000, 131072, 128K
+fn parse_number(s: &str) -> Result<usize> {
+    let (digits, multiplier) = match s.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&s[..i], 1 << 10),
+        Some((i, 'm' | 'M')) => (&s[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+
+    let n = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => usize::from_str_radix(hex, 16)?,
+        None => digits.parse()?,
+    };
+    n.checked_mul(multiplier)
+        .ok_or_else(|| anyhow!("{} is too large", s))
+}
+
+/// an --offset as given: from the start of the image, or back from its end
+#[derive(Clone, Copy)]
+enum Offset {
+    Start(usize),
+    End(usize),
+}
+
+impl std::fmt::Display for Offset {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Offset::Start(offset) => write!(f, "{:#x}", offset),
+            Offset::End(back) => write!(f, "-{:#x}", back),
+        }
+    }
+}
+
+impl Offset {
+    /// the offset from the start of file's image
+    fn in_image(self, file: &Path) -> Result<usize> {
+        let back = match self {
+            Offset::Start(offset) => return Ok(offset),
+            Offset::End(back) => back,
+        };
+        let len = image_len(file)?;
+        let offset = len.checked_sub(back as u64).ok_or_else(|| {
+            fail!(
+                Usage,
+                "offset -{:#x} is before the start of {} ({:#x} bytes)",
+                back,
+                file.display(),
+                len
+            )
+        })?;
+        debug!("offset -{:#x} is {:#x} in {}", back, offset, file.display());
+        Ok(offset as usize)
+    }
+}
+
+/// the partition of file's disk that goes by id, its gpt name, PARTUUID or
+/// number
+fn find_partition(file: &Path, id: &str) -> Result<Partition> {
+    let source = match in_memory(file)? {
+        Some(bytes) => EnvSource::Bytes(bytes),
+        None => EnvSource::Path(file),
+    };
+    let mut storage = source.open()?;
+    // enough for any gpt's entries
+    let len = storage.size()?.min(1 << 20) as usize;
+    let head = storage.read_region(0, len)?;
+    let parts = partition::partitions(&head)
+        .ok_or_else(|| fail!(NotFound, "{} has no partition table", file.display()))?;
+    let Some(part) = parts.iter().find(|part| part.is(id)) else {
+        let names: Vec<String> = parts
+            .iter()
+            .map(|part| match part.name.as_str() {
+                "" => format!("{} ({})", part.number, part.uuid),
+                name => format!("{} {}", part.number, name),
+            })
+            .collect();
+        return Err(fail!(
+            NotFound,
+            "{} has no partition {:?}, only {}",
+            file.display(),
+            id,
+            names.join(", ")
         ));
+    };
+    debug!(
+        "partition {} ({}) is {:#x} bytes at {:#x}",
+        part.number, part.uuid, part.size, part.offset
+    );
+    Ok(part.clone())
+}
+
+/// parse a number as parse_number does, or a negative one for an offset
+/// from the end
+fn parse_offset(s: &str) -> Result<Offset> {
+    Ok(match s.strip_prefix('-') {
+        Some(back) => Offset::End(parse_number(back)?),
+        None => Offset::Start(parse_number(s)?),
+    })
+}
+
+/// the size of the image filename holds: what's in memory for stdin and
+/// packed files, and the device's own size for devices
+fn image_len(filename: &Path) -> Result<u64> {
+    if let Some(bytes) = in_memory(filename)? {
+        return Ok(bytes.len() as u64);
+    }
+    Ok(EnvSource::Path(filename).open()?.size()?)
+}
+
+/// parse a number that fits in a byte
+fn parse_byte(s: &str) -> Result<u8> {
+    u8::try_from(parse_number(s)?).map_err(|_| anyhow!("{} doesn't fit in a byte", s))
+}
+
+/// parse a 128-bit aes key written as 32 hex digits, optionally with 0x
+fn parse_aes_key(s: &str) -> Result<[u8; 16]> {
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    if digits.len() != 32 || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(anyhow!("an aes key is 32 hex digits"));
+    }
+    let mut key = [0; 16];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16)?;
+    }
+    Ok(key)
+}
+
+/// bytes written as hex digits, optionally split up by colons or spaces
+/// the way macs and dumps are
+fn parse_hex(s: &str) -> Result<Vec<u8>> {
+    let digits: String = s.chars().filter(|c| !matches!(c, ':' | ' ')).collect();
+    if !digits.len().is_multiple_of(2) || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(anyhow!("{:?} isn't hex bytes", s));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| Ok(u8::from_str_radix(&digits[i..i + 2], 16)?))
+        .collect()
+}
+
+/// parse a --crc argument, a name from Crc::NAMED or changes to crc32
+fn parse_crc(s: &str) -> Result<Crc> {
+    if let Some(crc) = Crc::named(s) {
+        return Ok(crc);
+    }
+    let mut crc = Crc::CRC32;
+    for param in s.split(',') {
+        let value = |v: &str| -> Result<u32> {
+            u32::try_from(parse_number(v)?).map_err(|_| anyhow!("{} is more than 32 bits", v))
+        };
+        match param.split_once('=') {
+            Some(("poly", v)) => crc.poly = value(v)?,
+            Some(("init", v)) => crc.init = value(v)?,
+            Some(("xorout", v)) => crc.xorout = value(v)?,
+            Some(("reflected", "yes")) => crc.reflected = true,
+            Some(("reflected", "no")) => crc.reflected = false,
+            _ => {
+                let names: Vec<_> = Crc::NAMED.iter().map(|(name, _)| *name).collect();
+                return Err(anyhow!(
+                    "expected one of {} or poly=, init=, xorout= and reflected=, got {:?}",
+                    names.join(", "),
+                    param
+                ));
+            }
+        }
     }
+    Ok(crc)
+}
+
+/// parse a KEY=VALUE argument
+fn parse_assignment(s: &str) -> Result<(String, String)> {
+    let (key, val) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("expected KEY=VALUE, got {:?}", s))?;
+    Ok((key.to_owned(), val.to_owned()))
+}
 
-    // pad to length
-    data_bytes.extend(vec![0; max_data_len - usage]);
+/// a single modification to an environment
+#[derive(Clone)]
+enum Op {
+    Set(String, String),
+    /// add to the end of a value, setting it if it doesn't exist
+    Append(String, String),
+    /// add to the start of a value, setting it if it doesn't exist
+    Prepend(String, String),
+    Delete(String),
+    Rename {
+        from: String,
+        to: String,
+        overwrite: bool,
+    },
+}
 
-    let crc = crc32fast::hash(&data_bytes);
+/// parse a script of operations, one per line:
+///
+///     set KEY=VALUE
+///     append KEY=VALUE
+///     prepend KEY=VALUE
+///     delete KEY
+///     rename [--overwrite] OLD NEW
+///
+/// blank lines and lines starting with # are skipped
+fn parse_script(text: &str) -> Result<Vec<Op>> {
+    let mut ops = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim_start();
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
 
-    let mut total_vec: Vec<u8> = Vec::with_capacity(len);
+        let (cmd, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let args: Vec<&str> = rest.split_whitespace().collect();
+        // take the rest of the line verbatim so values can hold spaces
+        let assignment = |op: fn(String, String) -> Op| {
+            parse_assignment(rest.trim_start())
+                .map(|(k, v)| op(k, v))
+                .ok()
+        };
+        let op = match (cmd, args.as_slice()) {
+            ("set", _) => assignment(Op::Set),
+            ("append", _) => assignment(Op::Append),
+            ("prepend", _) => assignment(Op::Prepend),
+            ("delete", [key]) => Some(Op::Delete(key.to_string())),
+            ("rename", [from, to]) | ("rename", ["--overwrite", from, to]) => Some(Op::Rename {
+                from: from.to_string(),
+                to: to.to_string(),
+                overwrite: args.len() == 3,
+            }),
+            _ => None,
+        };
+        ops.push(op.ok_or_else(|| fail!(Invalid, "line {}: can't parse {:?}", i + 1, line))?);
+    }
+    Ok(ops)
+}
 
-    // first half
-    total_vec.extend(u32::to_le_bytes(crc));
-    total_vec.push(0b1); // flag as active
-    total_vec.extend(&data_bytes);
-    // second half
-    total_vec.extend(u32::to_le_bytes(crc));
-    total_vec.push(0b0); // flag as backup
-    total_vec.extend(&data_bytes);
+/// what set would say about key and val, for edits that go through entry
+fn check_var(key: &str, val: &str) -> Result<(), EnvError> {
+    validate_key(key)
+        .and_then(|_| validate_value(val))
+        .map_err(|problem| EnvError::InvalidVar {
+            key: key.to_owned(),
+            problem,
+        })
+}
 
-    if total_vec.len() != len {
-        panic!("environment is the wrong size!");
+/// apply ops to vars in order. deleting a missing variable is an error unless
+/// ignore_missing is set, in which case it's only a warning
+fn apply_ops(vars: &mut Env, ops: Vec<Op>, ignore_missing: bool) -> Result<()> {
+    for op in ops {
+        match op {
+            Op::Set(key, val) => {
+                vars.set(key, val)?;
+            }
+            // through bytes, so that a value that isn't utf-8 is kept
+            Op::Append(key, val) => {
+                check_var(&key, &val)?;
+                let mut bytes = vars.get_bytes(&key).unwrap_or_default().to_vec();
+                bytes.extend_from_slice(val.as_bytes());
+                vars.set_bytes(key, &bytes)?;
+            }
+            Op::Prepend(key, val) => {
+                check_var(&key, &val)?;
+                let mut bytes = val.into_bytes();
+                bytes.extend_from_slice(vars.get_bytes(&key).unwrap_or_default());
+                vars.set_bytes(key, &bytes)?;
+            }
+            Op::Delete(key) => {
+                if vars.remove(&key).is_none() {
+                    if !ignore_missing {
+                        return Err(fail!(NotFound, "no such variable: {}", key));
+                    }
+                    warn!("no such variable: {}", key);
+                }
+            }
+            Op::Rename {
+                from,
+                to,
+                overwrite,
+            } => {
+                if !overwrite && vars.contains_key(&to) {
+                    return Err(fail!(
+                        Invalid,
+                        "{} already exists, use --overwrite to replace it",
+                        to
+                    ));
+                }
+                if !vars.rename(&from, to)? {
+                    return Err(fail!(NotFound, "no such variable: {}", from));
+                }
+            }
+        }
     }
+    Ok(())
+}
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "read, modify, and write u-boot environments in their stored format"
+)]
+struct Cli {
+    /// show more detail on stderr: -v for crcs and progress, -vv for debugging
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+    /// only print errors on stderr
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// print results of read-only commands as json
+    #[arg(long, global = true)]
+    json: bool,
+    /// never use colored output. setting NO_COLOR does the same
+    #[arg(long, global = true)]
+    no_color: bool,
+    /// list the exit codes and what they mean
+    #[arg(long)]
+    help_exit_codes: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+fn print_json(value: &serde_json::Value) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// where to find the environment
+#[derive(Args)]
+struct EnvArgs {
+    /// image file containing the environment, or - for stdin
+    #[arg(short, long, required_unless_present_any = ["config", "dtb"])]
+    file: Option<PathBuf>,
+    /// take the device, offset, size and redundancy from an fw_env.config
+    /// such as /etc/fw_env.config, the way fw_printenv does. --file still
+    /// picks another image to read with the same layout
+    #[arg(long, conflicts_with_all = ["offset", "size", "redundant_offset", "format"])]
+    config: Option<PathBuf>,
+    /// take the offset, size and redundancy from a device tree instead: a
+    /// .dtb, or /proc/device-tree on the target. offsets are into the whole
+    /// flash or disk; without --file, the env partition's own mtd device
+    /// is found by its label in /proc/mtd
+    #[arg(
+        long,
+        value_name = "DTB",
+        conflicts_with_all = ["config", "offset", "offset_sectors", "partition", "size", "redundant_offset", "format", "env_index"]
+    )]
+    dtb: Option<PathBuf>,
+    #[command(flatten)]
+    layout: LayoutArgs,
+}
+
+impl EnvArgs {
+    /// the image. resolve must have filled it in from --config or --dtb if
+    /// it isn't given
+    fn file(&self) -> &Path {
+        self.file
+            .as_deref()
+            .expect("file used before it was resolved")
+    }
+
+    /// work out the layout, from --config or --dtb if given, then from the
+    /// file's size if it's still incomplete
+    fn resolve(&mut self) -> Result<()> {
+        if let Some(dtb) = self.dtb.clone() {
+            self.resolve_dtb(&dtb)?;
+        }
+        if let Some(config) = &self.config {
+            let mut lines = read_fw_env_config(config)?.into_iter();
+            let first = lines.next().expect("read_fw_env_config returns a line");
+            match lines.next() {
+                Some(second) if second.device != first.device => {
+                    return Err(fail!(
+                        Invalid,
+                        "{}: copies on two devices ({} and {}) aren't supported",
+                        config.display(),
+                        first.device.display(),
+                        second.device.display()
+                    ));
+                }
+                Some(second) if second.size != first.size => {
+                    return Err(fail!(
+                        Invalid,
+                        "{}: the two copies are different sizes ({:#x} and {:#x})",
+                        config.display(),
+                        first.size,
+                        second.size
+                    ));
+                }
+                Some(second) => {
+                    self.layout.redundant_offset = Some(second.offset.in_image(&second.device)?);
+                    self.layout.format = Some(Format::Redundant);
+                }
+                // like fw_printenv, one device line means a single copy
+                None => self.layout.format = Some(Format::Single),
+            }
+            self.layout.offset = Some(first.offset.in_image(&first.device)?);
+            self.layout.size = Some(first.size);
+            if self.layout.sector_size.is_none() {
+                self.layout.sector_size = first.sector_size;
+            }
+            self.file.get_or_insert(first.device);
+        }
+        let file = self.file().to_path_buf();
+        self.layout.resolve(&file)
+    }
+
+    /// fill in the layout from where the device tree at dtb says the
+    /// environment is
+    fn resolve_dtb(&mut self, dtb: &Path) -> Result<()> {
+        let tree = read_device_tree(dtb)?;
+        let env = fdt::find_env(&tree).ok_or_else(|| {
+            fail!(
+                NotFound,
+                "{} has no u-boot,env partition or u-boot,mmc-env-offset",
+                dtb.display()
+            )
+        })?;
+        debug!("{} puts the environment at {:?}", dtb.display(), env);
+        let (mut offset, mut redundant_offset) = (env.offset, env.redundant_offset);
+        if self.file.is_none() {
+            let label = env.label.as_deref().ok_or_else(|| {
+                fail!(
+                    Usage,
+                    "{} doesn't name the environment's partition; give --file",
+                    dtb.display()
+                )
+            })?;
+            if redundant_offset.is_some() {
+                return Err(fail!(
+                    Invalid,
+                    "{}: copies in two partitions need the whole flash; give --file",
+                    dtb.display()
+                ));
+            }
+            self.file = Some(mtd_by_name(label)?);
+            offset = 0;
+        }
+        let file = self.file().to_path_buf();
+
+        // u-boot's /config offsets count from the start of an mmc partition
+        // when it names one, or back from the end if negative
+        let base = match &env.partition {
+            Some(id) => Some(find_partition(&file, id)?),
+            None => None,
+        };
+        let place = |offset: i64| -> Result<usize> {
+            let back = offset.unsigned_abs() as usize;
+            Ok(match (&base, offset < 0) {
+                (Some(part), false) => part.offset as usize + back,
+                (Some(part), true) => (part.offset + part.size)
+                    .checked_sub(back as u64)
+                    .filter(|&offset| offset >= part.offset)
+                    .ok_or_else(|| {
+                        fail!(
+                            Invalid,
+                            "{}: offset -{:#x} is before the start of partition {}",
+                            dtb.display(),
+                            back,
+                            part.number
+                        )
+                    })? as usize,
+                (None, false) => back,
+                (None, true) => Offset::End(back).in_image(&file)?,
+            })
+        };
+        self.layout.offset = Some(place(offset)?);
+        if let Some(second) = redundant_offset.take() {
+            self.layout.redundant_offset = Some(place(second)?);
+        }
+        self.layout.size = env.size.map(|size| size as usize);
+        self.layout.format = match env.redundant {
+            Some(true) => Some(Format::Redundant),
+            Some(false) => Some(Format::Single),
+            None => None,
+        };
+        if env.counter {
+            self.layout.flags = Flags::Counter;
+        }
+        Ok(())
+    }
+}
+
+/// the device tree at path: a .dtb, or one unpacked into directories as
+/// /proc/device-tree is, nodes being directories and properties files
+fn read_device_tree(path: &Path) -> Result<fdt::Node> {
+    fn read_dir(dir: &Path, name: String) -> std::io::Result<fdt::Node> {
+        let mut node = fdt::Node {
+            name,
+            ..fdt::Node::default()
+        };
+        let mut entries = std::fs::read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if entry.file_type()?.is_dir() {
+                node.children.push(read_dir(&entry.path(), name)?);
+            } else {
+                node.properties.push((name, std::fs::read(entry.path())?));
+            }
+        }
+        Ok(node)
+    }
+
+    let context = |e| anyhow::Error::new(e).context(format!("can't read {}", path.display()));
+    if path.is_dir() {
+        return read_dir(path, String::new()).map_err(context);
+    }
+    let dtb = std::fs::read(path).map_err(context)?;
+    fdt::parse(&dtb).map_err(|e| anyhow::Error::new(e).context(path.display().to_string()))
+}
 
-    Ok(total_vec)
+/// the mtd device /proc/mtd lists under name, a partition's label
+fn mtd_by_name(name: &str) -> Result<PathBuf> {
+    let mtd = std::fs::read_to_string("/proc/mtd")
+        .map_err(|e| anyhow::Error::new(e).context("can't read /proc/mtd; give --file"))?;
+    // mtd3: 00020000 00010000 "u-boot-env"
+    mtd.lines()
+        .find_map(|line| {
+            let (device, rest) = line.split_once(':')?;
+            let label = rest.trim().splitn(3, ' ').nth(2)?;
+            (label.trim_matches('"') == name).then(|| PathBuf::from(format!("/dev/{}", device)))
+        })
+        .ok_or_else(|| fail!(NotFound, "no mtd device is called {:?}; give --file", name))
 }
 
-fn read_file(filename: &str, offset: usize, len: usize) -> Result<HashMap<String, String>> {
-    let mut f = File::open(filename)?;
-    let mut buf = vec![0; len];
-    f.seek(SeekFrom::Start(offset as u64))?;
-    f.read(&mut buf)?;
-    redundant_env_bytes_to_hashmap(&buf)
+/// one line of an fw_env.config: where a copy of the environment is
+struct ConfigLine {
+    device: PathBuf,
+    offset: Offset,
+    size: usize,
+    sector_size: Option<usize>,
 }
 
-fn patch_file(
-    hm: HashMap<String, String>,
-    filename: &str,
+/// the one or two copies in an fw_env.config. each line is a device, the
+/// offset (negative from the end) and size of the copy in it, and then its sector size (0 or left
+/// out for the device's own) and count, which isn't needed
+fn read_fw_env_config(path: &Path) -> Result<Vec<ConfigLine>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::Error::new(e).context(format!("can't read {}", path.display())))?;
+    let mut lines = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let mut fields = line.split_whitespace();
+        let Some(device) = fields.next().filter(|device| !device.starts_with('#')) else {
+            continue;
+        };
+        let number = |field: Option<&str>, what| {
+            let field = field
+                .ok_or_else(|| fail!(Invalid, "{} line {}: no {}", path.display(), i + 1, what))?;
+            parse_number(field).map_err(|e| {
+                fail!(
+                    Invalid,
+                    "{} line {}: bad {} {:?}: {}",
+                    path.display(),
+                    i + 1,
+                    what,
+                    field,
+                    e
+                )
+            })
+        };
+        let offset = match fields.next() {
+            Some(field) => match field.strip_prefix('-') {
+                Some(back) => Offset::End(number(Some(back), "offset")?),
+                None => Offset::Start(number(Some(field), "offset")?),
+            },
+            None => number(None, "offset").map(Offset::Start)?,
+        };
+        let size = number(fields.next(), "size")?;
+        let sector_size = match fields.next() {
+            Some(field) => Some(number(Some(field), "sector size")?).filter(|&size| size > 0),
+            None => None,
+        };
+        lines.push(ConfigLine {
+            device: device.into(),
+            offset,
+            size,
+            sector_size,
+        });
+    }
+    match lines.len() {
+        0 => Err(fail!(Invalid, "{} names no devices", path.display())),
+        1 | 2 => Ok(lines),
+        _ => Err(fail!(
+            Invalid,
+            "{} names more than two copies",
+            path.display()
+        )),
+    }
+}
+
+/// the options fw_printenv and fw_setenv share
+#[derive(Args)]
+struct FwArgs {
+    /// fw_env.config saying where the environment is
+    #[arg(short, long, default_value = "/etc/fw_env.config")]
+    config: PathBuf,
+    /// fw_env's lock directory. nothing is locked, it's accepted so
+    /// existing invocations keep working
+    #[arg(short, long, hide = true)]
+    lock: Option<PathBuf>,
+    /// aes key of an encrypted environment, as 32 hex digits
+    #[arg(short, long = "aes", value_parser = parse_aes_key, value_name = "HEX")]
+    aes_key: Option<[u8; 16]>,
+    /// how a redundant environment's flag bytes mark the current copy
+    /// [default: counter on nand, as fw_env does, otherwise boolean]
+    #[arg(long, value_enum)]
+    flags: Option<Flags>,
+}
+
+/// the flags fw_env uses on a device: those given, or a counter on nand
+/// and 1 and 0 anywhere else
+fn fw_flags(given: Option<Flags>, nand: bool) -> Flags {
+    match (given, nand) {
+        (Some(flags), _) => flags,
+        (None, true) => Flags::Counter,
+        (None, false) => Flags::Boolean,
+    }
+}
+
+impl FwArgs {
+    /// the environment config describes, resolved
+    fn env_args(self) -> Result<EnvArgs> {
+        let mut env = EnvArgs {
+            file: None,
+            config: Some(self.config),
+            dtb: None,
+            layout: LayoutArgs {
+                offset_arg: None,
+                offset_sectors: None,
+                partition: None,
+                offset: None,
+                size: None,
+                redundant_offset: None,
+                format: None,
+                flags: Flags::Boolean,
+                endian: ByteOrder::Little,
+                crc: Crc::CRC32,
+                padding: 0,
+                aes_key: self.aes_key,
+                duplicates: Duplicates::KeepLast,
+                env_index: None,
+                page_size: None,
+                oob_size: None,
+                sector_size: None,
+            },
+        };
+        env.resolve()?;
+        env.layout.flags = fw_flags(self.flags, storage::is_nand(env.file()));
+        Ok(env)
+    }
+}
+
+/// parse an fw_setenv script: `name value` lines, where the value is the
+/// rest of the line and a name alone means delete it. blank lines and
+/// lines starting with # are skipped
+fn parse_fw_script(text: &str) -> Vec<(String, Option<String>)> {
+    text.lines()
+        .map(|line| line.trim_start_matches([' ', '\t']))
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| match line.split_once([' ', '\t']) {
+            Some((name, val)) => {
+                let val = val.trim_start_matches([' ', '\t']);
+                (
+                    name.to_string(),
+                    Some(val).filter(|val| !val.is_empty()).map(str::to_string),
+                )
+            }
+            None => (line.to_string(), None),
+        })
+        .collect()
+}
+
+/// where the environment sits within an image
+#[derive(Args, Clone)]
+struct LayoutArgs {
+    /// byte offset of the environment within the image (decimal, 0x hex, K/M
+    /// suffixes), or back from the end of it if negative, as in -0x10000.
+    /// leave it and --size out for a uboot.env file; left out otherwise,
+    /// the image is searched for the environment
+    #[arg(
+        id = "offset",
+        long = "offset",
+        value_name = "OFFSET",
+        value_parser = parse_offset,
+        allow_hyphen_values = true
+    )]
+    offset_arg: Option<Offset>,
+    /// the offset in 512-byte sectors instead, as partition tables and
+    /// dd's seek= count them
+    #[arg(long, value_name = "SECTORS", value_parser = parse_number, conflicts_with = "offset")]
+    offset_sectors: Option<usize>,
+    /// the partition holding the environment, for a whole disk image or
+    /// device: its gpt name, PARTUUID or number. --offset and
+    /// --offset-sectors then count from its start, or back from its end,
+    /// and leaving them out means its start
+    #[arg(long, value_name = "NAME")]
+    partition: Option<String>,
+    /// --offset counted from the start, once resolve has worked it out
+    #[arg(skip)]
+    offset: Option<usize>,
+    /// total size of the environment region, both copies included when
+    /// it's redundant. with --redundant-offset, the size of each copy.
+    /// left out, it's found by trying sizes from 4K to 256K against the crc
+    #[arg(long, value_parser = parse_number)]
+    size: Option<usize>,
+    /// where the second copy is, when it isn't straight after the first,
+    /// such as a u-boot-env2 partition of its own
+    #[arg(long, value_parser = parse_number, conflicts_with = "format")]
+    redundant_offset: Option<usize>,
+    /// how the region is laid out [default: redundant, or file for a file
+    /// that looks like a uboot.env]
+    #[arg(
+        long = "layout",
+        visible_alias = "format",
+        value_name = "LAYOUT",
+        value_enum
+    )]
+    format: Option<Format>,
+    /// how a redundant environment's flag bytes mark the current copy
+    #[arg(long, value_enum, default_value_t = Flags::Boolean)]
+    flags: Flags,
+    /// byte order of the stored crcs. big on some powerpc and older mips
+    /// boards
+    #[arg(long, value_enum, default_value_t = ByteOrder::Little)]
+    endian: ByteOrder,
+    /// crc variant for vendor forks that changed it: crc32 (u-boot's),
+    /// jamcrc, bzip2, mpeg2, posix, or a comma-separated list of
+    /// poly=, init=, xorout= and reflected=yes|no changing crc32
+    #[arg(long, value_parser = parse_crc, default_value = "crc32")]
+    crc: Crc,
+    /// byte to fill unused space with when writing, 0 or 0xff to match
+    /// tools that leave nor flash erased. either is read
+    #[arg(long, value_parser = parse_byte, default_value = "0")]
+    padding: u8,
+    /// for boards built with CONFIG_ENV_AES, the 128-bit key as 32 hex
+    /// digits. the environment is decrypted on read and encrypted again
+    /// on write
+    #[arg(long, value_parser = parse_aes_key, value_name = "HEX")]
+    aes_key: Option<[u8; 16]>,
+    /// what to do with a variable stored twice, as on some corrupted
+    /// devices. either keep is reported
+    #[arg(long, value_enum, default_value_t = Duplicates::KeepLast)]
+    duplicates: Duplicates,
+    /// use the n'th environment the envs command lists, for images that
+    /// hold several (factory, user, recovery)
+    #[arg(long, value_name = "N", conflicts_with_all = ["offset", "offset_sectors", "partition", "size", "redundant_offset"])]
+    env_index: Option<usize>,
+    /// for a raw nand dump with the oob after every page (nanddump --oob),
+    /// the page size. offsets and sizes then count page data alone
+    #[arg(long, value_parser = parse_number, requires = "oob_size")]
+    page_size: Option<usize>,
+    /// the oob bytes after each page, 64 for 2K pages on most chips
+    #[arg(long, value_parser = parse_number, requires = "page_size")]
+    oob_size: Option<usize>,
+    /// the flash's erase block size, as in fw_env.config. writes are
+    /// widened to whole blocks, keeping anything else that shares them.
+    /// an mtd device reports its own
+    #[arg(long, value_parser = parse_number)]
+    sector_size: Option<usize>,
+}
+
+/// policies for variables stored twice
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Duplicates {
+    /// the first value counts
+    KeepFirst,
+    /// the last value counts, as it does for u-boot
+    KeepLast,
+    /// refuse to read the environment
+    Error,
+}
+
+impl From<Duplicates> for DuplicateKeys {
+    fn from(duplicates: Duplicates) -> DuplicateKeys {
+        match duplicates {
+            Duplicates::KeepFirst => DuplicateKeys::KeepFirst,
+            Duplicates::KeepLast => DuplicateKeys::KeepLast,
+            Duplicates::Error => DuplicateKeys::Error,
+        }
+    }
+}
+
+/// the byte orders a crc can be stored in
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ByteOrder {
+    Little,
+    Big,
+}
+
+impl From<ByteOrder> for Endian {
+    fn from(order: ByteOrder) -> Endian {
+        match order {
+            ByteOrder::Little => Endian::Little,
+            ByteOrder::Big => Endian::Big,
+        }
+    }
+}
+
+/// the flag schemes u-boot uses for redundant environments
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Flags {
+    /// 1 for the active copy, 0 for the obsolete one
+    Boolean,
+    /// a counter bumped on every save, as on nand
+    Counter,
+}
+
+impl From<Flags> for FlagScheme {
+    fn from(flags: Flags) -> FlagScheme {
+        match flags {
+            Flags::Boolean => FlagScheme::Boolean,
+            Flags::Counter => FlagScheme::Counter,
+        }
+    }
+}
+
+/// the environment layouts u-boot builds with
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// two copies, each with a crc and a flag byte (CONFIG_SYS_REDUNDAND_ENVIRONMENT)
+    Redundant,
+    /// one copy, a crc and then the variables
+    Single,
+    /// a uboot.env on a fat or ext boot partition: a single copy filling
+    /// the whole file
+    File,
+}
+
+/// the largest file taken for a uboot.env when no layout is given. they're
+/// CONFIG_ENV_SIZE bytes, which is a power of two and rarely over 128K,
+/// while flash images are megabytes
+const MAX_ENV_FILE: u64 = 0x80000;
+
+/// an environment LayoutArgs::find_envs came across
+#[derive(Clone, Copy)]
+struct FoundEnv {
     offset: usize,
-    len: usize,
-) -> Result<()> {
-    let mut f = OpenOptions::new().write(true).open(filename)?;
-    f.seek(SeekFrom::Start(offset as u64))?;
-    f.write_all(&hashmap_to_redundant_env_bytes(hm, len)?)?;
+    /// as --size takes it
+    size: usize,
+    format: Format,
+    /// out of 5
+    score: u32,
+    /// None if it has a good crc but doesn't parse
+    variables: Option<usize>,
+}
+
+impl FoundEnv {
+    fn format_name(&self) -> &'static str {
+        match self.format {
+            Format::Redundant => "redundant",
+            _ => "single",
+        }
+    }
+
+    /// the options that pick it out, and how sure that is
+    fn describe(&self) -> String {
+        format!(
+            "--offset {:#x} --size {:#x} ({}), confidence {}/5",
+            self.offset,
+            self.size,
+            self.format_name(),
+            self.score
+        )
+    }
+}
+
+impl LayoutArgs {
+    fn format(&self) -> Format {
+        self.format.unwrap_or(Format::Redundant)
+    }
+
+    /// fill in what's left out: everything for a uboot.env file, the whole
+    /// of which is the environment, the size from the crc with only an
+    /// offset, and otherwise whatever a search of file turns up
+    fn resolve(&mut self, file: &Path) -> Result<()> {
+        if self.page_size == Some(0) {
+            return Err(fail!(Usage, "--page-size can't be 0"));
+        }
+        let partition = match self.partition.take() {
+            Some(id) => Some(find_partition(file, &id)?),
+            None => None,
+        };
+        if let Some(offset) = self.offset_arg.take() {
+            self.offset = Some(match (&partition, offset) {
+                (Some(part), Offset::Start(offset)) => part.offset as usize + offset,
+                (Some(part), Offset::End(back)) => (part.offset + part.size)
+                    .checked_sub(back as u64)
+                    .filter(|&offset| offset >= part.offset)
+                    .ok_or_else(|| {
+                        fail!(
+                            Usage,
+                            "--offset -{:#x} is before the start of partition {}",
+                            back,
+                            part.number
+                        )
+                    })? as usize,
+                (None, offset) => offset.in_image(file)?,
+            });
+        }
+        if let Some(sectors) = self.offset_sectors.take() {
+            let offset = sectors
+                .checked_mul(512)
+                .ok_or_else(|| fail!(Usage, "--offset-sectors {} is too large", sectors))?;
+            self.offset = Some(offset + partition.as_ref().map_or(0, |part| part.offset as usize));
+        }
+        if let Some(part) = &partition {
+            self.offset.get_or_insert(part.offset as usize);
+        }
+        if let Some(index) = self.env_index {
+            return self.find_indexed(file, index);
+        }
+        if let (Some(_), Some(_), false) =
+            (self.offset, self.size, self.format == Some(Format::File))
+        {
+            return Ok(());
+        }
+        if self.format != Some(Format::File) {
+            match (self.offset, self.size) {
+                (Some(offset), None) => {
+                    let (size, format) = self.detect_size(file, offset)?;
+                    self.size = Some(size);
+                    self.format = Some(format);
+                    return Ok(());
+                }
+                (None, Some(_)) => return self.find_offset(file),
+                // nothing with oob in it is a uboot.env
+                (None, None) if self.oob().is_some() => return self.find_offset(file),
+                _ => {}
+            }
+        }
+        let len = match in_memory(file)? {
+            Some(bytes) => bytes.len() as u64,
+            None => std::fs::metadata(file)?.len(),
+        };
+        if self.format.is_none() {
+            if !len.is_power_of_two() || len > MAX_ENV_FILE {
+                return self.find_offset(file);
+            }
+            // a dump can be a power of two as well; search one whose crc
+            // doesn't check out as a uboot.env, if that finds anything
+            let whole = LayoutArgs {
+                offset: Some(0),
+                size: Some(len as usize),
+                format: Some(Format::File),
+                ..self.clone()
+            }
+            .to_layout();
+            let is_file = read_region(file, &whole)
+                .ok()
+                .and_then(|region| whole.copies(&region).ok())
+                .is_some_and(|copies| copies.iter().any(|c| c.is_valid()));
+            if !is_file && !self.find_envs(file)?.is_empty() {
+                return self.find_offset(file);
+            }
+            debug!(
+                "taking {} for a uboot.env of {:#x} bytes",
+                file.display(),
+                len
+            );
+            self.format = Some(Format::File);
+        }
+        let offset = *self.offset.get_or_insert(0);
+        if self.size.is_none() {
+            let size = (len as usize)
+                .checked_sub(offset)
+                .filter(|&size| size > 0)
+                .ok_or_else(|| {
+                    fail!(
+                        Usage,
+                        "--offset {:#x} is past the end of {}",
+                        offset,
+                        file.display()
+                    )
+                })?;
+            self.size = Some(size);
+        }
+        Ok(())
+    }
+
+    /// every environment in file with a good crc, in order, each scored out
+    /// of 5: good copies, a clean parse and variables every u-boot sets.
+    /// only the first 64M is searched, and --layout, --size and the crc
+    /// options narrow it down
+    fn find_envs(&self, file: &Path) -> Result<Vec<FoundEnv>> {
+        const SEARCHED: u64 = 64 << 20;
+        if image_len(file)? > SEARCHED {
+            warn!(
+                "only searching the first {} MiB of {}",
+                SEARCHED >> 20,
+                file.display()
+            );
+        }
+        let mut image = read_image_head(file, SEARCHED)?;
+        // searched in the page data alone, which offsets count
+        let plain = LayoutArgs {
+            page_size: None,
+            oob_size: None,
+            ..self.clone()
+        };
+        if let Some(oob) = self.oob() {
+            image = oob.data(&image);
+        }
+        let formats = match self.format {
+            Some(format) => vec![format],
+            None => vec![Format::Redundant, Format::Single],
+        };
+        // a copy's size
+        let sizes: Vec<usize> = (12..=18).map(|bits| 1 << bits).collect();
+        // a data area starts with name=, which rules out nearly everywhere
+        // before any crc is worked out
+        let starts_entry = |data: &[u8]| {
+            let end = data.iter().take(64).position(|&b| b == b'=' || b == 0);
+            end.is_some_and(|end| {
+                data[end] == b'='
+                    && std::str::from_utf8(&data[..end]).is_ok_and(|key| validate_key(key).is_ok())
+            })
+        };
+
+        let mut found: Vec<FoundEnv> = Vec::new();
+        for offset in (0..image.len()).step_by(0x100) {
+            if found.iter().any(|f| offset < f.offset + f.size) {
+                continue;
+            }
+            for &format in &formats {
+                let header = if format == Format::Redundant { 5 } else { 4 };
+                if !image.get(offset + header..).is_some_and(starts_entry) {
+                    continue;
+                }
+                let both = format == Format::Redundant && self.redundant_offset.is_none();
+                let sizes = match self.size {
+                    Some(size) if both => vec![size / 2],
+                    Some(size) => vec![size],
+                    None => sizes.clone(),
+                };
+                for size in sizes {
+                    let region_size = if both { 2 * size } else { size };
+                    let layout = LayoutArgs {
+                        offset: Some(offset),
+                        size: Some(region_size),
+                        format: Some(format),
+                        ..plain.clone()
+                    }
+                    .to_layout();
+                    let Some(region) = layout
+                        .spans()
+                        .into_iter()
+                        .map(|(at, span)| image.get(at as usize..at as usize + span.len()))
+                        .collect::<Option<Vec<_>>>()
+                        .map(|parts| parts.concat())
+                    else {
+                        break;
+                    };
+                    let Ok(copies) = layout.copies(&region) else {
+                        continue;
+                    };
+                    let good = copies.iter().filter(|c| c.is_valid()).count();
+                    if good == 0 {
+                        continue;
+                    }
+                    let mut score = if good == copies.len() { 2 } else { 1 };
+                    let vars = layout.parse(&region).ok();
+                    if let Some(vars) = &vars {
+                        score += 1;
+                        score += ["bootcmd", "bootdelay", "baudrate"]
+                            .iter()
+                            .filter(|key| vars.contains_key(key))
+                            .count()
+                            .min(2) as u32;
+                    }
+                    found.push(FoundEnv {
+                        offset,
+                        size: region_size,
+                        format,
+                        score,
+                        variables: vars.map(|vars| vars.len()),
+                    });
+                    break;
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// search file for the environment when no offset is given, filling in
+    /// the offset, size and format of the likeliest of find_envs. the choice
+    /// is confirmed at a terminal unless --yes was given; elsewhere only a
+    /// clear winner scoring 4 or more is taken
+    fn find_offset(&mut self, file: &Path) -> Result<()> {
+        let mut found = self.find_envs(file)?;
+        found.sort_by_key(|f| (std::cmp::Reverse(f.score), f.offset));
+        let Some(best) = found.first().copied() else {
+            return Err(fail!(
+                NotFound,
+                "no environment found in {}, give --offset",
+                file.display()
+            ));
+        };
+        let clear = found.get(1).is_none_or(|second| second.score < best.score);
+        eprintln!(
+            "found an environment in {}: {}",
+            file.display(),
+            best.describe()
+        );
+        for other in &found[1..] {
+            eprintln!("  also {}", other.describe());
+        }
+        if YES.load(Ordering::Relaxed) {
+            // --yes answers the question a terminal would be asked
+        } else if std::io::stdin().is_terminal() && !is_stdio(file) {
+            ask_to_continue()?;
+        } else if !clear || best.score < 4 {
+            return Err(fail!(
+                NotFound,
+                "not sure enough which environment in {} to use, give --offset or --env-index",
+                file.display()
+            ));
+        }
+        self.choose(best);
+        Ok(())
+    }
+
+    /// target the index'th environment (from 1) that find_envs lists, as
+    /// the envs command shows them
+    fn find_indexed(&mut self, file: &Path, index: usize) -> Result<()> {
+        let found = self.find_envs(file)?;
+        let chosen = index
+            .checked_sub(1)
+            .and_then(|i| found.get(i))
+            .copied()
+            .ok_or_else(|| {
+                fail!(
+                    NotFound,
+                    "{} has {} environments, not {}",
+                    file.display(),
+                    found.len(),
+                    index
+                )
+            })?;
+        info!("environment {}: {}", index, chosen.describe());
+        self.choose(chosen);
+        Ok(())
+    }
+
+    fn choose(&mut self, found: FoundEnv) {
+        self.offset = Some(found.offset);
+        self.size = Some(found.size);
+        self.format = Some(found.format);
+    }
+
+    /// the size, and the format if it wasn't given, of the environment at
+    /// offset in file: the common sizes from 4K to 256K, the first whose
+    /// stored crc matches (in either copy of a redundant environment)
+    fn detect_size(&self, file: &Path, offset: usize) -> Result<(usize, Format)> {
+        let formats = match self.format {
+            Some(format) => vec![format],
+            None => vec![Format::Redundant, Format::Single],
+        };
+        for bits in 12..=18 {
+            for &format in &formats {
+                let size = match (format, self.redundant_offset) {
+                    // both copies, unless they're apart
+                    (Format::Redundant, None) => 2 << bits,
+                    _ => 1 << bits,
+                };
+                let candidate = LayoutArgs {
+                    size: Some(size),
+                    format: Some(format),
+                    ..self.clone()
+                };
+                let layout = candidate.to_layout();
+                let Ok(region) = read_region(file, &layout) else {
+                    continue;
+                };
+                let valid = layout
+                    .copies(&region)
+                    .is_ok_and(|copies| copies.iter().any(|c| c.is_valid()));
+                if !valid {
+                    continue;
+                }
+                if log::log_enabled!(log::Level::Warn) {
+                    eprintln!(
+                        "detected --size {:#x} ({}) at {:#x}",
+                        size,
+                        match format {
+                            Format::Redundant => "redundant",
+                            _ => "single",
+                        },
+                        offset
+                    );
+                }
+                return Ok((size, format));
+            }
+        }
+        Err(fail!(
+            NotFound,
+            "no environment with a good crc at {:#x} in {}; give --size",
+            offset,
+            file.display()
+        ))
+    }
+
+    /// the layout described. resolve must have filled in the offset and size
+    fn oob(&self) -> Option<NandOob> {
+        let (page_size, oob_size) = self.page_size.zip(self.oob_size)?;
+        Some(NandOob {
+            page_size,
+            oob_size,
+        })
+    }
+
+    fn to_layout(&self) -> EnvLayout {
+        let (offset, size) = self
+            .offset
+            .zip(self.size)
+            .expect("layout used before it was resolved");
+        EnvLayout {
+            size: match self.redundant_offset {
+                Some(_) => size * 2,
+                None => size,
+            },
+            redundant_offset: self.redundant_offset.map(|o| o as u64),
+            redundant: self.format() == Format::Redundant,
+            flag_scheme: self.flags.into(),
+            crc_endianness: self.endian.into(),
+            crc: self.crc,
+            padding: self.padding,
+            aes_key: self.aes_key,
+            duplicates: self.duplicates.into(),
+            oob: self.oob(),
+            sector_size: self.sector_size,
+            ..EnvLayout::new(offset as u64, size)
+        }
+    }
+}
+
+/// modifications for commands that apply several at once
+#[derive(Args)]
+struct OpArgs {
+    /// file of operations to apply in order before any --set or --delete.
+    /// each line is `set KEY=VALUE`, `append KEY=VALUE`,
+    /// `prepend KEY=VALUE`, `delete KEY`, or `rename [--overwrite] OLD NEW`;
+    /// # starts a comment
+    #[arg(long)]
+    script: Option<PathBuf>,
+    /// set a variable; may be repeated
+    #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_assignment)]
+    sets: Vec<(String, String)>,
+    /// remove a variable; may be repeated
+    #[arg(long = "delete", value_name = "KEY")]
+    deletes: Vec<String>,
+    /// only warn, rather than fail, if a deleted variable isn't set
+    #[arg(long)]
+    ignore_missing: bool,
+}
+
+impl OpArgs {
+    /// the script's operations followed by the --set and --delete ones
+    fn ops(self) -> Result<Vec<Op>> {
+        // sets and deletes arrive as separate lists, so the relative
+        // order they were given in is lost. refuse anything ambiguous
+        let (sets, deletes) = (self.sets, self.deletes);
+        if let Some(key) = deletes.iter().find(|k| sets.iter().any(|(s, _)| s == *k)) {
+            return Err(fail!(Invalid, "{} is both set and deleted", key));
+        }
+
+        let mut ops = match self.script {
+            Some(path) => parse_script(&std::fs::read_to_string(path)?)?,
+            None => Vec::new(),
+        };
+        ops.extend(sets.into_iter().map(|(k, v)| Op::Set(k, v)));
+        ops.extend(deletes.into_iter().map(Op::Delete));
+        Ok(ops)
+    }
+}
+
+/// where to write the modified environment
+#[derive(Args)]
+struct WriteArgs {
+    /// copy the image to this file and patch the copy, leaving --file
+    /// untouched. - streams the patched image to stdout
+    #[arg(long, conflicts_with = "in_place")]
+    output: Option<PathBuf>,
+    /// patch --file itself, after saving the environment region to a
+    /// timestamped .bak next to it
+    #[arg(long)]
+    in_place: bool,
+    /// with --in-place, back up the whole file rather than just the region
+    #[arg(long, requires = "in_place")]
+    backup_whole: bool,
+    /// with --in-place, don't save a backup first, as for devices with
+    /// nowhere next to them to put one
+    #[arg(long, requires = "in_place", conflicts_with = "backup_whole")]
+    no_backup: bool,
+    /// show what would change without writing anything
+    #[arg(long)]
+    dry_run: bool,
+    /// write without asking for confirmation
+    #[arg(short, long)]
+    yes: bool,
+    /// read the region back after writing and check it matches
+    #[arg(long)]
+    verify_write: bool,
+    /// write variables in sorted order, so identical environments always
+    /// serialize to identical bytes
+    #[arg(long)]
+    sorted: bool,
+    /// like fw_setenv, write only the copy that isn't current and flag it
+    /// current, keeping the other as a fallback and halving flash wear
+    #[arg(long)]
+    alternate: bool,
+    /// write a compressed or android sparse --file back out as the plain
+    /// image it stands for, rather than packed again
+    #[arg(long, visible_alias = "unsparse")]
+    plain: bool,
+}
+
+/// the current time in utc as YYYYMMDD-HHMMSS
+fn timestamp() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rem) = (secs / 86400, secs % 86400);
+
+    // days since 1970-01-01 to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// the sidecar directory --in-place keeps an image's backups in
+fn backup_dir(file: &Path) -> PathBuf {
+    let mut name = file.to_path_buf().into_os_string();
+    name.push(".backups");
+    PathBuf::from(name)
+}
+
+/// save the environment region of env.file (or all of it) to a timestamped
+/// .bak in its backup directory, with a .json of metadata next to it.
+/// returns the backup's path
+fn backup_file(env: &EnvArgs, whole: bool) -> Result<PathBuf> {
+    let dir = backup_dir(env.file());
+    std::fs::create_dir_all(&dir)?;
+
+    let stamp = timestamp();
+    // more than one backup a second gets a counter to stay unique
+    let name = (0..)
+        .map(|n| match n {
+            0 => stamp.clone(),
+            n => format!("{}-{}", stamp, n),
+        })
+        .find(|name| !dir.join(format!("{}.bak", name)).exists())
+        .unwrap();
+    let path = dir.join(format!("{}.bak", name));
+
+    let layout = env.layout.to_layout();
+    let region = read_region(env.file(), &layout)?;
+    if whole {
+        std::fs::copy(env.file(), &path)?;
+    } else {
+        std::fs::write(&path, &region)?;
+    }
+
+    let source = std::fs::canonicalize(env.file()).unwrap_or_else(|_| env.file().to_path_buf());
+    let copies = layout.copies(&region)?;
+    let meta = json!({
+        "source": source.display().to_string(),
+        "offset": env.layout.offset,
+        "size": env.layout.size,
+        "redundant_offset": env.layout.redundant_offset,
+        "whole": whole,
+        "timestamp": stamp,
+        "crc": copies[0].stored_crc,
+    });
+    std::fs::write(
+        dir.join(format!("{}.json", name)),
+        serde_json::to_string_pretty(&meta)? + "\n",
+    )?;
+
+    info!("backed up {} to {}", env.file().display(), path.display());
+    Ok(path)
+}
+
+/// an image's backups and their metadata, oldest first
+fn list_backups(file: &Path) -> Result<Vec<(PathBuf, serde_json::Value)>> {
+    let dir = backup_dir(file);
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut backups = Vec::new();
+    for entry in std::fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "bak") {
+            continue;
+        }
+        let meta = match std::fs::read_to_string(path.with_extension("json")) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| fail!(Invalid, "{}: {}", path.with_extension("json").display(), e))?,
+            Err(e) => {
+                warn!("{} has no metadata: {}", path.display(), e);
+                serde_json::Value::Null
+            }
+        };
+        backups.push((path, meta));
+    }
+
+    // names are a timestamp with an optional -N counter
+    backups.sort_by_key(|(path, _)| {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let (stamp, n) = match stem.get(15..).and_then(|rest| rest.strip_prefix('-')) {
+            Some(n) => (stem[..15].to_string(), n.parse().unwrap_or(0)),
+            None => (stem.to_string(), 0u64),
+        };
+        (stamp, n)
+    });
+    Ok(backups)
+}
+
+/// show what's about to be written where and ask the user to confirm
+fn confirm_write(old: Option<&Env>, new: &Env, target: &Path, env: &EnvArgs) -> Result<()> {
+    let changed = match old {
+        Some(old) => format!("{} changed variables", old.diff(new).len()),
+        None => format!("a new environment of {} variables", new.len()),
+    };
+    let layout = env.layout.to_layout();
+    eprintln!(
+        "about to write {} to {} at offset {:#x}, size {:#x}",
+        changed,
+        target.display(),
+        layout.offset,
+        layout.size
+    );
+
+    ask_to_continue()
+}
+
+/// prompt on the terminal, failing unless the answer is yes
+fn ask_to_continue() -> Result<()> {
+    if !std::io::stdin().is_terminal() {
+        return Err(fail!(
+            Aborted,
+            "stdin is not a terminal, pass --yes to write anyway"
+        ));
+    }
+    eprint!("continue? [y/N] ");
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    if !matches!(answer.trim(), "y" | "Y" | "yes") {
+        return Err(fail!(Aborted, "aborted"));
+    }
     Ok(())
 }
 
-fn main() {
-    // values set for eero,cento SPI flash
-    let offset = 0x210000;
-    let len = 0x20000;
+/// what --dry-run prints: the changes vars would make and its space usage
+fn preview(vars: &Env, env: &EnvArgs) -> Result<()> {
+    let old = read_file(env.file(), &env.layout.to_layout()).unwrap_or_else(|e| {
+        println!(
+            "existing environment unreadable ({}), comparing to empty",
+            e
+        );
+        Env::new()
+    });
+    print_diff(&old.diff(vars), "current", "patched");
 
-    let backup_file = "backup.img";
-    let new_file = "new.img";
+    println!(
+        "usage: {} of {} bytes",
+        vars.serialized_len(),
+        env.layout.to_layout().capacity()
+    );
+    Ok(vars.check_fits(&env.layout.to_layout())?)
+}
 
-    // note: using a hashmap as backing means order will change.
-    // this doesn't (shouldn't) matter to u-boot in any way
+/// write vars back to the image, or to a patched copy of it if --output was given
+fn write_env(vars: Env, env: &EnvArgs, write: &WriteArgs) -> Result<()> {
+    if write.dry_run {
+        return preview(&vars, env);
+    }
+    let layout = env.layout.to_layout();
+    // only counters and alternate writes need to know what they're
+    // replacing, don't read slow devices twice otherwise
+    let current = if write.alternate || layout.flag_scheme == FlagScheme::Counter {
+        Some(read_region(env.file(), &layout)?)
+    } else {
+        None
+    };
+    let writer = EnvWriter {
+        sorted: write.sorted,
+        ..match &current {
+            Some(region) => EnvWriter::replacing(layout, region),
+            None => EnvWriter::new(layout),
+        }
+    };
+    let writes = match current {
+        Some(region) if write.alternate => writer.write_alternate(&vars, &region)?,
+        _ => vec![RegionWrite::whole(writer.write(&vars)?)],
+    };
+    commit(&writes, &vars, env, write)
+}
 
-    let mut hm = read_file(backup_file, offset, len).unwrap();
+/// env's region as it will be after writes
+fn after_writes(writes: &[RegionWrite], env: &EnvArgs) -> Result<Vec<u8>> {
+    let layout = env.layout.to_layout();
+    let mut region = match writes {
+        [whole] if whole.offset == 0 && whole.bytes.len() == layout.size => {
+            return Ok(whole.bytes.clone())
+        }
+        _ => read_region(env.file(), &layout)?,
+    };
+    for write in writes {
+        write.apply(&mut region);
+    }
+    Ok(region)
+}
 
-    // set bootdelay to a non-zero value
-    hm.insert("bootdelay".to_string(), 5.to_string());
-    // if you wish to further modify the environment, here's where you'd do so
+/// make writes to the environment wherever write says, after confirmation
+/// and backups. vars is what the region decodes to after them, for the
+/// summary
+fn commit(writes: &[RegionWrite], vars: &Env, env: &EnvArgs, write: &WriteArgs) -> Result<()> {
+    let target = write.output.as_deref().unwrap_or(env.file());
+    if write.output.is_none() && !write.in_place && !is_stdio(target) {
+        return Err(fail!(
+            Invalid,
+            "pass --output to patch a copy of {}, or --in-place to patch it directly",
+            target.display()
+        ));
+    }
 
-    println!("new environment:");
-    println!("{:#?}", hm);
+    // writing to stdout can't clobber anything, only ask for real files
+    if !write.yes && !is_stdio(target) {
+        let old = read_file(env.file(), &env.layout.to_layout()).ok();
+        confirm_write(old.as_ref(), vars, target, env)?;
+    }
+    if env.layout.oob().is_some() {
+        warn!("the oob is left as it was, so any ecc in it won't match the pages written");
+    }
+
+    let packed = packed_file(env.file())?;
+    let packings = match &packed {
+        Some(packed) if !write.plain => packed.packings.as_slice(),
+        Some(packed) => {
+            warn!(
+                "writing a plain image, not {} like {}",
+                packed
+                    .packings
+                    .iter()
+                    .map(|p| p.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" in "),
+                env.file().display()
+            );
+            &[]
+        }
+        None => &[],
+    };
+    if is_stdio(target) {
+        // patch a copy of the image in memory and stream all of it out
+        let image = patched_image(env, writes, packings)?;
+        if write.verify_write {
+            warn!("can't read back from stdout, --verify-write ignored");
+        }
+        info!("writing {:#x} byte image to stdout", image.len());
+        std::io::stdout().write_all(&image)?;
+        return Ok(());
+    }
 
-    // copy content of old file
-    std::fs::copy(backup_file, new_file).unwrap();
+    if write.in_place && !write.no_backup {
+        let backup = backup_file(env, write.backup_whole)?;
+        eprintln!("saved backup to {}", backup.display());
+    }
+
+    // a packed image is written whole, there's nothing to copy first
+    if let (Some(output), None) = (&write.output, &packed) {
+        // copy content of old file
+        info!("copying {} to {}", env.file().display(), output.display());
+        if is_stdio(env.file()) {
+            std::fs::write(output, stdin_bytes()?)?;
+        } else {
+            std::fs::copy(env.file(), output)?;
+        }
+    }
+    // work out what the region should hold before it's changed
+    let bytes = if write.verify_write {
+        Some(after_writes(writes, env)?)
+    } else {
+        None
+    };
     // overwrite region with updated content
-    patch_file(hm, new_file, offset, len).unwrap();
+    if packed.is_some() {
+        let image = patched_image(env, writes, packings)?;
+        info!(
+            "writing {:#x} byte image to {}",
+            image.len(),
+            target.display()
+        );
+        std::fs::write(target, image)?;
+        forget_packed(target);
+    } else {
+        write_region(target, &env.layout.to_layout(), writes)?;
+    }
+
+    if let Some(bytes) = bytes {
+        verify_write(target, env, &bytes, vars)?;
+    }
+    Ok(())
+}
+
+/// env's whole image with writes made to it, packed again with packings,
+/// the innermost last
+fn patched_image(env: &EnvArgs, writes: &[RegionWrite], packings: &[Packing]) -> Result<Vec<u8>> {
+    let mut image = read_image(env.file())?;
+    let layout = env.layout.to_layout();
+    for (offset, bytes) in writes.iter().flat_map(|write| write.placed(&layout)) {
+        let offset = offset as usize;
+        image
+            .get_mut(offset..offset + bytes.len())
+            .ok_or_else(|| fail!(Io, "image is too short"))?
+            .copy_from_slice(bytes);
+    }
+    for packing in packings.iter().rev() {
+        image = packing.pack(&image)?;
+    }
+    Ok(image)
+}
+
+/// check that what's now in target's region is exactly bytes, and that it
+/// parses back to vars
+fn verify_write(target: &Path, env: &EnvArgs, bytes: &[u8], vars: &Env) -> Result<()> {
+    let layout = env.layout.to_layout();
+    let written = read_region(target, &layout)?;
+    if let Some(i) = written.iter().zip(bytes).position(|(a, b)| a != b) {
+        let (at, span) = layout
+            .spans()
+            .into_iter()
+            .find(|(_, span)| span.contains(&i))
+            .unwrap_or((layout.offset, 0..0));
+        return Err(fail!(
+            Io,
+            "read-back verification failed: byte at {:#x} is {:#04x}, expected {:#04x}",
+            at + (i - span.start) as u64,
+            written[i],
+            bytes[i]
+        ));
+    }
+    let parsed = layout.parse_preserving(&written)?;
+    if parsed != *vars {
+        return Err(fail!(
+            Io,
+            "read-back verification failed: environment doesn't match"
+        ));
+    }
+    info!("read-back verification passed");
+    Ok(())
+}
+
+#[derive(Subcommand)]
+enum BackupsCommand {
+    /// list an image's backups, oldest first
+    List {
+        /// image the backups were taken of
+        #[arg(short, long)]
+        file: PathBuf,
+    },
+    /// delete all but the newest backups of an image
+    Prune {
+        /// image the backups were taken of
+        #[arg(short, long)]
+        file: PathBuf,
+        /// how many of the newest backups to keep
+        #[arg(long)]
+        keep: usize,
+        /// only list what would be deleted
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+/// formats an environment can be exported to or imported from
+#[derive(Clone, Copy, ValueEnum)]
+enum TextFormat {
+    /// one key=value per line
+    Text,
+    /// a uEnv.txt for boards that source one from their boot partition,
+    /// with newlines and backslashes escaped as u-boot expects
+    Uenv,
+    /// what u-boot's `env export -t` writes and `env import -t` reads
+    EnvExport,
+    /// what `env export -b` writes and `env import -b` reads: the
+    /// variables as nul-terminated strings with no crc, as pulled off a
+    /// running board over tftp or serial
+    EnvExportBinary,
+    /// `printenv` output as captured from a serial console, prompts, the
+    /// size line and lines the terminal wrapped included, for boards whose
+    /// flash can't be read any other way
+    Printenv,
+    /// a capture of `md.b` over the environment (its whole region, one
+    /// copy, or `env export -b`'s output), for boards that give nothing but
+    /// a console. import only
+    Md,
+}
+
+/// the architecture a boot script's header names, as mkimage's -A.
+/// `source` doesn't check it
+#[derive(Clone, Copy, ValueEnum)]
+enum ScriptArch {
+    Arm = 2,
+    X86 = 3,
+    Mips = 5,
+    Powerpc = 7,
+    Arm64 = 22,
+    Riscv = 26,
+}
+
+/// what import --merge does with variables set on both sides
+#[derive(Clone, Copy, ValueEnum)]
+enum MergeMode {
+    /// keep the image's value
+    Ours,
+    /// take the imported value
+    Theirs,
+    /// refuse to import
+    Fail,
+}
+
+impl From<MergeMode> for MergeStrategy {
+    fn from(mode: MergeMode) -> MergeStrategy {
+        match mode {
+            MergeMode::Ours => MergeStrategy::Ours,
+            MergeMode::Theirs => MergeStrategy::Theirs,
+            MergeMode::Fail => MergeStrategy::Fail,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// list every variable in the environment as key=value
+    Print {
+        #[command(flatten)]
+        env: EnvArgs,
+    },
+    /// print the raw value of a single variable
+    Get {
+        #[command(flatten)]
+        env: EnvArgs,
+        /// name of the variable
+        key: String,
+    },
+    /// write the environment to a file
+    #[command(mut_arg("format", |arg| arg.alias(None)))]
+    Export {
+        #[command(flatten)]
+        env: EnvArgs,
+        /// format to write
+        #[arg(long = "format", value_name = "FORMAT", value_enum, default_value_t = TextFormat::Text)]
+        text_format: TextFormat,
+        /// write a uEnv.txt, the same as --format uenv
+        #[arg(long, conflicts_with = "text_format")]
+        to_uenv: bool,
+        /// file to write the export to, or - for stdout
+        dest: PathBuf,
+    },
+    /// write an environment read from a file into the image
+    #[command(mut_arg("format", |arg| arg.alias(None)))]
+    Import {
+        #[command(flatten)]
+        env: EnvArgs,
+        #[command(flatten)]
+        write: WriteArgs,
+        /// format to read
+        #[arg(long = "format", value_name = "FORMAT", value_enum, default_value_t = TextFormat::Text)]
+        text_format: TextFormat,
+        /// read a uEnv.txt, the same as --format uenv
+        #[arg(long, conflicts_with = "text_format")]
+        from_uenv: bool,
+        /// keep variables in the existing environment that the import doesn't
+        /// mention, rather than replacing the environment outright. imported
+        /// values win unless --merge=ours or --merge=fail
+        #[arg(
+            long,
+            value_enum,
+            value_name = "CONFLICTS",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "theirs"
+        )]
+        merge: Option<MergeMode>,
+        /// file to import, or - for stdin
+        src: PathBuf,
+    },
+    /// show variables added, removed, or changed between two images
+    Diff {
+        #[command(flatten)]
+        env: EnvArgs,
+        /// image to compare against --file
+        #[arg(required_unless_present = "against_defaults")]
+        other: Option<PathBuf>,
+        /// compare against the default environment compiled into this u-boot
+        /// binary instead, showing what was changed from it
+        #[arg(long, value_name = "UBOOT_BIN", conflicts_with_all = ["other", "other_offset", "other_size"])]
+        against_defaults: Option<PathBuf>,
+        /// print the setenv, env delete and saveenv commands that turn the
+        /// first environment into the second (the defaults into --file's,
+        /// with --against-defaults), to paste into a serial console
+        #[arg(long, conflicts_with = "json")]
+        commands: bool,
+        /// offset of the environment in the other image, if different
+        #[arg(long, value_parser = parse_number)]
+        other_offset: Option<usize>,
+        /// size of the environment in the other image, if different
+        #[arg(long, value_parser = parse_number)]
+        other_size: Option<usize>,
+    },
+    /// check the crc of each copy of the environment. exits 0 if all are
+    /// valid, 1 if only one of two is, and 2 if none is
+    Verify {
+        #[command(flatten)]
+        env: EnvArgs,
+    },
+    /// show the layout, space usage, and crc state of the environment
+    Info {
+        #[command(flatten)]
+        env: EnvArgs,
+    },
+    /// search an image for regions that look like environments
+    Scan {
+        /// image file to search
+        #[arg(short, long)]
+        file: PathBuf,
+        /// distance between offsets to try
+        #[arg(long, value_parser = parse_number, default_value = "0x1000")]
+        step: usize,
+        /// smallest environment size to try
+        #[arg(long, value_parser = parse_number, default_value = "0x1000")]
+        min_size: usize,
+        /// largest environment size to try
+        #[arg(long, value_parser = parse_number, default_value = "0x40000")]
+        max_size: usize,
+        /// byte order of the crcs to look for
+        #[arg(long, value_enum, default_value_t = ByteOrder::Little)]
+        endian: ByteOrder,
+        /// crc variant to look for, as for --crc elsewhere
+        #[arg(long, value_parser = parse_crc, default_value = "crc32")]
+        crc: Crc,
+    },
+    /// list every environment in an image, numbered for --env-index, with
+    /// its offset, size and variable count
+    Envs {
+        /// image file to search
+        #[arg(short, long)]
+        file: PathBuf,
+        #[command(flatten)]
+        layout: LayoutArgs,
+    },
+    /// edit the environment as key=value text in $EDITOR. the result is
+    /// only written if it parses and fits
+    Edit {
+        #[command(flatten)]
+        env: EnvArgs,
+        #[command(flatten)]
+        write: WriteArgs,
+    },
+    /// browse and edit the environment interactively
+    #[cfg(feature = "tui")]
+    Tui {
+        #[command(flatten)]
+        env: EnvArgs,
+        #[command(flatten)]
+        write: WriteArgs,
+    },
+    /// list variables whose name or value matches a pattern
+    Grep {
+        #[command(flatten)]
+        env: EnvArgs,
+        /// substring to look for, or a regular expression with --regex
+        pattern: String,
+        /// treat the pattern as a regular expression
+        #[arg(short = 'E', long)]
+        regex: bool,
+        /// ignore case when matching
+        #[arg(short, long)]
+        ignore_case: bool,
+        /// only match variable names
+        #[arg(long, conflicts_with = "values")]
+        keys: bool,
+        /// only match values
+        #[arg(long)]
+        values: bool,
+    },
+    /// write a backup made by --in-place back into the image. the backup's
+    /// crc is checked first
+    Restore {
+        #[command(flatten)]
+        env: EnvArgs,
+        #[command(flatten)]
+        write: WriteArgs,
+        /// backup to restore, either of the region or of the whole file
+        #[arg(long)]
+        from: PathBuf,
+        /// restore even if the backup's crc doesn't check out
+        #[arg(long)]
+        force: bool,
+    },
+    /// list or prune the backups --in-place has saved for an image
+    Backups {
+        #[command(subcommand)]
+        action: BackupsCommand,
+    },
+    /// copy the environment from one image into another, re-serializing it
+    /// for the destination's layout
+    CopyEnv {
+        #[command(flatten)]
+        env: EnvArgs,
+        #[command(flatten)]
+        write: WriteArgs,
+        /// image to copy the environment into
+        #[arg(long)]
+        to: PathBuf,
+        /// offset of the environment in the destination, if different
+        #[arg(long, value_parser = parse_number)]
+        to_offset: Option<usize>,
+        /// size of the environment in the destination, if different
+        #[arg(long, value_parser = parse_number)]
+        to_size: Option<usize>,
+    },
+    /// build an environment image from a text file exactly as u-boot's
+    /// mkenvimage does, with the same options, to stand in for it in build
+    /// systems
+    Mkenvimage {
+        /// size of the image, the size of the environment partition
+        #[arg(short, long, value_parser = parse_number)]
+        size: usize,
+        /// where to write the image [default: stdout]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// add a flag byte, for one copy of a redundant environment
+        #[arg(short, long)]
+        redundant: bool,
+        /// store the crc big-endian
+        #[arg(short, long)]
+        big_endian: bool,
+        /// byte to fill unused space with
+        #[arg(short, long, value_parser = parse_byte, default_value = "0xff")]
+        padding: u8,
+        /// key=value lines, or - for stdin
+        #[arg(default_value = "-")]
+        input: PathBuf,
+    },
+    /// find the default environment compiled into a u-boot binary, what the
+    /// board falls back to when the stored one is bad or reset, and print
+    /// or export it
+    Defaults {
+        /// u-boot.bin, or a flash image with u-boot in it
+        #[arg(short, long)]
+        file: PathBuf,
+        /// format to export in, as for export
+        #[arg(long = "format", value_name = "FORMAT", value_enum, default_value_t = TextFormat::Text, requires = "dest")]
+        text_format: TextFormat,
+        /// file to export the defaults to, or - for stdout, rather than
+        /// printing them
+        dest: Option<PathBuf>,
+    },
+    /// print variables the way fw_printenv does, for scripts written
+    /// against it. also what runs when invoked through a link named
+    /// fw_printenv
+    #[command(name = "fw_printenv")]
+    FwPrintenv {
+        #[command(flatten)]
+        fw: FwArgs,
+        /// print only the value of the one variable named
+        #[arg(short, long, requires = "names")]
+        noheader: bool,
+        /// variables to print, all of them if none are named
+        names: Vec<String>,
+    },
+    /// change variables the way fw_setenv does, writing the copy that
+    /// isn't current. also what runs when invoked through a link named
+    /// fw_setenv
+    #[command(name = "fw_setenv")]
+    FwSetenv {
+        #[command(flatten)]
+        fw: FwArgs,
+        /// file of `name value` lines to apply, or - for stdin. a name
+        /// alone deletes it, and # starts a comment
+        #[arg(short, long, conflicts_with = "name")]
+        script: Option<PathBuf>,
+        /// variable to set, or delete if no value is given
+        #[arg(required_unless_present = "script")]
+        name: Option<String>,
+        /// the value, with several words joined by spaces
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        value: Vec<String>,
+    },
+    /// print a shell completion script, e.g. for
+    /// `uboot-patcher completions bash > /etc/bash_completion.d/uboot-patcher`
+    Completions {
+        /// shell to generate completions for
+        shell: clap_complete::Shell,
+    },
+    /// set a variable, adding it if it doesn't exist
+    Set {
+        #[command(flatten)]
+        env: EnvArgs,
+        #[command(flatten)]
+        write: WriteArgs,
+        /// name of the variable
+        key: String,
+        /// new value
+        #[arg(allow_hyphen_values = true)]
+        value: String,
+        /// add the value to the end of the existing one instead of replacing it
+        #[arg(long, conflicts_with = "prepend")]
+        append: bool,
+        /// add the value to the start of the existing one instead
+        #[arg(long)]
+        prepend: bool,
+        /// the value is hex bytes, for the binary values some vendors
+        /// store, which needn't be utf-8
+        #[arg(long, conflicts_with_all = ["append", "prepend"])]
+        hex: bool,
+    },
+    /// remove a variable from the environment
+    Delete {
+        #[command(flatten)]
+        env: EnvArgs,
+        #[command(flatten)]
+        write: WriteArgs,
+        /// name of the variable
+        key: String,
+        /// only warn, rather than fail, if the variable isn't set
+        #[arg(long)]
+        ignore_missing: bool,
+    },
+    /// rename a variable, keeping its value
+    Rename {
+        #[command(flatten)]
+        env: EnvArgs,
+        #[command(flatten)]
+        write: WriteArgs,
+        /// current name of the variable
+        old: String,
+        /// name to give it
+        new: String,
+        /// replace the new variable if it already exists
+        #[arg(long)]
+        overwrite: bool,
+    },
+    /// replace the environment with an empty one, or with the contents of a
+    /// key=value defaults file. the existing environment isn't read, so this
+    /// also works on a corrupt region
+    Clear {
+        #[command(flatten)]
+        env: EnvArgs,
+        #[command(flatten)]
+        write: WriteArgs,
+        /// seed the new environment from this key=value file
+        #[arg(long)]
+        defaults: Option<PathBuf>,
+    },
+    /// apply several operations in a single write
+    Patch {
+        #[command(flatten)]
+        env: EnvArgs,
+        #[command(flatten)]
+        write: WriteArgs,
+        #[command(flatten)]
+        ops: OpArgs,
+    },
+    /// write a boot.scr that makes the operations' changes from a u-boot
+    /// prompt (`load ... $loadaddr boot.scr; source $loadaddr`), for boards
+    /// whose flash can't be written from outside
+    BootScript {
+        #[command(flatten)]
+        env: EnvArgs,
+        #[command(flatten)]
+        ops: OpArgs,
+        /// where to write the script image, or - for stdout
+        #[arg(short, long)]
+        output: PathBuf,
+        /// image name in the header, as mkimage's -n
+        #[arg(long, default_value = "environment changes")]
+        name: String,
+        /// architecture in the header, as mkimage's -A
+        #[arg(long, value_enum, default_value_t = ScriptArch::Arm)]
+        arch: ScriptArch,
+        /// leave out the saveenv, so the changes last until the next reset
+        #[arg(long)]
+        no_saveenv: bool,
+        /// write the commands as plain text to paste into a serial console,
+        /// without the uimage header
+        #[arg(long)]
+        text: bool,
+    },
+    /// apply the same operations to many images, each patched in place or
+    /// copied into --output-dir
+    Batch {
+        /// image to patch; may be repeated
+        #[arg(short, long = "file", value_name = "FILE")]
+        files: Vec<PathBuf>,
+        /// patch every image matching this glob, e.g. 'dumps/*.bin'; may be
+        /// repeated
+        #[arg(long = "glob", value_name = "PATTERN")]
+        globs: Vec<String>,
+        #[command(flatten)]
+        layout: LayoutArgs,
+        #[command(flatten)]
+        ops: OpArgs,
+        /// write patched copies into this directory, keeping their names
+        #[arg(long, required_unless_present_any = ["in_place", "dry_run"])]
+        output_dir: Option<PathBuf>,
+        /// patch each image itself, backing up its environment first
+        #[arg(long, conflicts_with = "output_dir")]
+        in_place: bool,
+        /// with --in-place, back up whole files rather than just the region
+        #[arg(long, requires = "in_place")]
+        backup_whole: bool,
+        /// show what would change in each image without writing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// write without asking for confirmation
+        #[arg(short, long)]
+        yes: bool,
+        /// read each region back after writing and check it matches
+        #[arg(long)]
+        verify_write: bool,
+        /// write variables in sorted order
+        #[arg(long)]
+        sorted: bool,
+        /// write only the copy that isn't current, like fw_setenv
+        #[arg(long)]
+        alternate: bool,
+    },
+}
+
+impl Command {
+    /// the environment the command reads, for commands with one
+    fn env_args(&mut self) -> Option<&mut EnvArgs> {
+        match self {
+            Command::Print { env }
+            | Command::Get { env, .. }
+            | Command::Export { env, .. }
+            | Command::Import { env, .. }
+            | Command::Diff { env, .. }
+            | Command::Verify { env, .. }
+            | Command::Info { env }
+            | Command::Edit { env, .. }
+            | Command::Grep { env, .. }
+            | Command::Restore { env, .. }
+            | Command::CopyEnv { env, .. }
+            | Command::Set { env, .. }
+            | Command::Delete { env, .. }
+            | Command::Rename { env, .. }
+            | Command::Clear { env, .. }
+            | Command::Patch { env, .. }
+            | Command::BootScript { env, .. } => Some(env),
+            #[cfg(feature = "tui")]
+            Command::Tui { env, .. } => Some(env),
+            Command::Scan { .. }
+            | Command::Envs { .. }
+            | Command::Backups { .. }
+            | Command::Completions { .. }
+            | Command::Mkenvimage { .. }
+            | Command::Defaults { .. }
+            | Command::FwPrintenv { .. }
+            | Command::FwSetenv { .. }
+            | Command::Batch { .. } => None,
+        }
+    }
+
+    /// whether --yes was given, for commands that write
+    fn yes(&self) -> bool {
+        match self {
+            Command::Import { write, .. }
+            | Command::Edit { write, .. }
+            | Command::Restore { write, .. }
+            | Command::CopyEnv { write, .. }
+            | Command::Set { write, .. }
+            | Command::Delete { write, .. }
+            | Command::Rename { write, .. }
+            | Command::Clear { write, .. }
+            | Command::Patch { write, .. } => write.yes,
+            #[cfg(feature = "tui")]
+            Command::Tui { write, .. } => write.yes,
+            Command::Batch { yes, .. } => *yes,
+            _ => false,
+        }
+    }
+}
+
+fn main() {
+    // run as fw_printenv or fw_setenv when invoked through a link by that
+    // name, so the tool can stand in for u-boot-tools
+    let mut args: Vec<_> = std::env::args_os().collect();
+    let invoked_as = args
+        .first()
+        .map(Path::new)
+        .and_then(Path::file_name)
+        .and_then(|name| name.to_str())
+        .filter(|name| matches!(*name, "fw_printenv" | "fw_setenv"))
+        .map(str::to_string);
+    if let Some(name) = invoked_as {
+        args.insert(1, name.into());
+    }
+    let cli = Cli::parse_from(args);
+
+    let level = match (cli.quiet, cli.verbose) {
+        (true, _) => log::LevelFilter::Error,
+        (false, 0) => log::LevelFilter::Warn,
+        (false, 1) => log::LevelFilter::Info,
+        (false, 2) => log::LevelFilter::Debug,
+        (false, _) => log::LevelFilter::Trace,
+    };
+    env_logger::Builder::new()
+        .filter_level(level)
+        .format(|buf, record| {
+            let level = match record.level() {
+                log::Level::Warn => "warning".to_string(),
+                level => level.as_str().to_lowercase(),
+            };
+            writeln!(buf, "{}: {}", level, record.args())
+        })
+        .init();
+
+    NO_COLOR.store(cli.no_color, Ordering::Relaxed);
+
+    if cli.help_exit_codes {
+        print!("{}", EXIT_CODES);
+        return;
+    }
+    let Some(command) = cli.command else {
+        let _ = Cli::command().print_help();
+        std::process::exit(2);
+    };
+
+    if let Err(e) = run(command, cli.json) {
+        eprintln!("Error: {:#}", e);
+        if let Some(hint) = hint(&e) {
+            eprintln!("hint: {}", hint);
+        }
+        std::process::exit(exit_code(&e));
+    }
+}
+
+fn run(mut command: Command, json: bool) -> Result<()> {
+    YES.store(command.yes(), Ordering::Relaxed);
+    if let Some(env) = command.env_args() {
+        env.resolve()?;
+    }
+    match command {
+        Command::Print { env } => {
+            let vars = read_file(env.file(), &env.layout.to_layout())?;
+            let shown = printable_vars(&vars);
+            if json {
+                return print_json(&json!(BTreeMap::from_iter(shown)));
+            }
+
+            for (key, val) in shown {
+                println!("{}={}", key, val);
+            }
+        }
+        Command::Get { env, key } => {
+            let vars = read_file(env.file(), &env.layout.to_layout())?;
+            let val = vars
+                .get_bytes(&key)
+                .ok_or_else(|| fail!(NotFound, "no such variable: {}", key))?;
+            if json {
+                return print_json(&json!(escape_bytes(val)));
+            }
+            // as stored, even when it isn't utf-8
+            let mut stdout = std::io::stdout();
+            stdout.write_all(val)?;
+            stdout.write_all(b"\n")?;
+        }
+        Command::Export {
+            env,
+            text_format,
+            to_uenv,
+            dest,
+        } => {
+            let vars = read_file(env.file(), &env.layout.to_layout())?;
+            let text_format = if to_uenv {
+                TextFormat::Uenv
+            } else {
+                text_format
+            };
+            export(&vars, text_format, &dest)?;
+        }
+        Command::Defaults {
+            file,
+            text_format,
+            dest,
+        } => {
+            let (offset, vars) = read_defaults(&file)?;
+            if let Some(dest) = dest {
+                return export(&vars, text_format, &dest);
+            }
+            if json {
+                return print_json(&json!({
+                    "offset": offset,
+                    "variables": BTreeMap::from_iter(vars.iter()),
+                }));
+            }
+            let mut sorted: Vec<_> = vars.iter().collect();
+            sorted.sort();
+            for (key, val) in sorted {
+                println!("{}={}", key, val);
+            }
+        }
+        Command::Import {
+            env,
+            write,
+            text_format,
+            from_uenv,
+            merge,
+            src,
+        } => {
+            let mut bytes = if is_stdio(&src) {
+                if is_stdio(env.file()) {
+                    return Err(fail!(
+                        Invalid,
+                        "the image and the import can't both be stdin"
+                    ));
+                }
+                let mut bytes = Vec::new();
+                std::io::stdin().read_to_end(&mut bytes)?;
+                bytes
+            } else {
+                std::fs::read(src)?
+            };
+            let text_format = if from_uenv {
+                TextFormat::Uenv
+            } else {
+                text_format
+            };
+            if let TextFormat::EnvExport = text_format {
+                // a capture of memory runs on past the export's nul
+                if let Some(end) = bytes.iter().position(|&b| b == 0) {
+                    bytes.truncate(end);
+                }
+            }
+            let imported = match text_format {
+                TextFormat::EnvExportBinary => Env::parse_data(&bytes)?,
+                text_format => {
+                    let text = String::from_utf8(bytes)
+                        .map_err(|e| fail!(Invalid, "the import isn't text: {}", e))?;
+                    match text_format {
+                        TextFormat::Text => text_to_env(&text)?,
+                        TextFormat::Printenv => printenv_to_env(&text)?,
+                        TextFormat::Md => {
+                            env_from_dump(&md_to_bytes(&text)?, &env.layout.to_layout())?
+                        }
+                        _ => uenv_to_env(&text)?,
+                    }
+                }
+            };
+            let vars = match merge {
+                Some(mode) => {
+                    let mut vars = read_file(env.file(), &env.layout.to_layout())?;
+                    vars.merge(imported, mode.into())?;
+                    vars
+                }
+                None => imported,
+            };
+            write_env(vars, &env, &write)?;
+        }
+        Command::Diff {
+            env,
+            other,
+            other_offset,
+            other_size,
+            against_defaults,
+            commands,
+        } => {
+            let layout = env.layout.to_layout();
+            if let Some(defaults) = against_defaults {
+                let (_, old) = read_defaults(&defaults)?;
+                let new = read_file(env.file(), &layout)?;
+                let changes = old.diff(&new);
+                if commands {
+                    print!(
+                        "{}",
+                        console_commands(&changes_to_commands(&changes, !changes.is_empty()))
+                    );
+                    return Ok(());
+                }
+                if json {
+                    return print_json(&diff_to_json(&changes));
+                }
+                print_diff(
+                    &changes,
+                    &format!("defaults in {}", defaults.display()),
+                    &env.file().display().to_string(),
+                );
+                return Ok(());
+            }
+            let other = other.expect("clap requires other without --against-defaults");
+            let old = read_file(env.file(), &layout)?;
+            let new = read_file(
+                &other,
+                &EnvLayout {
+                    offset: other_offset.map_or(layout.offset, |o| o as u64),
+                    size: other_size.unwrap_or(layout.size),
+                    // a second copy elsewhere is somewhere else again
+                    redundant_offset: layout.redundant_offset.filter(|_| other_offset.is_none()),
+                    ..layout
+                },
+            )?;
+
+            let changes = old.diff(&new);
+            if commands {
+                print!(
+                    "{}",
+                    console_commands(&changes_to_commands(&changes, !changes.is_empty()))
+                );
+                return Ok(());
+            }
+            if json {
+                return print_json(&diff_to_json(&changes));
+            }
+            print_diff(
+                &changes,
+                &env.file().display().to_string(),
+                &other.display().to_string(),
+            );
+        }
+        Command::Verify { env } => {
+            let layout = env.layout.to_layout();
+            let buf = read_region(env.file(), &layout)?;
+            let copies = layout.copies(&buf)?;
+            let valid = copies.iter().filter(|c| c.is_valid()).count();
+
+            let current = layout.current_copy(&copies);
+            if json {
+                print_json(&json!({
+                    "copies": copies.iter().map(|c| copy_to_json(c, &layout)).collect::<Vec<_>>(),
+                    "valid": valid,
+                    "current": current.map(|i| i + 1),
+                }))?;
+            } else {
+                for (i, copy) in copies.iter().enumerate() {
+                    println!("copy {}: {}", i + 1, describe_copy(copy, &layout));
+                }
+                if let (Some(i), 2) = (current, copies.len()) {
+                    println!("using copy {}", i + 1);
+                }
+            }
+
+            std::process::exit(match valid {
+                n if n == copies.len() => 0,
+                0 => 2,
+                _ => 1,
+            });
+        }
+        Command::Info { env } => {
+            let layout = env.layout.to_layout();
+            let buf = read_region(env.file(), &layout)?;
+            let copies = layout.copies(&buf)?;
+            let vars = parse_region(&layout, &buf)?;
+
+            let capacity = layout.capacity();
+            let used = vars.serialized_len();
+            if json {
+                return print_json(&json!({
+                    "offset": layout.offset,
+                    "size": layout.size,
+                    "redundant": layout.redundant,
+                    "redundant_offset": layout.redundant_offset,
+                    "variables": vars.len(),
+                    "raw_entries": vars.raw_entries().count(),
+                    "used": used,
+                    "capacity": capacity,
+                    "free": vars.remaining(&layout),
+                    "copies": copies.iter().map(|c| copy_to_json(c, &layout)).collect::<Vec<_>>(),
+                    "current": layout.current_copy(&copies).map(|i| i + 1),
+                }));
+            }
+
+            match layout.redundant_offset.filter(|_| layout.redundant) {
+                Some(second) => {
+                    println!(
+                        "offset:    {:#x}, second copy at {:#x}",
+                        layout.offset, second
+                    );
+                    println!(
+                        "size:      {:#x} (2 copies of {:#x})",
+                        layout.size,
+                        layout.size / 2
+                    );
+                }
+                _ if layout.redundant => {
+                    println!("offset:    {:#x}", layout.offset);
+                    println!(
+                        "size:      {:#x} (2 copies of {:#x})",
+                        layout.size,
+                        layout.size / 2
+                    );
+                }
+                _ if env.layout.format() == Format::File => {
+                    println!("offset:    {:#x}", layout.offset);
+                    println!("size:      {:#x} (uboot.env file)", layout.size);
+                }
+                _ => {
+                    println!("offset:    {:#x}", layout.offset);
+                    println!("size:      {:#x} (single copy)", layout.size);
+                }
+            }
+            if let Some(oob) = layout.oob {
+                println!(
+                    "pages:     {:#x} bytes, each followed by {:#x} of oob",
+                    oob.page_size, oob.oob_size
+                );
+            }
+            match vars.raw_entries().count() {
+                0 => println!("variables: {}", vars.len()),
+                raw => println!("variables: {} and {} invalid entries", vars.len(), raw),
+            }
+            println!(
+                "used:      {} of {} bytes ({:.1}%)",
+                used,
+                capacity,
+                used as f64 * 100.0 / capacity as f64
+            );
+            println!("free:      {} bytes", vars.remaining(&layout));
+            for (i, copy) in copies.iter().enumerate() {
+                let current = layout.current_copy(&copies) == Some(i);
+                println!(
+                    "copy {}:    {}{}",
+                    i + 1,
+                    describe_copy(copy, &layout),
+                    if current && copies.len() > 1 {
+                        ", in use"
+                    } else {
+                        ""
+                    }
+                );
+            }
+        }
+        Command::Envs { file, layout } => {
+            let found = layout.find_envs(&file)?;
+            if found.is_empty() {
+                return Err(fail!(NotFound, "no environments found"));
+            }
+            if json {
+                let found: Vec<_> = found
+                    .iter()
+                    .enumerate()
+                    .map(|(i, f)| {
+                        json!({
+                            "index": i + 1,
+                            "offset": f.offset,
+                            "size": f.size,
+                            "format": f.format_name(),
+                            "variables": f.variables,
+                            "confidence": f.score,
+                        })
+                    })
+                    .collect();
+                return print_json(&json!(found));
+            }
+            println!("index  offset      size      format     variables  confidence");
+            for (i, f) in found.iter().enumerate() {
+                println!(
+                    "{:<5}  {:<10}  {:<8}  {:<9}  {:<9}  {}/5",
+                    i + 1,
+                    format!("{:#x}", f.offset),
+                    format!("{:#x}", f.size),
+                    f.format_name(),
+                    f.variables.map_or("-".to_string(), |n| n.to_string()),
+                    f.score
+                );
+            }
+        }
+        Command::Scan {
+            file,
+            step,
+            min_size,
+            max_size,
+            endian,
+            crc,
+        } => {
+            let image = read_image(&file)?;
+            let template = EnvLayout {
+                crc_endianness: endian.into(),
+                crc,
+                ..EnvLayout::new(0, 0)
+            };
+            let found = scan_image(&image, step, min_size, max_size, &template);
+            if found.is_empty() {
+                return Err(fail!(NotFound, "no environments found"));
+            }
+
+            if json {
+                let found: Vec<_> = found
+                    .iter()
+                    .map(|c| {
+                        let region = &image[c.offset..c.offset + c.size];
+                        json!({
+                            "offset": c.offset,
+                            "size": c.size,
+                            "copies": c.copies.iter().map(|copy| copy_to_json(copy, &c.layout)).collect::<Vec<_>>(),
+                            "variables": c.layout.view(region).ok().map(|view| view.len()),
+                        })
+                    })
+                    .collect();
+                return print_json(&json!(found));
+            }
+
+            for c in found {
+                let region = &image[c.offset..c.offset + c.size];
+                let summary = match c.layout.view(region) {
+                    Ok(view) => format!("{} variables", view.len()),
+                    Err(_) => "unparseable".to_string(),
+                };
+                let second = if c.copies[1].is_valid() {
+                    "both copies valid"
+                } else {
+                    "second copy invalid"
+                };
+                println!(
+                    "offset {:#x} size {:#x}: {}, {}",
+                    c.offset, c.size, second, summary
+                );
+            }
+        }
+        Command::Edit { env, write } => {
+            let mut vars = read_file(env.file(), &env.layout.to_layout())?;
+            // entries text can't show go back in as they were
+            let raw = vars.take_raw_entries();
+            let original = env_to_text(&vars)?;
+            let (edited, path) = edit_text(&original)?;
+
+            if edited == original {
+                remove_private_temp_file(&path)?;
+                println!("no changes");
+                return Ok(());
+            }
+
+            let validated = text_to_env(&edited).and_then(|mut new| {
+                new.keep_raw_entries(raw);
+                new.check_fits(&env.layout.to_layout())?;
+                Ok(new)
+            });
+            let new = validated
+                .map_err(|e| e.context(format!("not writing, edits left in {}", path.display())))?;
+
+            remove_private_temp_file(&path)?;
+            write_env(new, &env, &write)?;
+        }
+        #[cfg(feature = "tui")]
+        Command::Tui { env, write } => {
+            let vars = read_file(env.file(), &env.layout.to_layout())?;
+            if let Some(new) = tui::run(vars, env.layout.to_layout())? {
+                write_env(new, &env, &write)?;
+            }
+        }
+        Command::Grep {
+            env,
+            pattern,
+            regex,
+            ignore_case,
+            keys,
+            values,
+        } => {
+            let pattern = if regex {
+                pattern
+            } else {
+                regex::escape(&pattern)
+            };
+            let re = regex::RegexBuilder::new(&pattern)
+                .case_insensitive(ignore_case)
+                .build()?;
+            let color = color_enabled();
+
+            let vars = read_file(env.file(), &env.layout.to_layout())?;
+            let mut sorted: Vec<_> = vars.iter().collect();
+            sorted.sort();
+
+            let matches: Vec<_> = sorted
+                .into_iter()
+                .map(|(key, val)| {
+                    let key_match = !values && re.is_match(key);
+                    let val_match = !keys && re.is_match(val);
+                    (key, val, key_match, val_match)
+                })
+                .filter(|(_, _, key_match, val_match)| *key_match || *val_match)
+                .collect();
+            let found = !matches.is_empty();
+
+            if json {
+                let matches = BTreeMap::from_iter(matches.iter().map(|(k, v, _, _)| (k, v)));
+                print_json(&json!(matches))?;
+            } else {
+                for (key, val, key_match, val_match) in matches {
+                    if color {
+                        let key = if key_match {
+                            highlight(&re, key)
+                        } else {
+                            key.to_string()
+                        };
+                        let val = if val_match {
+                            highlight(&re, val)
+                        } else {
+                            val.to_string()
+                        };
+                        println!("{}={}", key, val);
+                    } else {
+                        println!("{}={}", key, val);
+                    }
+                }
+            }
+
+            // behave like grep for scripting: non-zero exit when nothing matched
+            if !found {
+                std::process::exit(1);
+            }
+        }
+        Command::Restore {
+            env,
+            write,
+            from,
+            force,
+        } => {
+            // region backups are exactly one environment long, anything
+            // else is a backup of the whole file
+            let backup = std::fs::read(&from)?;
+            let region = if backup.len() == env.layout.to_layout().size {
+                backup
+            } else {
+                read_region(&from, &env.layout.to_layout())?
+            };
+
+            let vars = match env.layout.to_layout().parse(&region) {
+                Ok(vars) => vars,
+                Err(e) if force => {
+                    warn!("restoring {} anyway: {:#}", from.display(), e);
+                    Env::new()
+                }
+                Err(e) => {
+                    return Err(anyhow::Error::new(e).context(format!(
+                        "{} failed verification, pass --force to restore it anyway",
+                        from.display()
+                    )))
+                }
+            };
+
+            if write.dry_run {
+                return preview(&vars, &env);
+            }
+            commit(&[RegionWrite::whole(region)], &vars, &env, &write)?;
+        }
+        Command::Backups { action } => match action {
+            BackupsCommand::List { file } => {
+                let backups = list_backups(&file)?;
+                if json {
+                    return print_json(&json!(backups
+                        .iter()
+                        .map(|(path, meta)| json!({
+                            "path": path.display().to_string(),
+                            "metadata": meta,
+                        }))
+                        .collect::<Vec<_>>()));
+                }
+                for (path, meta) in &backups {
+                    let kind = if meta["whole"].as_bool() == Some(true) {
+                        "whole "
+                    } else {
+                        "region"
+                    };
+                    println!(
+                        "{}  {}  {:#x}+{:#x}  crc {:#010x}  {}",
+                        path.display(),
+                        kind,
+                        meta["offset"].as_u64().unwrap_or(0),
+                        meta["size"].as_u64().unwrap_or(0),
+                        meta["crc"].as_u64().unwrap_or(0),
+                        meta["source"].as_str().unwrap_or("?"),
+                    );
+                }
+            }
+            BackupsCommand::Prune {
+                file,
+                keep,
+                dry_run,
+            } => {
+                let backups = list_backups(&file)?;
+                let excess = backups.len().saturating_sub(keep);
+                for (path, _) in &backups[..excess] {
+                    if dry_run {
+                        println!("would delete {}", path.display());
+                        continue;
+                    }
+                    std::fs::remove_file(path)?;
+                    // older backups may not have metadata
+                    let _ = std::fs::remove_file(path.with_extension("json"));
+                    println!("deleted {}", path.display());
+                }
+                info!(
+                    "kept {} of {} backups",
+                    backups.len() - excess,
+                    backups.len()
+                );
+            }
+        },
+        Command::CopyEnv {
+            env,
+            write,
+            to,
+            to_offset,
+            to_size,
+        } => {
+            let vars = read_file(env.file(), &env.layout.to_layout())?;
+            let dest = EnvArgs {
+                file: Some(to),
+                config: None,
+                dtb: None,
+                layout: LayoutArgs {
+                    offset: to_offset.or(env.layout.offset),
+                    size: to_size.or(env.layout.size),
+                    redundant_offset: env.layout.redundant_offset.filter(|_| to_offset.is_none()),
+                    ..env.layout
+                },
+            };
+            write_env(vars, &dest, &write)?;
+        }
+        Command::Mkenvimage {
+            size,
+            output,
+            redundant,
+            big_endian,
+            padding,
+            input,
+        } => {
+            let text = if is_stdio(&input) {
+                stdin_bytes()?.to_vec()
+            } else {
+                std::fs::read(&input)?
+            };
+            let endian = if big_endian {
+                Endian::Big
+            } else {
+                Endian::Little
+            };
+            let image = mkenvimage(&text, size, redundant, endian, padding)?;
+            match output {
+                Some(path) if !is_stdio(&path) => std::fs::write(path, image)?,
+                _ => std::io::stdout().write_all(&image)?,
+            }
+        }
+        Command::FwPrintenv {
+            fw,
+            noheader,
+            names,
+        } => {
+            let env = fw.env_args()?;
+            let vars = read_file(env.file(), &env.layout.to_layout())?;
+            if noheader && names.len() > 1 {
+                eprintln!("## Error: `-n' option requires exactly one argument");
+                std::process::exit(1);
+            }
+            if names.is_empty() {
+                for (key, val) in vars.iter() {
+                    println!("{}={}", key, val);
+                }
+            }
+            let mut missing = false;
+            for name in &names {
+                match vars.get(name) {
+                    Some(val) if noheader => println!("{}", val),
+                    Some(val) => println!("{}={}", name, val),
+                    None => {
+                        eprintln!("## Error: \"{}\" not defined", name);
+                        missing = true;
+                    }
+                }
+            }
+            if missing {
+                std::process::exit(1);
+            }
+        }
+        Command::FwSetenv {
+            fw,
+            script,
+            name,
+            value,
+        } => {
+            let changes = match (script, name) {
+                (Some(script), _) => {
+                    let text = if is_stdio(&script) {
+                        String::from_utf8(stdin_bytes()?.to_vec())?
+                    } else {
+                        std::fs::read_to_string(&script)?
+                    };
+                    parse_fw_script(&text)
+                }
+                (None, Some(name)) => {
+                    vec![(name, Some(value.join(" ")).filter(|val| !val.is_empty()))]
+                }
+                (None, None) => unreachable!("clap requires a name or a script"),
+            };
+            let env = fw.env_args()?;
+            let mut vars = read_file(env.file(), &env.layout.to_layout())?;
+            for (name, val) in changes {
+                match val {
+                    Some(val) => {
+                        vars.set(&name, &val)?;
+                    }
+                    None => {
+                        vars.remove(&name);
+                    }
+                }
+            }
+            let write = WriteArgs {
+                output: None,
+                in_place: true,
+                backup_whole: false,
+                no_backup: true,
+                dry_run: false,
+                yes: true,
+                verify_write: false,
+                sorted: false,
+                alternate: true,
+                plain: false,
+            };
+            write_env(vars, &env, &write)?;
+        }
+        Command::Completions { shell } => {
+            let mut cmd = Cli::command();
+            let name = cmd.get_name().to_string();
+            clap_complete::generate(shell, &mut cmd, name, &mut std::io::stdout());
+        }
+        Command::Set {
+            env,
+            write,
+            key,
+            value,
+            append,
+            prepend,
+            hex,
+        } => {
+            if hex {
+                let bytes = parse_hex(&value)?;
+                let mut vars = read_file(env.file(), &env.layout.to_layout())?;
+                vars.set_bytes(key, &bytes)?;
+                return write_env(vars, &env, &write);
+            }
+            let op = match (append, prepend) {
+                (true, _) => Op::Append(key, value),
+                (_, true) => Op::Prepend(key, value),
+                _ => Op::Set(key, value),
+            };
+            let mut vars = read_file(env.file(), &env.layout.to_layout())?;
+            apply_ops(&mut vars, vec![op], false)?;
+            write_env(vars, &env, &write)?;
+        }
+        Command::Delete {
+            env,
+            write,
+            key,
+            ignore_missing,
+        } => {
+            let mut vars = read_file(env.file(), &env.layout.to_layout())?;
+            apply_ops(&mut vars, vec![Op::Delete(key)], ignore_missing)?;
+            write_env(vars, &env, &write)?;
+        }
+        Command::Rename {
+            env,
+            write,
+            old,
+            new,
+            overwrite,
+        } => {
+            let mut vars = read_file(env.file(), &env.layout.to_layout())?;
+            let op = Op::Rename {
+                from: old,
+                to: new,
+                overwrite,
+            };
+            apply_ops(&mut vars, vec![op], false)?;
+            write_env(vars, &env, &write)?;
+        }
+        Command::Clear {
+            env,
+            write,
+            defaults,
+        } => {
+            let vars = match defaults {
+                Some(path) => text_to_env(&std::fs::read_to_string(path)?)?,
+                None => Env::new(),
+            };
+            write_env(vars, &env, &write)?;
+        }
+        Command::Patch { env, write, ops } => {
+            let ignore_missing = ops.ignore_missing;
+            let ops = ops.ops()?;
+
+            let mut vars = read_file(env.file(), &env.layout.to_layout())?;
+            apply_ops(&mut vars, ops, ignore_missing)?;
+            write_env(vars, &env, &write)?;
+        }
+        Command::BootScript {
+            env,
+            ops,
+            output,
+            name,
+            arch,
+            no_saveenv,
+            text,
+        } => {
+            let ignore_missing = ops.ignore_missing;
+            let ops = ops.ops()?;
+
+            let old = read_file(env.file(), &env.layout.to_layout())?;
+            let mut new = old.clone();
+            apply_ops(&mut new, ops, ignore_missing)?;
+            let changes = old.diff(&new);
+            if changes.is_empty() {
+                return Err(fail!(Invalid, "the operations change nothing"));
+            }
+            let commands = changes_to_commands(&changes, !no_saveenv);
+            let out = if text {
+                console_commands(&commands).into_bytes()
+            } else {
+                let script = format!("# generated by uboot-patcher\n{}\n", commands.join("\n"));
+                debug!("script:\n{}", script);
+                script_image(&script, &name, arch)
+            };
+            if is_stdio(&output) {
+                std::io::stdout().write_all(&out)?;
+            } else {
+                std::fs::write(&output, out)?;
+            }
+            info!("{} changes", changes.len());
+        }
+        Command::Batch {
+            mut files,
+            globs,
+            layout,
+            ops,
+            output_dir,
+            in_place,
+            backup_whole,
+            dry_run,
+            yes,
+            verify_write,
+            sorted,
+            alternate,
+        } => {
+            for pattern in globs {
+                let mut matched: Vec<PathBuf> = glob::glob(&pattern)?.collect::<Result<_, _>>()?;
+                if matched.is_empty() {
+                    warn!("{} matched no files", pattern);
+                }
+                matched.sort();
+                files.extend(matched);
+            }
+            if files.is_empty() {
+                return Err(fail!(Invalid, "no images given, use --file or --glob"));
+            }
+
+            let ignore_missing = ops.ignore_missing;
+            let ops = ops.ops()?;
+
+            if !yes && !dry_run {
+                let offset = layout.offset_arg.or(layout
+                    .offset_sectors
+                    .map(|sectors| Offset::Start(sectors * 512)));
+                let size = match layout.size {
+                    Some(size) => format!("size {:#x}", size),
+                    None => "size found from the crc".to_string(),
+                };
+                let copies = match layout.format {
+                    Some(Format::Single) => ", single copy",
+                    Some(Format::Redundant) => ", redundant",
+                    _ => "",
+                };
+                let described = match (layout.format, layout.env_index, offset, &layout.partition) {
+                    (Some(Format::File), ..) => "uboot.env files".to_string(),
+                    (_, Some(index), ..) => {
+                        format!("images, the environment numbered {} by envs in each", index)
+                    }
+                    (_, _, Some(offset), Some(partition)) => format!(
+                        "images at offset {} in partition {}, {}{}",
+                        offset, partition, size, copies
+                    ),
+                    (_, _, None, Some(partition)) => format!(
+                        "images at the start of partition {}, {}{}",
+                        partition, size, copies
+                    ),
+                    (_, _, Some(offset), None) => {
+                        format!("images at offset {}, {}{}", offset, size, copies)
+                    }
+                    (_, _, None, None) => match layout.size {
+                        Some(size) => format!(
+                            "images, searching each for an environment of size {:#x}{}",
+                            size, copies
+                        ),
+                        None => format!(
+                            "images, searching each for its environment{} (or as a uboot.env \
+                             if it looks like one)",
+                            copies
+                        ),
+                    },
+                };
+                eprintln!("about to patch {} {}", files.len(), described);
+                ask_to_continue()?;
+            }
+
+            // keep going past failures so one bad dump doesn't stop the run,
+            // and report everything at the end
+            let mut failed = Vec::new();
+            for file in &files {
+                let output = output_dir
+                    .as_ref()
+                    .map(|dir| dir.join(file.file_name().unwrap_or(file.as_os_str())));
+                let mut env = EnvArgs {
+                    file: Some(file.clone()),
+                    config: None,
+                    dtb: None,
+                    layout: layout.clone(),
+                };
+                let write = WriteArgs {
+                    output,
+                    in_place,
+                    backup_whole,
+                    no_backup: false,
+                    dry_run,
+                    yes: true,
+                    verify_write,
+                    sorted,
+                    alternate,
+                    plain: false,
+                };
+
+                if dry_run {
+                    println!("{}:", file.display());
+                }
+                let result = env
+                    .resolve()
+                    .and_then(|()| read_file(env.file(), &env.layout.to_layout()))
+                    .and_then(|mut vars| {
+                        apply_ops(&mut vars, ops.clone(), ignore_missing)?;
+                        write_env(vars, &env, &write)
+                    });
+                match result {
+                    Ok(()) => eprintln!("ok      {}", file.display()),
+                    Err(e) => {
+                        eprintln!("FAILED  {}: {:#}", file.display(), e);
+                        failed.push((file, e));
+                    }
+                }
+            }
+
+            eprintln!(
+                "{} of {} images ok",
+                files.len() - failed.len(),
+                files.len()
+            );
+            if let Some((_, e)) = failed.into_iter().next() {
+                return Err(e.context("not every image was patched"));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mkenvimage_matches_fixture() {
+        let text = b"# comment\nbootcmd=run a\n\nmsg=one\\\ntwo\nbaud=115200";
+        let image = mkenvimage(text, 0x40, true, Endian::Big, 0xff).unwrap();
+
+        let mut expected = vec![0x19, 0xe7, 0x9a, 0xa4, 1];
+        expected.extend_from_slice(b"bootcmd=run a\0msg=one\ntwo\0baud=115200\0\0");
+        expected.resize(0x40, 0xff);
+        assert_eq!(image, expected);
+    }
+
+    #[test]
+    fn mkenvimage_single_little_endian() {
+        let image = mkenvimage(b"a=1\nb=2\n", 0x10, false, Endian::Little, 0).unwrap();
+        assert_eq!(image, b"\xe7\xcc\x25\xbea=1\0b=2\0\0\0\0\0");
+    }
+
+    #[test]
+    fn export_text_escapes_like_u_boot() {
+        let mut env = Env::new();
+        env.set("b", "one\ntwo").unwrap();
+        env.set("a", "c:\\boot").unwrap();
+        assert_eq!(env_to_export_text(&env), "a=c:\\\\boot\nb=one\\\ntwo\n\0");
+    }
+
+    #[test]
+    fn append_over_a_value_that_isnt_utf8() {
+        let mut vars = Env::new();
+        vars.set_bytes("k", b"\xff").unwrap();
+        let ops = vec![
+            Op::Append("k".into(), "a".into()),
+            Op::Prepend("k".into(), "b".into()),
+        ];
+        apply_ops(&mut vars, ops, false).unwrap();
+        assert_eq!(vars.get_bytes("k"), Some(&b"b\xffa"[..]));
+        assert_eq!(vars.raw_entries().count(), 1);
+        assert_eq!(vars.to_data().unwrap(), b"k=b\xffa\0\0");
+    }
+
+    #[test]
+    fn packings_round_trip() {
+        let image: Vec<u8> = (0..0x10000u32).map(|i| (i / 7) as u8).collect();
+        for packing in [
+            Packing::Gzip,
+            Packing::Xz,
+            Packing::Zstd,
+            Packing::Sparse(0x1000),
+        ] {
+            let packed = packing.pack(&image).unwrap();
+            assert_eq!(Packing::detect(&packed), Some(packing));
+            assert_eq!(packing.unpack(&packed).unwrap(), image, "{}", packing);
+        }
+    }
+
+    #[test]
+    fn fw_flags_count_on_nand() {
+        assert!(fw_flags(None, true) == Flags::Counter);
+        assert!(fw_flags(Some(Flags::Boolean), true) == Flags::Boolean);
+    }
+
+    #[test]
+    fn fw_flags_are_boolean_elsewhere() {
+        assert!(fw_flags(None, false) == Flags::Boolean);
+        assert!(fw_flags(Some(Flags::Counter), false) == Flags::Counter);
+    }
+
+    #[test]
+    fn format_names_the_layout_except_on_export_and_import() {
+        let cli = Cli::try_parse_from(["uboot-patcher", "print", "-f", "a", "--format", "single"])
+            .unwrap();
+        let Some(Command::Print { env }) = cli.command else {
+            panic!("not print");
+        };
+        assert!(env.layout.format == Some(Format::Single));
+
+        let cli = Cli::try_parse_from([
+            "uboot-patcher",
+            "export",
+            "-f",
+            "a",
+            "--format",
+            "uenv",
+            "--layout",
+            "single",
+            "-",
+        ])
+        .unwrap();
+        let Some(Command::Export {
+            env, text_format, ..
+        }) = cli.command
+        else {
+            panic!("not export");
+        };
+        assert!(env.layout.format == Some(Format::Single));
+        assert!(matches!(text_format, TextFormat::Uenv));
+    }
+
+    #[test]
+    fn uenv_txt_parses_like_env_import() {
+        let text =
+            "# boot\r\n  bootargs=console=ttyS0 \\\\\r\nuenvcmd=echo a;\\\n echo b\nempty=\nbare\n";
+        let vars = uenv_to_env(text).unwrap();
+        assert_eq!(vars.get("bootargs"), Some("console=ttyS0 \\"));
+        assert_eq!(vars.get("uenvcmd"), Some("echo a;\n echo b"));
+        assert_eq!(vars.len(), 2);
+        assert_eq!(uenv_to_env(&env_to_uenv(&vars)).unwrap(), vars);
+
+        assert!(uenv_to_env("my var=1\n").is_err());
+    }
+
+    #[test]
+    fn default_env_is_the_run_of_entries_with_u_boot_keys() {
+        let mut image = vec![0xaa; 0x40];
+        image.extend(b"\0usage: a=b\0ver=1\0\0");
+        let at = image.len();
+        image.extend(b"bootcmd=run x\0bootdelay=3\0baudrate=115200\0\0");
+        image.resize(0x200, 0xff);
+        let (offset, vars) = find_default_env(&image).unwrap();
+        assert_eq!(offset, at);
+        assert_eq!(vars.get("bootdelay"), Some("3"));
+        assert_eq!(vars.len(), 3);
+
+        // a stored copy has a good crc in front, and isn't the default
+        let mut image = vec![0xaa; 0x1000];
+        image.extend(
+            mkenvimage(
+                b"a=1\nbootcmd=run x\nbootdelay=3\n",
+                0x1000,
+                false,
+                Endian::Little,
+                0,
+            )
+            .unwrap(),
+        );
+        assert!(find_default_env(&image).is_none());
+        assert!(find_default_env(b"\0\0no=env\0here").is_none());
+    }
+
+    #[test]
+    fn printenv_capture_parses() {
+        let text = "U-Boot 2023.04\r\n=> printenv\r\nbaudrate=115200\r\nscript=echo a\r\necho b\r\nbootdelay=3\r\n\r\nEnvironment size: 60/8188 bytes\r\n=> ";
+        let vars = printenv_to_env(text).unwrap();
+        assert_eq!(vars.get("baudrate"), Some("115200"));
+        assert_eq!(vars.get("script"), Some("echo a\necho b"));
+        assert_eq!(vars.len(), 3);
+
+        assert!(printenv_to_env("=> printenv\nnot a variable\nbaudrate=115200\n").is_err());
+        assert!(printenv_to_env("=> printenv\n\nEnvironment size: 0/8188 bytes\n").is_err());
+    }
+
+    #[test]
+    fn md_dump_of_an_environment_parses() {
+        let mut image = mkenvimage(
+            b"bootdelay=3\nbaudrate=115200\n",
+            0x100,
+            false,
+            Endian::Little,
+            0,
+        )
+        .unwrap();
+        image.extend([0xaa; 0x20]);
+        let mut text = "=> md.b 0x80000000 0x120\r\n".to_string();
+        for (i, line) in image.chunks(16).enumerate() {
+            let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+            text.push_str(&format!(
+                "{:08x}: {}    ................\r\n",
+                0x8000_0000 + i * 16,
+                hex.join(" ")
+            ));
+        }
+        text.push_str("=> ");
+        let bytes = md_to_bytes(&text).unwrap();
+        assert_eq!(bytes, image);
+        let vars = env_from_dump(&bytes, &EnvLayout::new(0, 0x100)).unwrap();
+        assert_eq!(vars.get("baudrate"), Some("115200"));
+        assert_eq!(vars.len(), 2);
+
+        let gap = "80000000: 01 02    ..\n80000010: 03 04    ..\n";
+        assert!(md_to_bytes(gap).is_err());
+        assert!(md_to_bytes("80000000: 04030201 08070605    ........\n").is_err());
+        assert!(md_to_bytes("=> md.b 0x80000000\n").is_err());
+        assert!(env_from_dump(&[0xff; 0x100], &EnvLayout::new(0, 0x100)).is_err());
+    }
+
+    #[test]
+    fn script_image_has_a_legacy_header() {
+        let image = script_image("setenv a 1\nsaveenv\n", "changes", ScriptArch::Arm64);
+        let word = |at: usize| u32::from_be_bytes(image[at..at + 4].try_into().unwrap());
+        assert_eq!(word(0), 0x2705_1956);
+        assert_eq!(word(12) as usize, image.len() - 64);
+        assert_eq!(word(24), Crc::CRC32.checksum(&image[64..]));
+        assert_eq!(image[28..32], [5, 22, 6, 0]);
+        assert_eq!(&image[32..40], b"changes\0");
+        assert_eq!(word(64), 19);
+        assert_eq!(word(68), 0);
+        assert_eq!(&image[72..], b"setenv a 1\nsaveenv\n");
+
+        let mut header = image[..64].to_vec();
+        header[4..8].fill(0);
+        assert_eq!(word(4), Crc::CRC32.checksum(&header));
+
+        // names past mkimage's 32 bytes are cut short
+        let image = script_image("", &"n".repeat(40), ScriptArch::Arm);
+        assert_eq!(image.len(), 64 + 8);
+        assert_eq!(&image[32..64], "n".repeat(32).as_bytes());
+    }
+
+    /// the layout options args give print
+    fn layout_args(args: &[&str]) -> LayoutArgs {
+        let args = ["uboot-patcher", "print", "-f", "img"].iter().chain(args);
+        let Some(Command::Print { env }) = Cli::try_parse_from(args).unwrap().command else {
+            panic!("not print");
+        };
+        env.layout
+    }
+
+    /// a redundant environment of two 4K copies at 0x1000, the second of them
+    /// blank, and a single 8K one at 0x4000
+    fn two_envs() -> Vec<u8> {
+        let text = b"bootdelay=3\nbootcmd=run x\n";
+        let mut image = vec![0xff; 0x1000];
+        image.extend(mkenvimage(text, 0x1000, true, Endian::Little, 0).unwrap());
+        image.resize(0x4000, 0xff);
+        image.extend(mkenvimage(text, 0x2000, false, Endian::Little, 0).unwrap());
+        image.resize(0x8000, 0xff);
+        image
+    }
+
+    /// path to a new file in the temp dir holding bytes
+    fn temp_image(name: &str, bytes: &[u8]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("uboot-patcher-{}-{}", std::process::id(), name));
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn size_is_detected_from_the_crc() {
+        let path = temp_image("detect-size", &two_envs());
+        let mut layout = layout_args(&[]);
+        layout.offset = Some(0x1000);
+        let redundant = layout.detect_size(&path, 0x1000);
+        layout.offset = Some(0x4000);
+        let single = layout.detect_size(&path, 0x4000);
+        layout.offset = Some(0x6000);
+        let blank = layout.detect_size(&path, 0x6000);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(redundant, Ok((0x2000, Format::Redundant))));
+        assert!(matches!(single, Ok((0x2000, Format::Single))));
+        assert!(blank.is_err());
+    }
+
+    #[test]
+    fn every_environment_in_an_image_is_found() {
+        let path = temp_image("find-envs", &two_envs());
+        let found = layout_args(&[]).find_envs(&path);
+        let singles = layout_args(&["--layout", "single"]).find_envs(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let found: Vec<_> = found
+            .unwrap()
+            .iter()
+            .map(|f| (f.offset, f.size, f.format_name(), f.score))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (0x1000, 0x2000, "redundant", 4),
+                (0x4000, 0x2000, "single", 5)
+            ]
+        );
+        let singles: Vec<_> = singles.unwrap().iter().map(|f| f.offset).collect();
+        assert_eq!(singles, [0x4000]);
+
+        let path = temp_image("find-none", &[0xff; 0x8000]);
+        let none = layout_args(&[]).find_envs(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(none.unwrap().is_empty());
+    }
+
+    #[test]
+    fn mkenvimage_too_large() {
+        assert!(mkenvimage(b"a=1\nb=2\n", 0x0c, false, Endian::Little, 0).is_err());
+    }
 }
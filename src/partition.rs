@@ -0,0 +1,267 @@
+//! the partition tables at the start of a disk image, gpt or the mbr's
+//! four primary entries, for finding an environment partition by name or
+//! guid rather than by working out its offset
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// one partition of a disk
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Partition {
+    /// counting from 1, as linux numbers them
+    pub number: usize,
+    /// the gpt name, empty for mbr partitions
+    pub name: String,
+    /// what linux calls the PARTUUID: the unique guid of a gpt partition,
+    /// or the disk signature and number of an mbr one, as 1234abcd-01
+    pub uuid: String,
+    /// bytes from the start of the disk
+    pub offset: u64,
+    pub size: u64,
+}
+
+impl Partition {
+    /// whether the partition goes by id: its number, its name, or its
+    /// PARTUUID, case aside
+    pub fn is(&self, id: &str) -> bool {
+        id.parse() == Ok(self.number)
+            || (!self.name.is_empty() && id == self.name)
+            || id.eq_ignore_ascii_case(&self.uuid)
+    }
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(bytes.get(at..at + 8)?.try_into().ok()?))
+}
+
+/// a gpt guid as it's written out, its first three fields little-endian
+fn guid(bytes: &[u8]) -> String {
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        u16::from_le_bytes([bytes[4], bytes[5]]),
+        u16::from_le_bytes([bytes[6], bytes[7]]),
+        bytes[8],
+        bytes[9],
+        bytes[10],
+        bytes[11],
+        bytes[12],
+        bytes[13],
+        bytes[14],
+        bytes[15]
+    )
+}
+
+/// the partitions of the disk that starts with disk, which has to hold the
+/// whole of a gpt's entries (the first megabyte always does in practice).
+/// None if there's no partition table at all
+pub fn partitions(disk: &[u8]) -> Option<Vec<Partition>> {
+    gpt(disk).or_else(|| mbr(disk))
+}
+
+fn gpt(disk: &[u8]) -> Option<Vec<Partition>> {
+    // the header is in the second sector, whichever size sectors are
+    let sector = [512u64, 4096]
+        .into_iter()
+        .find(|&sector| disk.get(sector as usize..sector as usize + 8) == Some(b"EFI PART"))?;
+    let header = &disk[sector as usize..];
+    // none of the header can be trusted not to overflow
+    let entries_at = usize::try_from(u64_at(header, 72)?.checked_mul(sector)?).ok()?;
+    let count = u32_at(header, 80)? as usize;
+    let entry_len = u32_at(header, 84)? as usize;
+    if entry_len < 128 {
+        return None;
+    }
+
+    let mut found = Vec::new();
+    for i in 0..count {
+        let start = i.checked_mul(entry_len)?.checked_add(entries_at)?;
+        let Some(entry) = start
+            .checked_add(entry_len)
+            .and_then(|end| disk.get(start..end))
+        else {
+            break;
+        };
+        // an all-zero type is an unused entry
+        if entry[..16].iter().all(|&b| b == 0) {
+            continue;
+        }
+        let (first, last) = (u64_at(entry, 32)?, u64_at(entry, 40)?);
+        let name = char::decode_utf16(
+            entry[56..128]
+                .chunks(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .take_while(|&c| c != 0),
+        )
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect();
+        found.push(Partition {
+            number: i + 1,
+            name,
+            uuid: guid(&entry[16..32]),
+            offset: first.checked_mul(sector)?,
+            size: last
+                .checked_add(1)?
+                .saturating_sub(first)
+                .checked_mul(sector)?,
+        });
+    }
+    Some(found)
+}
+
+fn mbr(disk: &[u8]) -> Option<Vec<Partition>> {
+    if disk.get(510..512) != Some(&[0x55, 0xaa]) {
+        return None;
+    }
+    let signature = u32_at(disk, 440)?;
+    let found: Vec<Partition> = (0..4)
+        .filter_map(|i| {
+            let entry = &disk[446 + i * 16..446 + (i + 1) * 16];
+            let (start, sectors) = (u32_at(entry, 8)?, u32_at(entry, 12)?);
+            (entry[4] != 0 && sectors > 0).then(|| Partition {
+                number: i + 1,
+                name: String::new(),
+                uuid: format!("{:08x}-{:02x}", signature, i + 1),
+                offset: u64::from(start) * 512,
+                size: u64::from(sectors) * 512,
+            })
+        })
+        .collect();
+    // a boot sector of some other kind has the same 55 aa
+    (!found.is_empty()).then_some(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// linux filesystem data and the u-boot-env partition's guid, as
+    /// they're stored
+    const LINUX: [u8; 16] = [
+        0xaf, 0x3d, 0xc6, 0x0f, 0x83, 0x84, 0x72, 0x47, 0x8e, 0x79, 0x3d, 0x69, 0xd8, 0x47, 0x7d,
+        0xe4,
+    ];
+    const ENV_GUID: [u8; 16] = [
+        0x78, 0x56, 0x34, 0x12, 0xbc, 0x9a, 0xf0, 0xde, 0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd,
+        0xef,
+    ];
+
+    /// a disk of sector byte sectors with its gpt header in sector 1 and
+    /// four 128 byte entries from sector 2: an unused one, then boot at
+    /// sectors 0x40-0x7f and uboot-env at 0x80-0x87
+    fn gpt_disk(sector: usize) -> Vec<u8> {
+        let mut disk = vec![0; sector * 2 + 4 * 128];
+        let header = &mut disk[sector..];
+        header[..8].copy_from_slice(b"EFI PART");
+        header[72..80].copy_from_slice(&2u64.to_le_bytes());
+        header[80..84].copy_from_slice(&4u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        for (i, (guid, first, last, name)) in [
+            (&[0x11; 16], 0x40u64, 0x7fu64, "boot"),
+            (&ENV_GUID, 0x80, 0x87, "uboot-env"),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let entry = &mut disk[sector * 2 + (i + 1) * 128..][..128];
+            entry[..16].copy_from_slice(&LINUX);
+            entry[16..32].copy_from_slice(guid);
+            entry[32..40].copy_from_slice(&first.to_le_bytes());
+            entry[40..48].copy_from_slice(&last.to_le_bytes());
+            for (j, c) in name.encode_utf16().enumerate() {
+                entry[56 + j * 2..58 + j * 2].copy_from_slice(&c.to_le_bytes());
+            }
+        }
+        disk
+    }
+
+    fn find<'a>(partitions: &'a [Partition], id: &str) -> Option<&'a Partition> {
+        partitions.iter().find(|p| p.is(id))
+    }
+
+    #[test]
+    fn gpt_by_name_guid_and_number() {
+        for sector in [512, 4096] {
+            let partitions = partitions(&gpt_disk(sector)).unwrap();
+            assert_eq!(partitions.len(), 2);
+            let env = find(&partitions, "uboot-env").unwrap();
+            assert_eq!(env.number, 3);
+            assert_eq!(env.uuid, "12345678-9abc-def0-0123-456789abcdef");
+            assert_eq!(
+                (env.offset, env.size),
+                (0x80 * sector as u64, 8 * sector as u64)
+            );
+            assert_eq!(
+                find(&partitions, "12345678-9ABC-DEF0-0123-456789ABCDEF"),
+                Some(env)
+            );
+            assert_eq!(find(&partitions, "3"), Some(env));
+            assert_eq!(find(&partitions, "2").unwrap().name, "boot");
+            assert_eq!(find(&partitions, "1"), None);
+        }
+    }
+
+    #[test]
+    fn gpt_fields_that_overflow() {
+        let set = |at: usize, bytes: &[u8]| {
+            let mut disk = gpt_disk(512);
+            disk[at..at + bytes.len()].copy_from_slice(bytes);
+            partitions(&disk)
+        };
+        // the entries' sector, and the entries' length times their count
+        assert_eq!(set(512 + 72, &u64::MAX.to_le_bytes()), None);
+        assert_eq!(
+            set(512 + 72, &(u64::MAX / 512).to_le_bytes()),
+            Some(Vec::new())
+        );
+        let entries = set(512 + 80, &u32::MAX.to_le_bytes()).unwrap();
+        assert_eq!(entries.len(), 2);
+        // a partition's first and last sectors
+        assert_eq!(set(1024 + 128 + 32, &u64::MAX.to_le_bytes()), None);
+        assert_eq!(set(1024 + 128 + 40, &u64::MAX.to_le_bytes()), None);
+    }
+
+    /// an mbr with signature 1234abcd and a fat partition second, at
+    /// sector 0x800 for 0x1000 sectors
+    fn mbr_disk() -> Vec<u8> {
+        let mut disk = vec![0; 512];
+        disk[440..444].copy_from_slice(&0x1234_abcdu32.to_le_bytes());
+        let entry = &mut disk[446 + 16..446 + 32];
+        entry[4] = 0x0c;
+        entry[8..12].copy_from_slice(&0x800u32.to_le_bytes());
+        entry[12..16].copy_from_slice(&0x1000u32.to_le_bytes());
+        disk[510..512].copy_from_slice(&[0x55, 0xaa]);
+        disk
+    }
+
+    #[test]
+    fn mbr_by_number_and_partuuid() {
+        let partitions = partitions(&mbr_disk()).unwrap();
+        assert_eq!(
+            partitions,
+            [Partition {
+                number: 2,
+                name: String::new(),
+                uuid: "1234abcd-02".into(),
+                offset: 0x10_0000,
+                size: 0x20_0000,
+            }]
+        );
+        assert_eq!(find(&partitions, "2"), Some(&partitions[0]));
+        assert_eq!(find(&partitions, "1234ABCD-02"), Some(&partitions[0]));
+        assert_eq!(find(&partitions, ""), None);
+    }
+
+    #[test]
+    fn no_table() {
+        assert_eq!(partitions(&[0; 1024]), None);
+        let mut disk = mbr_disk();
+        disk[446 + 16 + 4] = 0;
+        assert_eq!(partitions(&disk), None);
+    }
+}
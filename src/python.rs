@@ -0,0 +1,152 @@
+//! the uboot_patcher python module. `maturin build` (or `pip install .`)
+//! builds it with the python feature, as the cdylib in python/
+//!
+//! ```python
+//! import uboot_patcher
+//!
+//! env = uboot_patcher.Env.load("dump.bin", offset=0x210000, size=0x20000)
+//! env["bootdelay"] = "5"
+//! env.save("dump.bin", offset=0x210000, size=0x20000)
+//! ```
+
+use std::path::PathBuf;
+
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyKeyError};
+use pyo3::prelude::*;
+
+use crate::{storage, EnvError, EnvLayout, EnvWriter};
+
+create_exception!(
+    uboot_patcher,
+    Error,
+    PyException,
+    "a bad crc, a full environment, or unparseable data"
+);
+
+fn to_py(e: EnvError) -> PyErr {
+    match e {
+        EnvError::Io(e) => e.into(),
+        e => Error::new_err(e.to_string()),
+    }
+}
+
+/// the variables of an environment, in order
+#[pyclass(name = "Env", module = "uboot_patcher")]
+struct PyEnv {
+    env: crate::Env,
+}
+
+#[pymethods]
+impl PyEnv {
+    /// an environment holding vars, if given
+    #[new]
+    #[pyo3(signature = (vars=None))]
+    fn new(vars: Option<Vec<(String, String)>>) -> PyResult<PyEnv> {
+        let mut env = crate::Env::new();
+        for (key, val) in vars.unwrap_or_default() {
+            env.set(key, val).map_err(to_py)?;
+        }
+        Ok(PyEnv { env })
+    }
+
+    /// parse the bytes of a redundant environment
+    #[staticmethod]
+    fn parse(bytes: &[u8]) -> PyResult<PyEnv> {
+        let env = crate::Env::parse(bytes).map_err(to_py)?;
+        Ok(PyEnv { env })
+    }
+
+    /// read and parse the environment at offset in an image file or device
+    #[staticmethod]
+    fn load(path: PathBuf, offset: u64, size: usize) -> PyResult<PyEnv> {
+        let env = storage::read_env(&path, &EnvLayout::new(offset, size)).map_err(to_py)?;
+        Ok(PyEnv { env })
+    }
+
+    /// serialize as a redundant environment of size bytes
+    #[pyo3(signature = (size, sorted=false))]
+    fn to_bytes(&self, size: usize, sorted: bool) -> PyResult<Vec<u8>> {
+        let bytes = if sorted {
+            self.env.to_sorted_bytes(size)
+        } else {
+            self.env.to_bytes(size)
+        };
+        bytes.map_err(to_py)
+    }
+
+    /// write over the environment at offset in an image file or device.
+    /// nothing is backed up first
+    #[pyo3(signature = (path, offset, size, sorted=false))]
+    fn save(&self, path: PathBuf, offset: u64, size: usize, sorted: bool) -> PyResult<()> {
+        let writer = EnvWriter {
+            sorted,
+            ..EnvWriter::new(EnvLayout::new(offset, size))
+        };
+        storage::write_env(&path, &self.env, &writer).map_err(to_py)
+    }
+
+    #[pyo3(signature = (key, default=None))]
+    fn get(&self, key: &str, default: Option<String>) -> Option<String> {
+        self.env.get(key).map(str::to_owned).or(default)
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.env.keys().map(str::to_owned).collect()
+    }
+
+    fn items(&self) -> Vec<(String, String)> {
+        self.env
+            .iter()
+            .map(|(k, v)| (k.to_owned(), v.to_owned()))
+            .collect()
+    }
+
+    fn __getitem__(&self, key: &str) -> PyResult<String> {
+        self.env
+            .get(key)
+            .map(str::to_owned)
+            .ok_or_else(|| PyKeyError::new_err(key.to_owned()))
+    }
+
+    fn __setitem__(&mut self, key: String, val: String) -> PyResult<()> {
+        self.env.set(key, val).map(drop).map_err(to_py)
+    }
+
+    fn __delitem__(&mut self, key: &str) -> PyResult<()> {
+        self.env
+            .remove(key)
+            .map(drop)
+            .ok_or_else(|| PyKeyError::new_err(key.to_owned()))
+    }
+
+    fn __contains__(&self, key: &str) -> bool {
+        self.env.contains_key(key)
+    }
+
+    fn __len__(&self) -> usize {
+        self.env.len()
+    }
+
+    fn __iter__(&self) -> PyResult<Py<PyAny>> {
+        Python::attach(|py| {
+            let keys = pyo3::types::PyList::new(py, self.keys())?;
+            Ok(keys.try_iter()?.into_any().unbind())
+        })
+    }
+
+    fn __eq__(&self, other: &PyEnv) -> bool {
+        self.env == other.env
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Env({:?})", self.items())
+    }
+}
+
+#[pymodule]
+fn uboot_patcher(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyEnv>()?;
+    m.add("Error", m.py().get_type::<Error>())?;
+    Ok(())
+}
@@ -0,0 +1,298 @@
+//! android sparse images (simg), the format fastboot flashes and many
+//! emmc dumps ship as: a header, then chunks of raw blocks, blocks filled
+//! with one repeated word, and blocks left as they were. expand turns one
+//! into the plain image and encode turns a plain image back into one
+
+use alloc::vec::Vec;
+
+/// the first four bytes of a sparse image, little-endian
+pub const MAGIC: u32 = 0xed26_ff3a;
+
+const FILE_HEADER_LEN: usize = 28;
+const CHUNK_HEADER_LEN: usize = 12;
+const CHUNK_RAW: u16 = 0xcac1;
+const CHUNK_FILL: u16 = 0xcac2;
+const CHUNK_DONT_CARE: u16 = 0xcac3;
+const CHUNK_CRC32: u16 = 0xcac4;
+
+/// what's wrong with a sparse image that can't be expanded
+#[derive(Debug, thiserror::Error)]
+pub enum SparseError {
+    /// the header isn't one expand knows, or is cut short
+    #[error("not a version 1 android sparse image")]
+    BadHeader,
+    /// chunk runs past the end of the file
+    #[error("sparse chunk {chunk} is cut short")]
+    Truncated { chunk: usize },
+    /// chunk's type isn't raw, fill, don't care or crc32
+    #[error("sparse chunk {chunk} has unknown type {kind:#06x}")]
+    UnknownChunk { chunk: usize, kind: u16 },
+    /// the chunks don't add up to the blocks the header says there are
+    #[error("sparse chunks hold {found} blocks, the header says {expected}")]
+    WrongLength { expected: u32, found: u64 },
+    /// the header's blocks come to more than can be held in memory
+    #[error("sparse image expands to {len} bytes, too large to hold in memory")]
+    TooLarge { len: u64 },
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+/// whether bytes start like a sparse image
+pub fn is_sparse(bytes: &[u8]) -> bool {
+    bytes.len() >= 4 && u32_at(bytes, 0) == MAGIC
+}
+
+/// the block size sparse counts its chunks in, to encode it again the same
+/// way
+pub fn block_size(sparse: &[u8]) -> Option<usize> {
+    (is_sparse(sparse) && sparse.len() >= FILE_HEADER_LEN).then(|| u32_at(sparse, 12) as usize)
+}
+
+/// the plain image sparse stands for. blocks it doesn't care about come
+/// out as zeroes, and crc32 chunks aren't checked
+pub fn expand(sparse: &[u8]) -> Result<Vec<u8>, SparseError> {
+    if !is_sparse(sparse) || sparse.len() < FILE_HEADER_LEN || u16_at(sparse, 4) != 1 {
+        return Err(SparseError::BadHeader);
+    }
+    let header_len = u16_at(sparse, 8) as usize;
+    let chunk_header_len = u16_at(sparse, 10) as usize;
+    let block = u32_at(sparse, 12) as usize;
+    let total_blocks = u32_at(sparse, 16);
+    let chunks = u32_at(sparse, 20) as usize;
+    // fill chunks repeat a word, so blocks come in whole words. encode
+    // relies on it when the image is written back
+    if header_len < FILE_HEADER_LEN
+        || chunk_header_len < CHUNK_HEADER_LEN
+        || block == 0
+        || !block.is_multiple_of(4)
+    {
+        return Err(SparseError::BadHeader);
+    }
+
+    // all of it up front, so a header claiming terabytes fails here rather
+    // than aborting on an allocation later. the chunks are held to it
+    let len = u64::from(total_blocks) * block as u64;
+    let mut image = Vec::new();
+    usize::try_from(len)
+        .ok()
+        .and_then(|len| image.try_reserve_exact(len).ok())
+        .ok_or(SparseError::TooLarge { len })?;
+    let mut blocks = 0u32;
+    let mut at = header_len;
+    for chunk in 0..chunks {
+        let header = sparse
+            .get(at..at + chunk_header_len)
+            .ok_or(SparseError::Truncated { chunk })?;
+        let kind = u16_at(header, 0);
+        let count = u32_at(header, 4);
+        let total_len = (u32_at(header, 8) as usize).max(chunk_header_len);
+        // more blocks than the header says would grow past what's reserved
+        blocks = blocks
+            .checked_add(count)
+            .filter(|&blocks| blocks <= total_blocks)
+            .ok_or(SparseError::WrongLength {
+                expected: total_blocks,
+                found: u64::from(blocks) + u64::from(count),
+            })?;
+        let body = sparse
+            .get(at + chunk_header_len..at + total_len)
+            .ok_or(SparseError::Truncated { chunk })?;
+        let len = count as usize * block;
+        match kind {
+            CHUNK_RAW => {
+                image.extend_from_slice(body.get(..len).ok_or(SparseError::Truncated { chunk })?)
+            }
+            CHUNK_FILL => {
+                let word = body.get(..4).ok_or(SparseError::Truncated { chunk })?;
+                image.extend(word.iter().cycle().take(len));
+            }
+            CHUNK_DONT_CARE => image.resize(image.len() + len, 0),
+            CHUNK_CRC32 => {}
+            kind => return Err(SparseError::UnknownChunk { chunk, kind }),
+        }
+        at += total_len;
+    }
+    if blocks != total_blocks {
+        return Err(SparseError::WrongLength {
+            expected: total_blocks,
+            found: u64::from(blocks),
+        });
+    }
+    Ok(image)
+}
+
+/// image as a sparse image of block-sized blocks (a multiple of 4, usually
+/// 4096), with runs of blocks that repeat one word as fill chunks and the
+/// rest as raw ones. a last partial block is padded with zeroes
+pub fn encode(image: &[u8], block: usize) -> Vec<u8> {
+    debug_assert!(
+        block > 0 && block.is_multiple_of(4),
+        "sparse blocks of {} bytes aren't whole words",
+        block
+    );
+    let fill_word = |b: &[u8]| {
+        if b.len() != block {
+            return None;
+        }
+        let word = &b[..4];
+        b.chunks(4).all(|w| w == word).then(|| u32_at(word, 0))
+    };
+    // runs of blocks, each Some(word) for a fill or None for raw
+    let mut runs: Vec<(Option<u32>, usize, usize)> = Vec::new();
+    for (i, b) in image.chunks(block).enumerate() {
+        let fill = fill_word(b);
+        match runs.last_mut() {
+            Some((last, _, count)) if *last == fill => *count += 1,
+            _ => runs.push((fill, i, 1)),
+        }
+    }
+
+    let total_blocks = image.len().div_ceil(block);
+    let mut out = Vec::with_capacity(image.len() / 2);
+    out.extend(MAGIC.to_le_bytes());
+    out.extend(1u16.to_le_bytes());
+    out.extend(0u16.to_le_bytes());
+    out.extend((FILE_HEADER_LEN as u16).to_le_bytes());
+    out.extend((CHUNK_HEADER_LEN as u16).to_le_bytes());
+    out.extend((block as u32).to_le_bytes());
+    out.extend((total_blocks as u32).to_le_bytes());
+    out.extend((runs.len() as u32).to_le_bytes());
+    // no image checksum, as img2simg leaves it
+    out.extend(0u32.to_le_bytes());
+    for (fill, start, count) in runs {
+        let body_len = match fill {
+            Some(_) => 4,
+            None => count * block,
+        };
+        out.extend(
+            match fill {
+                Some(_) => CHUNK_FILL,
+                None => CHUNK_RAW,
+            }
+            .to_le_bytes(),
+        );
+        out.extend(0u16.to_le_bytes());
+        out.extend((count as u32).to_le_bytes());
+        out.extend(((CHUNK_HEADER_LEN + body_len) as u32).to_le_bytes());
+        match fill {
+            Some(word) => out.extend(word.to_le_bytes()),
+            None => {
+                let data = &image[start * block..image.len().min((start + count) * block)];
+                out.extend_from_slice(data);
+                out.resize(out.len() + count * block - data.len(), 0);
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a sparse header for total_blocks blocks of block bytes in chunks
+    /// chunks
+    fn header(block: u32, total_blocks: u32, chunks: u32) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend(MAGIC.to_le_bytes());
+        out.extend(1u16.to_le_bytes());
+        out.extend(0u16.to_le_bytes());
+        out.extend(28u16.to_le_bytes());
+        out.extend(12u16.to_le_bytes());
+        out.extend(block.to_le_bytes());
+        out.extend(total_blocks.to_le_bytes());
+        out.extend(chunks.to_le_bytes());
+        out.extend(0u32.to_le_bytes());
+        out
+    }
+
+    fn chunk(kind: u16, count: u32, body: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend(kind.to_le_bytes());
+        out.extend(0u16.to_le_bytes());
+        out.extend(count.to_le_bytes());
+        out.extend((12 + body.len() as u32).to_le_bytes());
+        out.extend_from_slice(body);
+        out
+    }
+
+    /// two raw blocks around two filled with 01 02 03 04, in 8-byte blocks
+    const IMAGE: &[u8] =
+        b"abcdefgh\x01\x02\x03\x04\x01\x02\x03\x04\x01\x02\x03\x04\x01\x02\x03\x04ijklmnop";
+    #[rustfmt::skip]
+    const SPARSE: &[u8] = &[
+        // magic, version 1.0, header lengths, 8-byte blocks, 4 blocks, 3 chunks, no checksum
+        0x3a, 0xff, 0x26, 0xed, 1, 0, 0, 0, 28, 0, 12, 0,
+        8, 0, 0, 0, 4, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0,
+        // raw, 1 block
+        0xc1, 0xca, 0, 0, 1, 0, 0, 0, 20, 0, 0, 0,
+        b'a', b'b', b'c', b'd', b'e', b'f', b'g', b'h',
+        // fill, 2 blocks
+        0xc2, 0xca, 0, 0, 2, 0, 0, 0, 16, 0, 0, 0, 1, 2, 3, 4,
+        // raw, 1 block
+        0xc1, 0xca, 0, 0, 1, 0, 0, 0, 20, 0, 0, 0,
+        b'i', b'j', b'k', b'l', b'm', b'n', b'o', b'p',
+    ];
+
+    #[test]
+    fn encode_matches_fixture() {
+        assert_eq!(encode(IMAGE, 8), SPARSE);
+        assert_eq!(block_size(SPARSE), Some(8));
+    }
+
+    #[test]
+    fn expand_matches_fixture() {
+        assert_eq!(expand(SPARSE).unwrap(), IMAGE);
+    }
+
+    #[test]
+    fn round_trip_pads_the_last_block() {
+        let image = b"\0\0\0\0\0\0\0\0tail";
+        let mut padded = image.to_vec();
+        padded.resize(16, 0);
+        assert_eq!(expand(&encode(image, 8)).unwrap(), padded);
+    }
+
+    #[test]
+    fn dont_care_and_crc_chunks() {
+        let mut sparse = header(8, 2, 3);
+        sparse.extend(chunk(CHUNK_RAW, 1, b"abcdefgh"));
+        sparse.extend(chunk(CHUNK_DONT_CARE, 1, &[]));
+        sparse.extend(chunk(CHUNK_CRC32, 0, &[0xde, 0xad, 0xbe, 0xef]));
+        assert_eq!(expand(&sparse).unwrap(), b"abcdefgh\0\0\0\0\0\0\0\0");
+    }
+
+    #[test]
+    fn blocks_that_arent_whole_words_are_an_error() {
+        for block in [0, 2, 6] {
+            let mut sparse = header(block, 1, 1);
+            sparse.extend(chunk(CHUNK_DONT_CARE, 1, &[]));
+            assert!(matches!(expand(&sparse), Err(SparseError::BadHeader)));
+        }
+    }
+
+    #[test]
+    fn huge_header_is_an_error() {
+        let sparse = header(0xffff_f000, u32::MAX, 0);
+        assert!(matches!(expand(&sparse), Err(SparseError::TooLarge { .. })));
+    }
+
+    #[test]
+    fn chunks_past_the_header_are_an_error() {
+        let mut sparse = header(4096, 1, 1);
+        sparse.extend(chunk(CHUNK_DONT_CARE, u32::MAX, &[]));
+        assert!(matches!(
+            expand(&sparse),
+            Err(SparseError::WrongLength {
+                expected: 1,
+                found: 0xffff_ffff
+            })
+        ));
+    }
+}
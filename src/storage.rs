@@ -0,0 +1,1313 @@
+//! where environments are read from and written to. the codec only ever
+//! sees byte slices; an EnvStorage turns offsets into reads and writes on a
+//! particular kind of medium, and Progress reads and writes whole
+//! environments through one while reporting how it's going. EnvSource and
+//! EnvSink pick a storage for in-memory images, paths and open files alike
+
+use std::borrow::Cow;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{Env, EnvLayout, EnvWriter, RegionWrite, Result};
+
+/// a medium holding an environment somewhere within it
+pub trait EnvStorage {
+    /// len bytes starting at offset
+    fn read_region(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>>;
+
+    /// write bytes at offset. on media that need it the region has to have
+    /// been erased first
+    fn write_region(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()>;
+
+    /// make len bytes at offset ready to be written. nothing to do unless
+    /// the medium is flash
+    fn erase(&mut self, _offset: u64, _len: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// the size of the blocks erase works in, if it only works in whole
+    /// ones
+    fn erase_block(&self) -> Option<u64> {
+        None
+    }
+
+    /// what writes should come in whole multiples of, if anything. Progress
+    /// rounds its chunks up to it, so nand gets whole pages and a ubi
+    /// volume, which is rewritten whole on every write, gets a region at
+    /// once
+    fn write_size(&self) -> Option<u64> {
+        None
+    }
+
+    /// total size of the medium in bytes
+    fn size(&mut self) -> io::Result<u64>;
+}
+
+fn read_at(file: &mut File, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; len];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn write_at(file: &mut File, offset: u64, bytes: &[u8]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(bytes)?;
+    file.sync_all()
+}
+
+/// an image file, such as a flash dump
+pub struct FileStorage {
+    file: File,
+}
+
+impl FileStorage {
+    pub fn new(file: File) -> FileStorage {
+        FileStorage { file }
+    }
+}
+
+impl EnvStorage for FileStorage {
+    fn read_region(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        read_at(&mut self.file, offset, len)
+    }
+
+    fn write_region(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        write_at(&mut self.file, offset, bytes)
+    }
+
+    fn size(&mut self) -> io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+}
+
+/// a block device like /dev/mmcblk0 or /dev/sdb. reads and writes are of
+/// whole sectors into aligned buffers, the rest of a sector read back
+/// around a write that doesn't fill it, so the file can be opened with
+/// O_DIRECT (as open does) and what's written reaches the device
+pub struct BlockDevice {
+    file: File,
+    sector: u64,
+    /// kept for setting force_ro again once the file's closed, which
+    /// declaring it after file does
+    #[cfg(target_os = "linux")]
+    _force_ro: Option<ForceRo>,
+}
+
+/// a block device's force_ro, cleared for writing to it, as emmc boot
+/// partitions need, and set again when this is dropped
+#[cfg(target_os = "linux")]
+struct ForceRo(PathBuf);
+
+#[cfg(target_os = "linux")]
+impl ForceRo {
+    /// clear path's force_ro if it's a block device with it set
+    fn clear(path: &Path) -> io::Result<Option<ForceRo>> {
+        use std::os::unix::fs::FileTypeExt;
+
+        if !std::fs::metadata(path)?.file_type().is_block_device() {
+            return Ok(None);
+        }
+        let name = std::fs::canonicalize(path)?;
+        let Some(name) = name.file_name() else {
+            return Ok(None);
+        };
+        let sysfs = Path::new("/sys/class/block").join(name).join("force_ro");
+        match std::fs::read_to_string(&sysfs) {
+            Ok(set) if set.trim() == "1" => {}
+            _ => return Ok(None),
+        }
+        std::fs::write(&sysfs, "0").map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!(
+                    "{} is read-only and its {} can't be cleared ({}); run as root, \
+                     or `echo 0 > {}` first",
+                    path.display(),
+                    sysfs.display(),
+                    e,
+                    sysfs.display()
+                ),
+            )
+        })?;
+        log::info!("cleared {} to write, it's set again after", sysfs.display());
+        Ok(Some(ForceRo(sysfs)))
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for ForceRo {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::write(&self.0, "1") {
+            log::warn!("couldn't set {} again: {}", self.0.display(), e);
+        }
+    }
+}
+
+/// what O_DIRECT buffers are aligned to, enough for any sector size
+const DIRECT_ALIGN: usize = 4096;
+
+/// len bytes of an over-allocated buffer, starting where it's aligned for
+/// O_DIRECT
+struct AlignedBuf {
+    buf: Vec<u8>,
+    start: usize,
+    len: usize,
+}
+
+impl AlignedBuf {
+    fn new(len: usize) -> AlignedBuf {
+        let buf = vec![0; len + DIRECT_ALIGN];
+        let start = buf.as_ptr().align_offset(DIRECT_ALIGN);
+        AlignedBuf { buf, start, len }
+    }
+
+    fn bytes(&mut self) -> &mut [u8] {
+        &mut self.buf[self.start..self.start + self.len]
+    }
+}
+
+impl BlockDevice {
+    /// the sector size is the device's logical one, or 512 if it can't be
+    /// asked
+    pub fn new(file: File) -> BlockDevice {
+        #[cfg(target_os = "linux")]
+        let sector = logical_sector_size(&file).unwrap_or(512);
+        #[cfg(not(target_os = "linux"))]
+        let sector = 512;
+        BlockDevice {
+            file,
+            sector,
+            #[cfg(target_os = "linux")]
+            _force_ro: None,
+        }
+    }
+
+    /// the whole sectors holding len bytes at offset: where they start,
+    /// and their contents
+    fn read_sectors(&mut self, offset: u64, len: usize) -> io::Result<(u64, AlignedBuf)> {
+        let start = offset / self.sector * self.sector;
+        let end = (offset + len as u64).div_ceil(self.sector) * self.sector;
+        let mut buf = AlignedBuf::new((end - start) as usize);
+        self.file.seek(SeekFrom::Start(start))?;
+        self.file.read_exact(buf.bytes())?;
+        Ok((start, buf))
+    }
+}
+
+/// BLKSSZGET's answer for file
+#[cfg(target_os = "linux")]
+fn logical_sector_size(file: &File) -> Option<u64> {
+    use std::os::fd::AsRawFd;
+
+    // _IO(0x12, 104)
+    const BLKSSZGET: u32 = 0x1268;
+    let mut size: libc::c_int = 0;
+    // SAFETY: BLKSSZGET writes an int
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(), BLKSSZGET as _, &mut size) };
+    (ret >= 0 && size > 0).then_some(size as u64)
+}
+
+impl EnvStorage for BlockDevice {
+    fn read_region(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let (start, mut buf) = self.read_sectors(offset, len)?;
+        let at = (offset - start) as usize;
+        Ok(buf.bytes()[at..at + len].to_vec())
+    }
+
+    fn write_region(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        let aligned =
+            offset.is_multiple_of(self.sector) && (bytes.len() as u64).is_multiple_of(self.sector);
+        let (start, mut buf) = if aligned {
+            (offset, AlignedBuf::new(bytes.len()))
+        } else {
+            self.read_sectors(offset, bytes.len())?
+        };
+        let at = (offset - start) as usize;
+        buf.bytes()[at..at + bytes.len()].copy_from_slice(bytes);
+        write_at(&mut self.file, start, buf.bytes())
+    }
+
+    fn size(&mut self) -> io::Result<u64> {
+        // metadata says 0 for devices, ask where the end is instead
+        self.file.seek(SeekFrom::End(0))
+    }
+}
+
+/// a linux mtd character device like /dev/mtd0, i.e. raw flash. blocks
+/// are unlocked before they're erased, as fw_setenv does, and locked again
+/// when it's dropped if they were locked to begin with
+#[cfg(target_os = "linux")]
+pub struct Mtd {
+    file: File,
+    info: mtd::MtdInfo,
+    /// blocks this unlocked, as start and length
+    unlocked: Vec<(u32, u32)>,
+}
+
+#[cfg(target_os = "linux")]
+mod mtd {
+    use std::fs::File;
+    use std::io;
+    use std::os::fd::AsRawFd;
+
+    /// struct mtd_info_user from <mtd/mtd-abi.h>
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct MtdInfo {
+        pub kind: u8,
+        pub flags: u32,
+        pub size: u32,
+        pub erasesize: u32,
+        pub writesize: u32,
+        pub oobsize: u32,
+        pub padding: u64,
+    }
+
+    /// struct erase_info_user
+    #[repr(C)]
+    struct EraseInfo {
+        start: u32,
+        length: u32,
+    }
+
+    // _IOR('M', 1, struct mtd_info_user) and _IOW('M', 2, struct erase_info_user)
+    const MEMGETINFO: u32 = 0x8020_4d01;
+    const MEMERASE: u32 = 0x4008_4d02;
+    // _IOW('M', 5 and 6, struct erase_info_user), _IOW('M', 11, loff_t) and
+    // _IOR('M', 23, struct erase_info_user)
+    const MEMLOCK: u32 = 0x4008_4d05;
+    const MEMUNLOCK: u32 = 0x4008_4d06;
+    const MEMGETBADBLOCK: u32 = 0x4008_4d0b;
+    const MEMISLOCKED: u32 = 0x8008_4d17;
+
+    /// MTD_NANDFLASH and MTD_MLCNANDFLASH
+    pub fn is_nand(info: &MtdInfo) -> bool {
+        matches!(info.kind, 4 | 8)
+    }
+
+    pub fn get_info(file: &File) -> io::Result<MtdInfo> {
+        let mut info = MtdInfo::default();
+        // SAFETY: MEMGETINFO fills in exactly a struct mtd_info_user
+        let ret = unsafe { libc::ioctl(file.as_raw_fd(), MEMGETINFO as _, &mut info) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(info)
+    }
+
+    /// one of the ioctls taking a struct erase_info_user, returning what
+    /// it does
+    fn blocks_ioctl(file: &File, request: u32, start: u32, length: u32) -> io::Result<i32> {
+        let erase = EraseInfo { start, length };
+        // SAFETY: each of these only reads a struct erase_info_user
+        let ret = unsafe { libc::ioctl(file.as_raw_fd(), request as _, &erase) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ret)
+    }
+
+    pub fn erase(file: &File, start: u32, length: u32) -> io::Result<()> {
+        blocks_ioctl(file, MEMERASE, start, length).map(drop)
+    }
+
+    /// whether any of the blocks are locked. chips without locking say
+    /// they aren't
+    pub fn is_locked(file: &File, start: u32, length: u32) -> bool {
+        blocks_ioctl(file, MEMISLOCKED, start, length).is_ok_and(|locked| locked > 0)
+    }
+
+    pub fn unlock(file: &File, start: u32, length: u32) -> io::Result<()> {
+        blocks_ioctl(file, MEMUNLOCK, start, length).map(drop)
+    }
+
+    pub fn lock(file: &File, start: u32, length: u32) -> io::Result<()> {
+        blocks_ioctl(file, MEMLOCK, start, length).map(drop)
+    }
+
+    /// whether the nand block at offset is marked bad
+    pub fn is_bad(file: &File, offset: u64) -> io::Result<bool> {
+        let offset = offset as libc::loff_t;
+        // SAFETY: MEMGETBADBLOCK only reads the loff_t
+        let ret = unsafe { libc::ioctl(file.as_raw_fd(), MEMGETBADBLOCK as _, &offset) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ret > 0)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Mtd {
+    /// fails if file isn't an mtd device
+    pub fn new(file: File) -> io::Result<Mtd> {
+        let info = mtd::get_info(&file)?;
+        Ok(Mtd {
+            file,
+            info,
+            unlocked: Vec::new(),
+        })
+    }
+
+    /// fail if any block of len bytes at offset is a bad nand block. they
+    /// aren't skipped the way u-boot can, so the environment has to be
+    /// given somewhere without any
+    fn check_blocks(&self, offset: u64, len: usize) -> io::Result<()> {
+        if !mtd::is_nand(&self.info) {
+            return Ok(());
+        }
+        let block = u64::from(self.info.erasesize.max(1));
+        let end = offset + len as u64;
+        let mut at = offset / block * block;
+        while at < end {
+            if mtd::is_bad(&self.file, at)? {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("the nand block at {:#x} is marked bad", at),
+                ));
+            }
+            at += block;
+        }
+        Ok(())
+    }
+
+    /// the size of the blocks erase works in
+    pub fn erase_size(&self) -> u32 {
+        self.info.erasesize
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl EnvStorage for Mtd {
+    fn read_region(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        read_at(&mut self.file, offset, len)
+    }
+
+    /// nand is only written whole pages at a time, into erased blocks; a
+    /// partial write, like clearing an old copy's flag, is refused
+    fn write_region(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        let page = u64::from(self.info.writesize.max(1));
+        if mtd::is_nand(&self.info)
+            && (!offset.is_multiple_of(page) || !(bytes.len() as u64).is_multiple_of(page))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{:#x} bytes at {:#x} isn't whole {:#x} byte nand pages",
+                    bytes.len(),
+                    offset,
+                    page
+                ),
+            ));
+        }
+        write_at(&mut self.file, offset, bytes)
+    }
+
+    /// erase whole blocks. a region that doesn't start and end on an erase
+    /// block boundary is refused rather than taking its neighbours with it
+    fn erase(&mut self, offset: u64, len: usize) -> io::Result<()> {
+        let block = u64::from(self.info.erasesize.max(1));
+        if !offset.is_multiple_of(block) || !(len as u64).is_multiple_of(block) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{:#x} bytes at {:#x} isn't aligned to the {:#x} byte erase blocks",
+                    len, offset, block
+                ),
+            ));
+        }
+        self.check_blocks(offset, len)?;
+        let too_big = || io::Error::new(io::ErrorKind::InvalidInput, "region past end of mtd");
+        let start = offset.try_into().map_err(|_| too_big())?;
+        let length = len.try_into().map_err(|_| too_big())?;
+        if mtd::is_locked(&self.file, start, length) {
+            self.unlocked.push((start, length));
+        }
+        // like fw_setenv, unlock regardless: chips that can't say whether
+        // they're locked may still be, and ones without locking refuse
+        let _ = mtd::unlock(&self.file, start, length);
+        mtd::erase(&self.file, start, length)
+    }
+
+    fn erase_block(&self) -> Option<u64> {
+        Some(u64::from(self.info.erasesize))
+    }
+
+    /// whole pages on nand, anything on nor
+    fn write_size(&self) -> Option<u64> {
+        mtd::is_nand(&self.info).then(|| u64::from(self.info.writesize.max(1)))
+    }
+
+    fn size(&mut self) -> io::Result<u64> {
+        Ok(u64::from(self.info.size))
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Mtd {
+    fn drop(&mut self) {
+        for &(start, length) in &self.unlocked {
+            // nothing to be done about it failing now, the write went fine
+            let _ = mtd::lock(&self.file, start, length);
+        }
+    }
+}
+
+/// a ubi volume like /dev/ubi0_1. ubi moves the volume's data around the
+/// flash underneath, so rather than writing in place, which would be
+/// written over, every write is a volume update replacing all of it
+#[cfg(target_os = "linux")]
+pub struct UbiVolume {
+    file: File,
+    size: u64,
+}
+
+#[cfg(target_os = "linux")]
+mod ubi {
+    use std::fs::File;
+    use std::io;
+    use std::os::fd::AsRawFd;
+    use std::path::{Path, PathBuf};
+
+    // _IOW('O', 0, __s64)
+    const UBI_IOCVOLUP: u32 = 0x4008_4f00;
+
+    /// the size of the ubi volume at path, or None if it isn't one
+    pub fn volume_size(path: &Path) -> Option<u64> {
+        let name = std::fs::canonicalize(path).ok()?;
+        let sysfs = Path::new("/sys/class/ubi").join(name.file_name()?);
+        std::fs::read_to_string(sysfs.join("data_bytes"))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    }
+
+    /// the /dev/ubiN_M for a path naming a volume as /dev/ubiN:NAME, the
+    /// way u-boot and libubootenv do, or None for any other path, and for
+    /// a file that really is called that
+    pub fn by_name(path: &Path) -> io::Result<Option<PathBuf>> {
+        let Some((device, volume)) = path
+            .to_str()
+            .and_then(|path| path.strip_prefix("/dev/"))
+            .and_then(|name| name.split_once(':'))
+            .filter(|(device, _)| {
+                device
+                    .strip_prefix("ubi")
+                    .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+            })
+            .filter(|_| !path.exists())
+        else {
+            return Ok(None);
+        };
+        let prefix = format!("{}_", device);
+        let entries = std::fs::read_dir("/sys/class/ubi").map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("no ubi devices to find {} on: {}", device, e),
+            )
+        })?;
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name();
+            if !name.to_string_lossy().starts_with(&prefix) {
+                continue;
+            }
+            let found = std::fs::read_to_string(entry.path().join("name"))?;
+            if found.trim_end_matches('\n') == volume {
+                return Ok(Some(Path::new("/dev").join(name)));
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} has no volume named {:?}", device, volume),
+        ))
+    }
+
+    /// start a volume update of len bytes, which the next len bytes
+    /// written make up
+    pub fn start_update(file: &File, len: u64) -> io::Result<()> {
+        let len = len as i64;
+        // SAFETY: UBI_IOCVOLUP only reads the s64
+        let ret = unsafe { libc::ioctl(file.as_raw_fd(), UBI_IOCVOLUP as _, &len) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl EnvStorage for UbiVolume {
+    fn read_region(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        read_at(&mut self.file, offset, len)
+    }
+
+    /// the whole volume as it is with bytes at offset, as one update
+    fn write_region(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        let end = offset + bytes.len() as u64;
+        if end > self.size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{:#x} bytes at {:#x} run past the end of the {:#x} byte ubi volume",
+                    bytes.len(),
+                    offset,
+                    self.size
+                ),
+            ));
+        }
+        let mut volume = read_at(&mut self.file, 0, self.size as usize)?;
+        volume[offset as usize..end as usize].copy_from_slice(bytes);
+        ubi::start_update(&self.file, self.size)?;
+        write_at(&mut self.file, 0, &volume)
+    }
+
+    /// all of it, so that writing a region is one update
+    fn write_size(&self) -> Option<u64> {
+        Some(self.size)
+    }
+
+    fn size(&mut self) -> io::Result<u64> {
+        Ok(self.size)
+    }
+}
+
+/// the mtd character device an mtdblock device is a view of, or None for
+/// any other block device. mtdblock writes by reading, erasing and
+/// rewriting whole blocks in its cache, so an interrupted write on nor can
+/// lose far more than the environment; the character device is written
+/// instead
+#[cfg(target_os = "linux")]
+fn mtd_for_mtdblock(file: &File) -> io::Result<Option<PathBuf>> {
+    use std::os::unix::fs::MetadataExt;
+
+    const MTD_BLOCK_MAJOR: u64 = 31;
+    let rdev = file.metadata()?.rdev();
+    // glibc's major() and minor()
+    let major = ((rdev >> 8) & 0xfff) | ((rdev >> 32) & !0xfff);
+    let minor = (rdev & 0xff) | ((rdev >> 12) & !0xff);
+    Ok((major == MTD_BLOCK_MAJOR).then(|| PathBuf::from(format!("/dev/mtd{}", minor))))
+}
+
+/// whether path is a nand mtd device, whose redundant environment fw_env
+/// marks with a counter rather than 1 and 0
+pub fn is_nand(path: &Path) -> bool {
+    #[cfg(target_os = "linux")]
+    if let Ok(info) = File::open(path).and_then(|file| mtd::get_info(&file)) {
+        return mtd::is_nand(&info);
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = path;
+    false
+}
+
+/// open path with the storage that suits it: UbiVolume for ubi volumes
+/// (/dev/ubiN:NAME picking one by name), mtd for mtd character devices (and
+/// for writing to mtdblock ones), BlockDevice for block
+/// devices, FileStorage for anything else
+pub fn open(path: &Path, writable: bool) -> io::Result<Box<dyn EnvStorage>> {
+    #[cfg(target_os = "linux")]
+    let by_name = ubi::by_name(path)?;
+    #[cfg(target_os = "linux")]
+    let path = by_name.as_deref().unwrap_or(path);
+    // a block device kept read-only, like an emmc boot partition, is made
+    // writable only while it's open
+    #[cfg(target_os = "linux")]
+    let force_ro = match writable {
+        true => ForceRo::clear(path)?,
+        false => None,
+    };
+    let file = OpenOptions::new().read(true).write(writable).open(path)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+
+        let kind = file.metadata()?.file_type();
+        #[cfg(target_os = "linux")]
+        if kind.is_char_device() {
+            if let Some(size) = ubi::volume_size(path) {
+                return Ok(Box::new(UbiVolume { file, size }));
+            }
+            let mtd = Mtd::new(file).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("{} isn't an mtd device: {}", path.display(), e),
+                )
+            })?;
+            return Ok(Box::new(mtd));
+        }
+        if kind.is_block_device() {
+            #[cfg(target_os = "linux")]
+            if let (true, Some(char_dev)) = (writable, mtd_for_mtdblock(&file)?) {
+                log::info!("writing {} through {}", path.display(), char_dev.display());
+                let mtd = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(&char_dev)
+                    .and_then(Mtd::new)
+                    .map_err(|e| {
+                        io::Error::new(
+                            e.kind(),
+                            format!(
+                                "{} is an mtdblock device, which can lose whole erase blocks \
+                                 when written to, and {} that writes go through instead \
+                                 can't be opened: {}",
+                                path.display(),
+                                char_dev.display(),
+                                e
+                            ),
+                        )
+                    })?;
+                return Ok(Box::new(mtd));
+            }
+            // bypass the page cache where the device allows it, so an sd
+            // card can be pulled as soon as this is done
+            #[cfg(target_os = "linux")]
+            let file = {
+                use std::os::unix::fs::OpenOptionsExt;
+
+                OpenOptions::new()
+                    .read(true)
+                    .write(writable)
+                    .custom_flags(libc::O_DIRECT)
+                    .open(path)
+                    .unwrap_or(file)
+            };
+            #[cfg(target_os = "linux")]
+            return Ok(Box::new(BlockDevice {
+                _force_ro: force_ro,
+                ..BlockDevice::new(file)
+            }));
+            #[cfg(not(target_os = "linux"))]
+            return Ok(Box::new(BlockDevice::new(file)));
+        }
+    }
+    Ok(Box::new(FileStorage::new(file)))
+}
+
+impl<S: EnvStorage + ?Sized> EnvStorage for &mut S {
+    fn read_region(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        (**self).read_region(offset, len)
+    }
+
+    fn write_region(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        (**self).write_region(offset, bytes)
+    }
+
+    fn erase(&mut self, offset: u64, len: usize) -> io::Result<()> {
+        (**self).erase(offset, len)
+    }
+
+    fn erase_block(&self) -> Option<u64> {
+        (**self).erase_block()
+    }
+
+    fn write_size(&self) -> Option<u64> {
+        (**self).write_size()
+    }
+
+    fn size(&mut self) -> io::Result<u64> {
+        (**self).size()
+    }
+}
+
+impl<S: EnvStorage + ?Sized> EnvStorage for Box<S> {
+    fn read_region(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        (**self).read_region(offset, len)
+    }
+
+    fn write_region(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        (**self).write_region(offset, bytes)
+    }
+
+    fn erase(&mut self, offset: u64, len: usize) -> io::Result<()> {
+        (**self).erase(offset, len)
+    }
+
+    fn erase_block(&self) -> Option<u64> {
+        (**self).erase_block()
+    }
+
+    fn write_size(&self) -> Option<u64> {
+        (**self).write_size()
+    }
+
+    fn size(&mut self) -> io::Result<u64> {
+        (**self).size()
+    }
+}
+
+/// the range len bytes at offset covers in an image of image_len bytes
+fn mem_range(offset: u64, len: usize, image_len: usize) -> io::Result<std::ops::Range<usize>> {
+    usize::try_from(offset)
+        .ok()
+        .and_then(|start| Some(start..start.checked_add(len)?))
+        .filter(|range| range.end <= image_len)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "image is too short"))
+}
+
+/// an image in memory that can only be read
+struct MemSource<'a>(&'a [u8]);
+
+impl EnvStorage for MemSource<'_> {
+    fn read_region(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        Ok(self.0[mem_range(offset, len, self.0.len())?].to_vec())
+    }
+
+    fn write_region(&mut self, _offset: u64, _bytes: &[u8]) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "image is read-only",
+        ))
+    }
+
+    fn size(&mut self) -> io::Result<u64> {
+        Ok(self.0.len() as u64)
+    }
+}
+
+/// an image in memory edited in place. it can't grow, so writes past the
+/// end fail
+struct MemSink<'a>(&'a mut [u8]);
+
+impl EnvStorage for MemSink<'_> {
+    fn read_region(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        Ok(self.0[mem_range(offset, len, self.0.len())?].to_vec())
+    }
+
+    fn write_region(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        let range = mem_range(offset, bytes.len(), self.0.len())?;
+        self.0[range].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn size(&mut self) -> io::Result<u64> {
+        Ok(self.0.len() as u64)
+    }
+}
+
+/// anywhere an environment can be read from: an image already in memory, a
+/// path to open, an open file, or any other storage
+pub enum EnvSource<'a> {
+    Bytes(&'a [u8]),
+    Path(&'a Path),
+    File(File),
+    Storage(Box<dyn EnvStorage + 'a>),
+}
+
+impl<'a> EnvSource<'a> {
+    /// the storage to read through, opening a path read-only
+    pub fn open(self) -> io::Result<Box<dyn EnvStorage + 'a>> {
+        Ok(match self {
+            EnvSource::Bytes(bytes) => Box::new(MemSource(bytes)),
+            EnvSource::Path(path) => open(path, false)?,
+            EnvSource::File(file) => Box::new(FileStorage::new(file)),
+            EnvSource::Storage(storage) => storage,
+        })
+    }
+}
+
+impl<'a> From<&'a [u8]> for EnvSource<'a> {
+    fn from(bytes: &'a [u8]) -> EnvSource<'a> {
+        EnvSource::Bytes(bytes)
+    }
+}
+
+impl<'a> From<&'a Vec<u8>> for EnvSource<'a> {
+    fn from(bytes: &'a Vec<u8>) -> EnvSource<'a> {
+        EnvSource::Bytes(bytes)
+    }
+}
+
+impl<'a> From<&'a Path> for EnvSource<'a> {
+    fn from(path: &'a Path) -> EnvSource<'a> {
+        EnvSource::Path(path)
+    }
+}
+
+impl<'a> From<&'a PathBuf> for EnvSource<'a> {
+    fn from(path: &'a PathBuf) -> EnvSource<'a> {
+        EnvSource::Path(path)
+    }
+}
+
+impl From<File> for EnvSource<'_> {
+    fn from(file: File) -> Self {
+        EnvSource::File(file)
+    }
+}
+
+impl<'a, S: EnvStorage + 'a> From<&'a mut S> for EnvSource<'a> {
+    fn from(storage: &'a mut S) -> EnvSource<'a> {
+        EnvSource::Storage(Box::new(storage))
+    }
+}
+
+/// anywhere an environment can be written back to, the writable
+/// counterpart of EnvSource. a buffer is edited in place and can't grow
+pub enum EnvSink<'a> {
+    Bytes(&'a mut [u8]),
+    Path(&'a Path),
+    File(File),
+    Storage(Box<dyn EnvStorage + 'a>),
+}
+
+impl<'a> EnvSink<'a> {
+    /// the storage to read and write through, opening a path read-write
+    pub fn open(self) -> io::Result<Box<dyn EnvStorage + 'a>> {
+        Ok(match self {
+            EnvSink::Bytes(bytes) => Box::new(MemSink(bytes)),
+            EnvSink::Path(path) => open(path, true)?,
+            EnvSink::File(file) => Box::new(FileStorage::new(file)),
+            EnvSink::Storage(storage) => storage,
+        })
+    }
+}
+
+impl<'a> From<&'a mut [u8]> for EnvSink<'a> {
+    fn from(bytes: &'a mut [u8]) -> EnvSink<'a> {
+        EnvSink::Bytes(bytes)
+    }
+}
+
+impl<'a> From<&'a mut Vec<u8>> for EnvSink<'a> {
+    fn from(bytes: &'a mut Vec<u8>) -> EnvSink<'a> {
+        EnvSink::Bytes(bytes)
+    }
+}
+
+impl<'a> From<&'a Path> for EnvSink<'a> {
+    fn from(path: &'a Path) -> EnvSink<'a> {
+        EnvSink::Path(path)
+    }
+}
+
+impl<'a> From<&'a PathBuf> for EnvSink<'a> {
+    fn from(path: &'a PathBuf) -> EnvSink<'a> {
+        EnvSink::Path(path)
+    }
+}
+
+impl From<File> for EnvSink<'_> {
+    fn from(file: File) -> Self {
+        EnvSink::File(file)
+    }
+}
+
+impl<'a, S: EnvStorage + 'a> From<&'a mut S> for EnvSink<'a> {
+    fn from(storage: &'a mut S) -> EnvSink<'a> {
+        EnvSink::Storage(Box::new(storage))
+    }
+}
+
+/// the bytes of layout's region, read from wherever its spans are
+pub fn read_layout(storage: &mut dyn EnvStorage, layout: &EnvLayout) -> io::Result<Vec<u8>> {
+    let mut region = Vec::with_capacity(layout.size);
+    for (offset, span) in layout.spans() {
+        region.extend(storage.read_region(offset, span.len())?);
+    }
+    Ok(region)
+}
+
+/// bytes to write at offset, widened to the whole erase blocks they fall in
+/// (layout's sector size, or else the storage's) with what's around them
+/// read back, so erasing those blocks keeps it
+fn widen<'b>(
+    storage: &mut dyn EnvStorage,
+    layout: &EnvLayout,
+    offset: u64,
+    bytes: &'b [u8],
+) -> io::Result<(u64, Cow<'b, [u8]>)> {
+    let block = layout
+        .sector_size
+        .map(|size| size as u64)
+        .or_else(|| storage.erase_block())
+        .filter(|&block| block > 1);
+    let Some(block) = block else {
+        return Ok((offset, Cow::Borrowed(bytes)));
+    };
+    let start = offset / block * block;
+    let end = (offset + bytes.len() as u64)
+        .div_ceil(block)
+        .saturating_mul(block)
+        .min(storage.size()?.max(offset + bytes.len() as u64));
+    if (start, end) == (offset, offset + bytes.len() as u64) {
+        return Ok((offset, Cow::Borrowed(bytes)));
+    }
+    log::debug!(
+        "rewriting {:#x} bytes at {:#x} to keep the rest of the erase blocks",
+        end - start,
+        start
+    );
+    let mut widened = storage.read_region(start, (end - start) as usize)?;
+    let at = (offset - start) as usize;
+    widened[at..at + bytes.len()].copy_from_slice(bytes);
+    Ok((start, Cow::Owned(widened)))
+}
+
+/// make writes to layout's region, in order, erasing only where they say
+pub fn apply(
+    storage: &mut dyn EnvStorage,
+    layout: &EnvLayout,
+    writes: &[RegionWrite],
+) -> io::Result<()> {
+    for write in writes {
+        for (offset, bytes) in write.placed(layout) {
+            if write.erase {
+                let (offset, bytes) = widen(storage, layout, offset, bytes)?;
+                storage.erase(offset, bytes.len())?;
+                storage.write_region(offset, &bytes)?;
+            } else {
+                storage.write_region(offset, bytes)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// read and parse the environment in layout from source
+pub fn read_env<'a>(source: impl Into<EnvSource<'a>>, layout: &EnvLayout) -> Result<Env> {
+    let bytes = read_layout(&mut source.into().open()?, layout)?;
+    layout.parse(&bytes)
+}
+
+/// serialize env with writer and write it to sink where its layout says
+pub fn write_env<'a>(sink: impl Into<EnvSink<'a>>, env: &Env, writer: &EnvWriter) -> Result<()> {
+    let bytes = writer.write(env)?;
+    let mut storage = sink.into().open()?;
+    apply(&mut storage, &writer.layout, &[RegionWrite::whole(bytes)])?;
+    Ok(())
+}
+
+/// read the environment in layout from sink, change it with f, and write
+/// it back, returning what was written. nothing is written if f fails
+///
+/// ```
+/// use uboot_patcher::storage::patch_env;
+/// use uboot_patcher::{Env, EnvLayout};
+///
+/// let layout = EnvLayout::new(0x100, 0x400);
+/// let mut image = vec![0; 0x1000];
+/// image[0x100..0x500].copy_from_slice(&Env::new().to_bytes(0x400)?);
+/// patch_env(&mut image, &layout, |env| {
+///     env.set("bootdelay", "5")?;
+///     Ok(())
+/// })?;
+/// assert_eq!(layout.parse(&image[0x100..0x500])?.get("bootdelay"), Some("5"));
+/// # Ok::<(), uboot_patcher::EnvError>(())
+/// ```
+pub fn patch_env<'a>(
+    sink: impl Into<EnvSink<'a>>,
+    layout: &EnvLayout,
+    f: impl FnOnce(&mut Env) -> Result<()>,
+) -> Result<Env> {
+    let mut storage = sink.into().open()?;
+    let region = read_layout(&mut storage, layout)?;
+    let mut env = layout.parse(&region)?;
+    f(&mut env)?;
+    write_env(&mut storage, &env, &EnvWriter::replacing(*layout, &region))?;
+    Ok(env)
+}
+
+/// a step of reading or writing an environment, reported by Progress
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// done of total bytes read so far
+    Read { done: usize, total: usize },
+    /// the bytes read parsed into this many variables
+    Parsed { vars: usize },
+    /// the environment serialized and fits
+    Validated,
+    /// the region was erased, ready to write
+    Erased,
+    /// done of total bytes written so far
+    Written { done: usize, total: usize },
+    /// what was written read back the same
+    Verified,
+}
+
+/// reads and writes environments on storage a chunk at a time, telling a
+/// callback how far along it is. for frontends that want to show progress
+/// on slow media
+pub struct Progress<'a> {
+    /// bytes per read or write
+    pub chunk_size: usize,
+    /// read the region back after write_env and check it matches
+    pub verify: bool,
+    callback: Box<dyn FnMut(Event) + 'a>,
+}
+
+impl<'a> Progress<'a> {
+    /// 4 KiB chunks, or whole multiples of what the storage writes in, and
+    /// no verification
+    pub fn new(callback: impl FnMut(Event) + 'a) -> Progress<'a> {
+        Progress {
+            chunk_size: 0x1000,
+            verify: false,
+            callback: Box::new(callback),
+        }
+    }
+
+    /// len bytes in chunks of chunk_size, or of the next multiple of unit
+    /// if that's bigger
+    fn chunks(
+        &self,
+        len: usize,
+        unit: Option<u64>,
+    ) -> impl Iterator<Item = std::ops::Range<usize>> {
+        let unit = unit.map_or(1, |unit| usize::try_from(unit).unwrap_or(usize::MAX));
+        let step = self
+            .chunk_size
+            .max(1)
+            .checked_next_multiple_of(unit)
+            .unwrap_or(usize::MAX);
+        (0..len)
+            .step_by(step)
+            .map(move |start| start..len.min(start + step))
+    }
+
+    /// len bytes starting at offset
+    pub fn read_region(
+        &mut self,
+        storage: &mut dyn EnvStorage,
+        offset: u64,
+        len: usize,
+    ) -> io::Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(len);
+        for chunk in self.chunks(len, None) {
+            buf.extend(storage.read_region(offset + chunk.start as u64, chunk.len())?);
+            (self.callback)(Event::Read {
+                done: chunk.end,
+                total: len,
+            });
+        }
+        Ok(buf)
+    }
+
+    /// erase the region bytes will go in, then write them
+    pub fn write_region(
+        &mut self,
+        storage: &mut dyn EnvStorage,
+        offset: u64,
+        bytes: &[u8],
+    ) -> io::Result<()> {
+        storage.erase(offset, bytes.len())?;
+        (self.callback)(Event::Erased);
+        self.write_chunks(storage, offset, bytes)
+    }
+
+    /// layout's region, read from wherever its spans are
+    pub fn read_layout(
+        &mut self,
+        storage: &mut dyn EnvStorage,
+        layout: &EnvLayout,
+    ) -> io::Result<Vec<u8>> {
+        let mut region = Vec::with_capacity(layout.size);
+        for (offset, span) in layout.spans() {
+            region.extend(self.read_region(storage, offset, span.len())?);
+        }
+        Ok(region)
+    }
+
+    /// make writes to layout's region, in order, erasing only where they
+    /// say to
+    pub fn apply(
+        &mut self,
+        storage: &mut dyn EnvStorage,
+        layout: &EnvLayout,
+        writes: &[RegionWrite],
+    ) -> io::Result<()> {
+        for write in writes {
+            for (offset, bytes) in write.placed(layout) {
+                if write.erase {
+                    let (offset, bytes) = widen(storage, layout, offset, bytes)?;
+                    self.write_region(storage, offset, &bytes)?;
+                } else {
+                    self.write_chunks(storage, offset, bytes)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_chunks(
+        &mut self,
+        storage: &mut dyn EnvStorage,
+        offset: u64,
+        bytes: &[u8],
+    ) -> io::Result<()> {
+        for chunk in self.chunks(bytes.len(), storage.write_size()) {
+            storage.write_region(offset + chunk.start as u64, &bytes[chunk.clone()])?;
+            (self.callback)(Event::Written {
+                done: chunk.end,
+                total: bytes.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// read and parse the environment in layout
+    pub fn read_env(&mut self, storage: &mut dyn EnvStorage, layout: &EnvLayout) -> Result<Env> {
+        let region = self.read_layout(storage, layout)?;
+        let env = layout.parse(&region)?;
+        (self.callback)(Event::Parsed { vars: env.len() });
+        Ok(env)
+    }
+
+    /// serialize env with writer and write it where its layout says,
+    /// reading it back afterwards if verify is set
+    pub fn write_env(
+        &mut self,
+        storage: &mut dyn EnvStorage,
+        env: &Env,
+        writer: &EnvWriter,
+    ) -> Result<()> {
+        let bytes = writer.write(env)?;
+        (self.callback)(Event::Validated);
+        self.apply(
+            storage,
+            &writer.layout,
+            &[RegionWrite::whole(bytes.clone())],
+        )?;
+        if self.verify {
+            if self.read_layout(storage, &writer.layout)? != bytes {
+                return Err(io::Error::other("read-back verification failed").into());
+            }
+            (self.callback)(Event::Verified);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// storage in memory that writes in units of write_size, keeping the
+    /// length of every write
+    struct Paged {
+        image: Vec<u8>,
+        write_size: u64,
+        writes: Vec<usize>,
+    }
+
+    impl Paged {
+        fn new(len: usize, write_size: u64) -> Paged {
+            Paged {
+                image: vec![0xff; len],
+                write_size,
+                writes: Vec::new(),
+            }
+        }
+    }
+
+    impl EnvStorage for Paged {
+        fn read_region(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+            MemSource(&self.image).read_region(offset, len)
+        }
+
+        fn write_region(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+            if !offset.is_multiple_of(self.write_size)
+                || !(bytes.len() as u64).is_multiple_of(self.write_size)
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "not whole pages",
+                ));
+            }
+            self.writes.push(bytes.len());
+            MemSink(&mut self.image).write_region(offset, bytes)
+        }
+
+        fn write_size(&self) -> Option<u64> {
+            Some(self.write_size)
+        }
+
+        fn size(&mut self) -> io::Result<u64> {
+            Ok(self.image.len() as u64)
+        }
+    }
+
+    #[test]
+    fn chunks_are_whole_nand_pages() {
+        let mut nand = Paged::new(0x8000, 0x4000);
+        let bytes = vec![0x5a; 0x8000];
+        Progress::new(drop)
+            .write_region(&mut nand, 0, &bytes)
+            .unwrap();
+        assert_eq!(nand.writes, [0x4000, 0x4000]);
+        assert_eq!(nand.image, bytes);
+    }
+
+    #[test]
+    fn chunks_stay_small_on_small_pages() {
+        let mut nand = Paged::new(0x4000, 0x800);
+        Progress::new(drop)
+            .write_region(&mut nand, 0, &[0; 0x4000])
+            .unwrap();
+        assert_eq!(nand.writes, [0x1000; 4]);
+    }
+
+    /// a ubi volume in memory: every write replaces all of it, as
+    /// UbiVolume's updates do, and is counted
+    struct Volume {
+        image: Vec<u8>,
+        updates: usize,
+    }
+
+    impl EnvStorage for Volume {
+        fn read_region(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+            MemSource(&self.image).read_region(offset, len)
+        }
+
+        fn write_region(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+            let mut volume = self.image.clone();
+            MemSink(&mut volume).write_region(offset, bytes)?;
+            self.image = volume;
+            self.updates += 1;
+            Ok(())
+        }
+
+        fn write_size(&self) -> Option<u64> {
+            Some(self.image.len() as u64)
+        }
+
+        fn size(&mut self) -> io::Result<u64> {
+            Ok(self.image.len() as u64)
+        }
+    }
+
+    #[test]
+    fn a_region_is_one_ubi_update() {
+        let mut volume = Volume {
+            image: vec![0; 0x40000],
+            updates: 0,
+        };
+        let layout = EnvLayout::new(0, 0x20000);
+        let mut env = Env::new();
+        env.set("bootcmd", "run ubiboot").unwrap();
+        let writer = EnvWriter::new(layout);
+        Progress::new(drop)
+            .write_env(&mut volume, &env, &writer)
+            .unwrap();
+        assert_eq!(volume.updates, 1);
+        assert_eq!(read_env(&volume.image[..], &layout).unwrap(), env);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn only_dev_ubi_paths_name_volumes() {
+        for path in [
+            "ubi0:env",
+            "backups/ubi0:env",
+            "/dev/ubifoo:env",
+            "/dev/ubi:env",
+        ] {
+            assert_eq!(ubi::by_name(Path::new(path)).unwrap(), None, "{}", path);
+        }
+    }
+}
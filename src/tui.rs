@@ -0,0 +1,309 @@
+//! interactive full-screen environment editor
+
+use anyhow::Result;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+use uboot_patcher::{validate_key, Change, Env, EnvLayout, EnvSession};
+
+const HELP: &str =
+    "j/k move  / search  enter edit  a add  d delete  r revert  R revert all  u undo  U redo  w write  q quit";
+
+/// what keypresses currently do
+enum Mode {
+    Browse,
+    Search,
+    EditValue(String),
+    NewKey,
+    NewValue(String),
+    ConfirmQuit,
+}
+
+struct App {
+    session: EnvSession,
+    layout: EnvLayout,
+    filter: String,
+    list: ListState,
+    mode: Mode,
+    input: String,
+    message: Option<String>,
+}
+
+/// run the editor until the user quits. returns the edited environment if
+/// they chose to write it, None if they quit without writing
+pub fn run(hm: Env, layout: EnvLayout) -> Result<Option<Env>> {
+    let mut app = App {
+        session: EnvSession::new(hm),
+        layout,
+        filter: String::new(),
+        list: ListState::default().with_selected(Some(0)),
+        mode: Mode::Browse,
+        input: String::new(),
+        message: None,
+    };
+    ratatui::run(|terminal| app.run(terminal))
+}
+
+impl App {
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<Option<Env>> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            self.message = None;
+            if let Some(done) = self.handle_key(key.code) {
+                return Ok(done);
+            }
+        }
+    }
+
+    /// sorted keys matching the search filter
+    fn visible_keys(&self) -> Vec<&str> {
+        let filter = self.filter.to_lowercase();
+        let mut keys: Vec<&str> = self
+            .session
+            .env()
+            .iter()
+            .filter(|(k, v)| {
+                k.to_lowercase().contains(&filter) || v.to_lowercase().contains(&filter)
+            })
+            .map(|(k, _)| k)
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    fn selected_key(&self) -> Option<String> {
+        let keys = self.visible_keys();
+        self.list
+            .selected()
+            .and_then(|i| keys.get(i))
+            .map(|k| k.to_string())
+    }
+
+    fn has_changes(&self) -> bool {
+        self.session.is_modified()
+    }
+
+    /// keep the selection on a visible row after the list changes
+    fn clamp_selection(&mut self) {
+        let len = self.visible_keys().len();
+        let selected = self.list.selected().unwrap_or(0).min(len.saturating_sub(1));
+        self.list.select(Some(selected));
+    }
+
+    /// returns Some when the editor should exit, holding what run() returns
+    fn handle_key(&mut self, code: KeyCode) -> Option<Option<Env>> {
+        match std::mem::replace(&mut self.mode, Mode::Browse) {
+            Mode::Browse => return self.handle_browse(code),
+            Mode::ConfirmQuit => {
+                if let KeyCode::Char('y') = code {
+                    return Some(None);
+                }
+            }
+            Mode::Search => match code {
+                KeyCode::Esc => self.filter.clear(),
+                KeyCode::Enter => {}
+                KeyCode::Backspace => {
+                    self.filter.pop();
+                    self.mode = Mode::Search;
+                }
+                KeyCode::Char(c) => {
+                    self.filter.push(c);
+                    self.mode = Mode::Search;
+                }
+                _ => self.mode = Mode::Search,
+            },
+            mode @ (Mode::EditValue(_) | Mode::NewKey | Mode::NewValue(_)) => match code {
+                KeyCode::Esc => self.input.clear(),
+                KeyCode::Enter => {
+                    let input = std::mem::take(&mut self.input);
+                    match mode {
+                        Mode::EditValue(key) | Mode::NewValue(key) => {
+                            let fits = self.session.env().fits_with(&key, &input, &self.layout);
+                            if let Err(e) = self.session.set(key, input) {
+                                self.message = Some(e.to_string());
+                            } else if !fits {
+                                self.message = Some(format!(
+                                    "won't fit: {} bytes over, remove something before writing",
+                                    self.session.env().serialized_len() - self.layout.capacity()
+                                ));
+                            }
+                        }
+                        Mode::NewKey => match validate_key(&input) {
+                            Err(problem) => {
+                                self.message = Some(format!("can't use {:?}: {}", input, problem));
+                            }
+                            Ok(()) if self.session.env().contains_key(&input) => {
+                                self.message = Some(format!("{} already exists", input));
+                            }
+                            Ok(()) => self.mode = Mode::NewValue(input),
+                        },
+                        _ => unreachable!(),
+                    }
+                }
+                KeyCode::Backspace => {
+                    self.input.pop();
+                    self.mode = mode;
+                }
+                KeyCode::Char(c) => {
+                    self.input.push(c);
+                    self.mode = mode;
+                }
+                _ => self.mode = mode,
+            },
+        }
+        self.clamp_selection();
+        None
+    }
+
+    fn handle_browse(&mut self, code: KeyCode) -> Option<Option<Env>> {
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => {
+                if !self.has_changes() {
+                    return Some(None);
+                }
+                self.mode = Mode::ConfirmQuit;
+            }
+            KeyCode::Char('j') | KeyCode::Down => self.list.select_next(),
+            KeyCode::Char('k') | KeyCode::Up => self.list.select_previous(),
+            KeyCode::Char('/') => self.mode = Mode::Search,
+            KeyCode::Enter | KeyCode::Char('e') => {
+                if let Some(key) = self.selected_key() {
+                    self.input = self.session.env().get(&key).unwrap_or_default().to_string();
+                    self.mode = Mode::EditValue(key);
+                }
+            }
+            KeyCode::Char('a') => self.mode = Mode::NewKey,
+            KeyCode::Char('d') => {
+                if let Some(key) = self.selected_key() {
+                    self.session.remove(&key);
+                }
+            }
+            KeyCode::Char('r') => {
+                if let Some(key) = self.selected_key() {
+                    let original = self.session.original().get(&key).map(str::to_owned);
+                    self.session.edit(|env| match original {
+                        // the original may not pass set's checks, but it
+                        // was readable, so put it back as it was
+                        Some(val) => *env.entry(key).or_default() = val,
+                        None => {
+                            env.remove(&key);
+                        }
+                    });
+                }
+            }
+            KeyCode::Char('R') => self.session.revert(),
+            KeyCode::Char('u') => {
+                self.message = (!self.session.undo()).then(|| "nothing to undo".into());
+            }
+            KeyCode::Char('U') => {
+                self.message = (!self.session.redo()).then(|| "nothing to redo".into());
+            }
+            KeyCode::Char('w') => {
+                if !self.has_changes() {
+                    self.message = Some("nothing to write".into());
+                } else if self.session.env().serialized_len() > self.layout.capacity() {
+                    self.message = Some("environment doesn't fit, not writing".into());
+                } else {
+                    return Some(Some(self.session.env().clone()));
+                }
+            }
+            _ => {}
+        }
+        self.clamp_selection();
+        None
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status, bottom] = Layout::vertical([
+            Constraint::Min(1),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [vars, pending] =
+            Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)])
+                .areas(main);
+
+        // variables, marking anything that differs from what was read
+        let items: Vec<ListItem> = self
+            .visible_keys()
+            .into_iter()
+            .map(|key| {
+                let style = match self.session.original().get(key) {
+                    None => Style::new().fg(Color::Green),
+                    Some(val) if self.session.env().get(key) != Some(val) => {
+                        Style::new().fg(Color::Yellow)
+                    }
+                    _ => Style::new(),
+                };
+                ListItem::new(Line::from(vec![
+                    Span::styled(key.to_string(), style.add_modifier(Modifier::BOLD)),
+                    Span::styled(
+                        format!("={}", self.session.env().get(key).unwrap_or_default()),
+                        style,
+                    ),
+                ]))
+            })
+            .collect();
+        let title = if self.filter.is_empty() {
+            " variables ".to_string()
+        } else {
+            format!(" variables matching {:?} ", self.filter)
+        };
+        let list = List::new(items)
+            .block(Block::bordered().title(title))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, vars, &mut self.list);
+
+        let changes: Vec<ListItem> = self
+            .session
+            .changes()
+            .into_iter()
+            .map(|change| match change {
+                Change::Added(k, v) => ListItem::new(format!("+{}={}", k, v)).green(),
+                Change::Removed(k, v) => ListItem::new(format!("-{}={}", k, v)).red(),
+                Change::Changed(k, _, n) => ListItem::new(format!("~{}={}", k, n)).yellow(),
+            })
+            .collect();
+        frame.render_widget(
+            List::new(changes).block(Block::bordered().title(" pending changes ")),
+            pending,
+        );
+
+        let used = self.session.env().serialized_len();
+        let budget = format!(
+            "{} vars, {} of {} bytes used ({:.1}%)",
+            self.session.env().len(),
+            used,
+            self.layout.capacity(),
+            used as f64 * 100.0 / self.layout.capacity() as f64
+        );
+        let budget_style = if used > self.layout.capacity() {
+            Style::new().fg(Color::Red).add_modifier(Modifier::BOLD)
+        } else {
+            Style::new()
+        };
+        frame.render_widget(Paragraph::new(budget).style(budget_style), status);
+
+        let line = match &self.mode {
+            Mode::Browse => self.message.clone().unwrap_or_else(|| HELP.to_string()),
+            Mode::ConfirmQuit => "discard pending changes? (y/n)".to_string(),
+            Mode::Search => format!("/{}", self.filter),
+            Mode::EditValue(key) | Mode::NewValue(key) => format!("{}={}", key, self.input),
+            Mode::NewKey => format!("new variable: {}", self.input),
+        };
+        frame.render_widget(Paragraph::new(line), bottom);
+    }
+}
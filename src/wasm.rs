@@ -0,0 +1,117 @@
+//! javascript bindings for wasm32-unknown-unknown, for inspecting and
+//! editing environments in a browser. build a cdylib with
+//! `cargo rustc --lib --target wasm32-unknown-unknown --no-default-features
+//! --features wasm --crate-type cdylib` and run wasm-bindgen over it
+//!
+//! ```js
+//! import { Env } from "uboot_patcher";
+//!
+//! const image = new Uint8Array(await file.arrayBuffer());
+//! const env = Env.parseAt(image, 0x210000, 0x20000);
+//! env.set("bootdelay", "5");
+//! const patched = env.patchImage(image, 0x210000, 0x20000);
+//! ```
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use wasm_bindgen::prelude::*;
+
+use crate::{max_data_len, EnvError};
+
+fn to_js(e: EnvError) -> JsError {
+    JsError::new(&e.to_string())
+}
+
+/// the variables of an environment, in order
+#[wasm_bindgen(js_name = Env)]
+pub struct WasmEnv {
+    env: crate::Env,
+}
+
+#[wasm_bindgen(js_class = Env)]
+impl WasmEnv {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> WasmEnv {
+        WasmEnv {
+            env: crate::Env::new(),
+        }
+    }
+
+    /// parse the bytes of just the environment region
+    pub fn parse(bytes: &[u8]) -> Result<WasmEnv, JsError> {
+        let env = crate::Env::parse(bytes).map_err(to_js)?;
+        Ok(WasmEnv { env })
+    }
+
+    /// parse the environment at offset in a whole flash image
+    #[wasm_bindgen(js_name = parseAt)]
+    pub fn parse_at(image: &[u8], offset: usize, size: usize) -> Result<WasmEnv, JsError> {
+        let region = image
+            .get(offset..offset.saturating_add(size))
+            .ok_or_else(|| JsError::new("image is too short"))?;
+        WasmEnv::parse(region)
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.env.get(key).map(ToString::to_string)
+    }
+
+    pub fn set(&mut self, key: &str, val: &str) -> Result<(), JsError> {
+        self.env.set(key, val).map(drop).map_err(to_js)
+    }
+
+    /// returns whether key was set
+    pub fn remove(&mut self, key: &str) -> bool {
+        self.env.remove(key).is_some()
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.env.keys().map(ToString::to_string).collect()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn length(&self) -> usize {
+        self.env.len()
+    }
+
+    /// bytes the variables take up, to show against capacity
+    #[wasm_bindgen(js_name = serializedLen)]
+    pub fn serialized_len(&self) -> usize {
+        self.env.serialized_len()
+    }
+
+    /// room for variables in an environment of size bytes
+    pub fn capacity(size: usize) -> usize {
+        max_data_len(size)
+    }
+
+    /// serialize as a redundant environment of size bytes
+    #[wasm_bindgen(js_name = toBytes)]
+    pub fn to_bytes(&self, size: usize) -> Result<Vec<u8>, JsError> {
+        self.env.to_bytes(size).map_err(to_js)
+    }
+
+    /// a copy of image with the environment at offset replaced by this one
+    #[wasm_bindgen(js_name = patchImage)]
+    pub fn patch_image(
+        &self,
+        image: &[u8],
+        offset: usize,
+        size: usize,
+    ) -> Result<Vec<u8>, JsError> {
+        let bytes = self.to_bytes(size)?;
+        let mut image = image.to_vec();
+        image
+            .get_mut(offset..offset.saturating_add(size))
+            .ok_or_else(|| JsError::new("image is too short"))?
+            .copy_from_slice(&bytes);
+        Ok(image)
+    }
+}
+
+impl Default for WasmEnv {
+    fn default() -> WasmEnv {
+        WasmEnv::new()
+    }
+}